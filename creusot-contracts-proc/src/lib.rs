@@ -48,6 +48,17 @@ pub fn variant(attr: TS1, tokens: TS1) -> TS1 {
     })
 }
 
+#[proc_macro_attribute]
+pub fn why3_meta(attr: TS1, tokens: TS1) -> TS1 {
+    let meta_toks = attr.to_string();
+    let item = proc_macro2::TokenStream::from(tokens);
+
+    TS1::from(quote! {
+      #[creusot::why3_meta=#meta_toks]
+      #item
+    })
+}
+
 struct Invariant {
     name: syn::Ident,
     invariant: syn::Term,
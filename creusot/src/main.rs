@@ -49,6 +49,8 @@ mod def_path_trie;
 
 struct ToWhy {
     output_file: Option<String>,
+    /// Prefix for the names declared by the output, see [`mlcfg::printer::NameMangler`]
+    mangle_prefix: Option<String>,
 }
 
 impl Callbacks for ToWhy {
@@ -73,7 +75,7 @@ impl Callbacks for ToWhy {
             .enter(|tcx| {
                 let session = c.session();
                 // TODO: Resolve extern crates
-                translate(&self.output_file, session, tcx, resolver)
+                translate(&self.output_file, self.mangle_prefix.as_deref(), session, tcx, resolver)
             })
             .unwrap();
         Compilation::Stop
@@ -86,12 +88,18 @@ fn main() {
     let mut args = get_args().collect::<Vec<String>>();
 
     let output_file = args.iter().position(|a| a == "-o").map(|ix| args[ix + 1].clone());
+    // Not a rustc flag, so it is removed before the arguments are handed over
+    let mangle_prefix = args.iter().position(|a| a == "--mangle-prefix").map(|ix| {
+        let prefix = args.remove(ix + 1);
+        args.remove(ix);
+        prefix
+    });
 
     args.push(format!("--sysroot={}", sysroot_path()));
     args.push("-Cpanic=abort".to_owned());
     args.push("-Coverflow-checks=off".to_owned());
     // args.push("-Znll-facts".to_owned());
-    RunCompiler::new(&args, &mut ToWhy { output_file }).run().unwrap();
+    RunCompiler::new(&args, &mut ToWhy { output_file, mangle_prefix }).run().unwrap();
 }
 
 use std::io::Result;
@@ -109,6 +117,7 @@ fn is_type_decl(item: &Item) -> bool {
 
 fn translate(
     output: &Option<String>,
+    mangle_prefix: Option<&str>,
     sess: &Session,
     tcx: TyCtxt,
     resolver: Rc<RefCell<BoxedResolver>>,
@@ -166,6 +175,15 @@ fn translate(
 
         use mlcfg::Decl;
         use specification::Spec::*;
+
+        let metas = specification::why3_metas(attrs).unwrap_or_else(|_| {
+            let msg = "malformed `why3_meta`, expected a quoted name and comma separated arguments";
+            ty_ctx.crash_and_error(tcx.def_span(def_id), msg)
+        });
+        for meta in metas {
+            krate.modules.get_mut_with_default(module.clone()).decls.push(meta);
+        }
+
         match specification::spec_kind(attrs).unwrap() {
             Invariant { .. } => continue,
             Logic { body: exp, contract } => {
@@ -173,6 +191,9 @@ fn translate(
 
                 let mut translated = specification::logic_to_why(&resolver, &mut ty_ctx, def_id, &body, exp);
                 translated.contract = out_contract;
+                debug_assert_eq!(translated.check_logic(), Ok(()));
+                let exps = std::iter::once(&translated.body).chain(translated.contract.clauses());
+                report_duplicate_binders(sess, tcx.def_span(def_id), exps);
                 krate.modules.get_mut_with_default(module).decls.push(Decl::LogicDecl(translated));
             }
             Program { contract } => {
@@ -180,6 +201,7 @@ fn translate(
                 let subst = specification::subst_for_arguments(&body);
 
                 out_contract.subst(&subst);
+                report_duplicate_binders(sess, tcx.def_span(def_id), out_contract.clauses());
 
                 // Basic clean up, replace FalseEdges with Gotos. Could potentially also replace other statement with Nops.
                 // Investigate if existing MIR passes do this as part of 'post borrowck cleanup'.
@@ -188,8 +210,20 @@ fn translate(
 
                 let translated = FunctionTranslator::new(sess, tcx, &mut ty_ctx, &body, resolver)
                     .translate(def_id, out_contract);
-
-                krate.modules.get_mut_with_default(module).decls.push(Decl::FunDecl(translated));
+                let entry = mlcfg::BlockId(0);
+                debug_assert_eq!(mlcfg::validate_cfg(entry, &translated.blocks), Ok(()));
+
+                let decl = if cfg!(feature = "structured-cfg") {
+                    translated.into_structured().map_or_else(Decl::FunDecl, Decl::LetFun)
+                } else {
+                    Decl::FunDecl(translated)
+                };
+                if cfg!(feature = "validate-verbatim") {
+                    if let Err(err) = decl.check_verbatims() {
+                        sess.span_err(tcx.def_span(def_id), &err.to_string());
+                    }
+                }
+                krate.modules.get_mut_with_default(module).decls.push(decl);
             }
         }
     }
@@ -203,48 +237,81 @@ fn translate(
         None => Box::new(std::io::stdout()),
     };
 
-    print_crate(&mut out, krate)?;
+    print_crate(&mut out, krate, mangle_prefix)?;
     Ok(())
 }
 use std::io::Write;
 
+fn report_duplicate_binders<'a>(
+    sess: &Session,
+    span: rustc_span::Span,
+    exps: impl Iterator<Item = &'a mlcfg::Exp>,
+) {
+    for err in exps.filter_map(|e| e.check_binders().err()) {
+        sess.span_err(span, &err.to_string());
+    }
+}
+
 // TODO: Clean up, this printing code should not be in main.
-const IMPORTS : &'static str =
-"  use Ref
-  use mach.int.Int
-  use mach.int.Int32
-  use mach.int.Int64
-  use mach.int.UInt32
-  use mach.int.UInt64
-  use string.Char
-  use floating_point.Single
-  use floating_point.Double
-  use prelude.Prelude";
-
-fn print_crate<W>(out: &mut W, krate: TranslatedCrate) -> std::io::Result<()>
+fn print_crate<W>(
+    out: &mut W,
+    krate: TranslatedCrate,
+    mangle_prefix: Option<&str>,
+) -> std::io::Result<()>
 where
     W: Write,
 {
     writeln!(out, "module {}", krate.name)?;
-    writeln!(out, "{}\n", IMPORTS)?;
+
+    let ty_decls: Vec<_> = krate.types().map(|(ty, _)| mlcfg::Decl::TyDecl(ty.clone())).collect();
+    let mut decls: Vec<_> = ty_decls.iter().collect();
+    module_decls(&krate.modules, &mut decls);
+    let mangler = mangle_prefix.map(|prefix| {
+        let mut mangler = mlcfg::printer::NameMangler::new(prefix, decls.iter().copied());
+        for (_, pred) in krate.types() {
+            mangler.declare(&pred.name);
+        }
+        mangler
+    });
+    let mut theories = mlcfg::imports::Theories::default().required(decls);
+    // Every module uses the drop predicates of the prelude, and MLCFG variables are references
+    theories.extend(vec!["Ref".to_owned(), "prelude.Prelude".to_owned()]);
+    for theory in theories {
+        writeln!(out, "  use {}", theory)?;
+    }
+    writeln!(out)?;
     writeln!(out, "  scope Type")?;
     for (decl, pred) in krate.types() {
-        let fe = mlcfg::printer::FormatEnv { indent: 2, scope: &["Type".into()] };
+        let fe = mlcfg::printer::FormatEnv {
+            indent: 2,
+            column: 0,
+            scope: &["Type".into()],
+            config: Default::default(),
+            mangler: mangler.as_ref(),
+        };
 
         writeln!(out, "{}", fe.to(decl))?;
         writeln!(out, "{}", fe.to(pred))?;
     }
     writeln!(out, "  end")?;
 
-    print_module_tree(out, &mut Vec::new(), &krate.modules).unwrap();
+    print_module_tree(out, &mut Vec::new(), &krate.modules, mangler.as_ref()).unwrap();
     writeln!(out, "end")?;
 
     Ok(())
 }
+fn module_decls<'a>(mod_tree: &'a DefPathTrie<mlcfg::Module>, decls: &mut Vec<&'a mlcfg::Decl>) {
+    decls.extend(mod_tree.value().into_iter().flat_map(|module| &module.decls));
+    for (_, child) in mod_tree.children_with_keys() {
+        module_decls(child, decls);
+    }
+}
+
 fn print_module_tree<W>(
     out: &mut W,
     open_scopes: &mut Vec<String>,
     mod_tree: &DefPathTrie<mlcfg::Module>,
+    mangler: Option<&mlcfg::printer::NameMangler>,
 ) -> std::io::Result<()>
 where
     W: Write,
@@ -258,12 +325,18 @@ where
 
         writeln!(out, "{:ident$}scope {}", "", scope_name, ident = indent_level)?;
         open_scopes.push(scope_name);
-        print_module_tree(out, open_scopes, child)?;
+        print_module_tree(out, open_scopes, child, mangler)?;
         open_scopes.pop();
         writeln!(out, "{:ident$}end", "", ident = indent_level)?;
     }
 
-    let fe = mlcfg::printer::FormatEnv { indent: indent_level, scope: &open_scopes[..] };
+    let fe = mlcfg::printer::FormatEnv {
+        indent: indent_level,
+        column: 0,
+        scope: &open_scopes[..],
+        config: Default::default(),
+        mangler,
+    };
 
     let module = mod_tree.value().unwrap();
 
@@ -79,6 +79,9 @@ pub struct FunctionTranslator<'a, 'b, 'tcx> {
     // Spec / Ghost variables
     erased_locals: BitSet<Local>,
 
+    // Number of invariants emitted so far, used to give each a stable id
+    invariant_count: usize,
+
     // Current block being generated
     current_block: (Vec<mlcfg::Statement>, Option<mlcfg::Terminator>),
 
@@ -186,6 +189,7 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
             local_init,
             erased_locals,
             never_live,
+            invariant_count: 0,
             current_block: (Vec::new(), None),
             past_blocks: BTreeMap::new(),
             ty_ctx: ctx,
@@ -193,7 +197,9 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
         }
     }
 
-    fn emit_statement(&mut self, s: mlcfg::Statement) {
+    fn emit_statement(&mut self, mut s: mlcfg::Statement) {
+        // Program expressions evaluate `&&` and `||` lazily, like Rust
+        s.short_circuit();
         self.current_block.0.push(s);
     }
 
@@ -227,8 +233,16 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
 
         let name = translate_value_id(self.tcx, nm);
 
-        move_invariants_into_loop(&mut self.past_blocks);
-        Function { name, retty, args, vars, blocks: self.past_blocks, contract: contracts }
+        let invariants = loop_invariants(&mut self.past_blocks);
+        Function {
+            name,
+            retty,
+            args,
+            vars,
+            blocks: self.past_blocks,
+            invariants,
+            contract: contracts,
+        }
     }
 
     fn translate_body(&mut self) {
@@ -405,14 +419,11 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
                         pattern: ConsP(tyname, pat),
                         arg: box inner,
                         body: box Var("a".into()),
+                        ghost: false,
                     }
                 }
                 TupleAccess { size, ix } => {
-                    let mut pat = vec![Wildcard; *ix];
-                    pat.push(VarP("a".into()));
-                    pat.append(&mut vec![Wildcard; size - ix - 1]);
-
-                    inner = Let { pattern: TupleP(pat), arg: box inner, body: box Var("a".into()) }
+                    inner = TupleField { tuple: box inner, idx: *ix, arity: *size }
                 }
             }
         }
@@ -470,6 +481,7 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
                         pattern: ConsP(tyname.clone(), varpats),
                         arg: box self.translate_rplace(&stump),
                         body: box Constructor { ctor: tyname, args: varexps },
+                        ghost: false,
                     }
                 }
                 TupleAccess { size, ix } => {
@@ -485,6 +497,7 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
                         pattern: TupleP(varpats),
                         arg: box self.translate_rplace(&stump),
                         body: box Tuple(varexps),
+                        ghost: false,
                     }
                 }
             }
@@ -495,30 +508,32 @@ impl<'a, 'b, 'tcx> FunctionTranslator<'a, 'b, 'tcx> {
     }
 }
 
-fn move_invariants_into_loop(body: &mut BTreeMap<BlockId, Block>) {
+// The invariants of each loop, keyed by the header block of the loop they are attached to
+fn loop_invariants(
+    body: &mut BTreeMap<BlockId, Block>,
+) -> BTreeMap<BlockId, Vec<(String, mlcfg::Exp)>> {
     // CORRECTNESS: We assume that invariants are placed at the end of the block entering into the loop.
     // This is enforced syntactically at source level using macros, however it could get broken during
     // compilation.
-    let mut changes = std::collections::HashMap::new();
+    let mut invariants = BTreeMap::new();
     for (_, block) in body.iter_mut() {
-        let (invariants, rest) =
-            block.statements.clone().into_iter().partition(|stmt| matches!(stmt, Invariant(_, _)));
+        let (invs, rest): (Vec<_>, _) =
+            block.statements.clone().into_iter().partition(|stmt| matches!(stmt, Invariant { .. }));
 
         let _ = std::mem::replace(&mut block.statements, rest);
-        if !invariants.is_empty() {
+        if !invs.is_empty() {
             if let mlcfg::Terminator::Goto(tgt) = &block.terminator {
-                changes.insert(*tgt, invariants);
+                let invs = invs.into_iter().map(|inv| match inv {
+                    Invariant { label, exp, .. } => (label, exp),
+                    _ => unreachable!(),
+                });
+                invariants.entry(*tgt).or_insert_with(Vec::new).extend(invs);
             } else {
                 panic!("BAD INVARIANT BAD!")
             }
         }
     }
-
-    for (tgt, mut invs) in changes.drain() {
-        let tgt = body.get_mut(&tgt).unwrap();
-        invs.append(&mut tgt.statements);
-        let _ = std::mem::replace(&mut tgt.statements, invs);
-    }
+    invariants
 }
 
 use heck::{CamelCase, MixedCase};
@@ -570,7 +585,7 @@ fn translate_defid(tcx: TyCtxt, def_id: DefId, ty: bool) -> QName {
         (_, _) => unreachable!(),
     }
 
-    QName { module: mod_segs, name: name_segs }
+    QName::new(mod_segs, name_segs)
 }
 
 fn mk_anon(l: Local) -> LocalIdent {
@@ -584,53 +599,60 @@ pub fn from_mir_constant<'tcx>(
     tcx: TyCtxt<'tcx>,
     c: &rustc_middle::mir::Constant<'tcx>,
 ) -> mlcfg::Constant {
-    use rustc_middle::ty::TyKind::{Int, Uint};
-    use rustc_middle::ty::{IntTy::*, UintTy::*};
+    use rustc_middle::ty::TyKind::{Float, Int, Uint};
+    use rustc_middle::ty::{FloatTy, IntTy::*, UintTy::*};
     use rustc_target::abi::Size;
 
+    let pretty = || {
+        use rustc_middle::ty::print::{PrettyPrinter, FmtPrinter};
+        let mut fmt = String::new();
+        let cx = FmtPrinter::new(tcx, &mut fmt, Namespace::ValueNS);
+        cx.pretty_print_const(c.literal, false).unwrap();
+
+        Constant::Other(fmt)
+    };
+
     match c.literal.ty.kind() {
-        Int(I8) => {
-            Constant::Int(c.literal.val.try_to_bits(Size::from_bytes(1)).unwrap() as i128, Some(ty::i8_ty()))
+        Int(ity) if *ity != Isize => {
+            let bytes = match ity {
+                I8 => 1,
+                I16 => 2,
+                I32 => 4,
+                I64 => 8,
+                I128 => 16,
+                Isize => unreachable!(),
+            };
+            let bits = c.literal.val.try_to_bits(Size::from_bytes(bytes)).unwrap();
+            Constant::Int(bits as i128, Some(ty::intty_to_ty(ity)))
         }
-        Int(I16) => Constant::Int(
-            c.literal.val.try_to_bits(Size::from_bytes(2)).unwrap() as i128,
-            Some(ty::i16_ty()),
-        ),
-        Int(I32) => Constant::Int(
-            c.literal.val.try_to_bits(Size::from_bytes(4)).unwrap() as i128,
-            Some(ty::i32_ty()),
-        ),
-        Int(I64) => Constant::Int(
-            c.literal.val.try_to_bits(Size::from_bytes(8)).unwrap() as i128,
-            Some(ty::i64_ty()),
-        ),
-        Int(I128) => unimplemented!("128-bit integers are not supported"),
-
-        Uint(U8) => {
-            Constant::Uint(c.literal.val.try_to_bits(Size::from_bytes(1)).unwrap(), Some(ty::u8_ty()))
+        Uint(uty) => {
+            let bytes = match uty {
+                U8 => 1,
+                U16 => 2,
+                U32 => 4,
+                U64 | Usize => 8,
+                U128 => 16,
+            };
+            let bits = c.literal.val.try_to_bits(Size::from_bytes(bytes)).unwrap();
+            Constant::Uint(bits, Some(ty::uintty_to_ty(uty)))
         }
-        Uint(U16) => {
-            Constant::Uint(c.literal.val.try_to_bits(Size::from_bytes(2)).unwrap(), Some(ty::u16_ty()))
-        }
-        Uint(U32) => {
-            Constant::Uint(c.literal.val.try_to_bits(Size::from_bytes(4)).unwrap(), Some(ty::u32_ty()))
-        }
-        Uint(U64) => {
-            Constant::Uint(c.literal.val.try_to_bits(Size::from_bytes(8)).unwrap(), Some(ty::u64_ty()))
-        }
-        Uint(U128) => {
-             unimplemented!("128-bit integers are not supported")
-        }
-        Uint(Usize) => {
-            Constant::Uint(c.literal.val.try_to_bits(Size::from_bytes(8)).unwrap(), Some(ty::usize_ty()))
-        }
-        _ => {
-            use rustc_middle::ty::print::{PrettyPrinter, FmtPrinter};
-            let mut fmt = String::new();
-            let cx = FmtPrinter::new(tcx, &mut fmt, Namespace::ValueNS);
-            cx.pretty_print_const(c.literal, false).unwrap();
-
-            Constant::Other(fmt)
+        Float(fty) => {
+            let value = match fty {
+                FloatTy::F32 => {
+                    let bits = c.literal.val.try_to_bits(Size::from_bytes(4)).unwrap();
+                    f32::from_bits(bits as u32) as f64
+                }
+                FloatTy::F64 => {
+                    let bits = c.literal.val.try_to_bits(Size::from_bytes(8)).unwrap();
+                    f64::from_bits(bits as u64)
+                }
+            };
+            // Infinities and NaN have no literal
+            if !value.is_finite() {
+                return pretty();
+            }
+            Constant::Float(value, Some(ty::floatty_to_ty(fty)))
         }
+        _ => pretty(),
     }
 }
@@ -33,7 +33,7 @@ pub fn requires_to_why<'tcx>(
 
     pearlite::typing::check_term(&mut tyctx, &mut t, &term::Type::BOOLEAN).unwrap();
     // TODO: perform substitution on pearlite?
-    lower_term_to_why(ctx, t)
+    lower_term_to_why(ctx, t).unwrap_or_else(|err| report_lower_error(ctx, body, err))
 }
 
 pub fn variant_to_why<'tcx>(res: &RustcResolver<'tcx>, ctx: &mut Ctx<'_, 'tcx>, body: &Body<'tcx>, attr_val: String) -> Exp {
@@ -45,7 +45,7 @@ pub fn variant_to_why<'tcx>(res: &RustcResolver<'tcx>, ctx: &mut Ctx<'_, 'tcx>,
 
     pearlite::typing::infer_term(&mut tyctx, &mut t).unwrap();
     // TODO: perform substitution on pearlite?
-    lower_term_to_why(ctx, t)
+    lower_term_to_why(ctx, t).unwrap_or_else(|err| report_lower_error(ctx, body, err))
 }
 
 pub fn ensures_to_why<'tcx>(res: &RustcResolver<'tcx>, ctx: &mut Ctx<'_, 'tcx>,
@@ -61,7 +61,10 @@ pub fn ensures_to_why<'tcx>(res: &RustcResolver<'tcx>, ctx: &mut Ctx<'_, 'tcx>,
 
     pearlite::typing::check_term(&mut tyctx, &mut t, &term::Type::BOOLEAN).unwrap();
     // TODO: perform substitution on pearlite?
-    lower_term_to_why(ctx, t)
+    let mut e = lower_term_to_why(ctx, t).unwrap_or_else(|err| report_lower_error(ctx, body, err));
+    let result: HashMap<_, _> = std::iter::once(("result".into(), Exp::Result)).collect();
+    e.subst(&result);
+    e
 }
 
 pub fn invariant_to_why<'tcx>(
@@ -91,7 +94,7 @@ pub fn invariant_to_why<'tcx>(
 
     let mut t = term::Term::from_syn(res, p).unwrap();
     pearlite::typing::check_term(&mut tyctx, &mut t, &term::Type::BOOLEAN).unwrap();
-    let mut e = lower_term_to_why(ctx, t);
+    let mut e = lower_term_to_why(ctx, t).unwrap_or_else(|err| report_lower_error(ctx, body, err));
     let fvs = e.fvs();
 
     let vars_in_scope: Vec<_> =
@@ -117,7 +120,7 @@ pub fn invariant_to_why<'tcx>(
         .collect();
         //
     e.subst(&subst);
-    format!("{}", FormatEnv { scope: &[], indent: 0 }.to(&e))
+    format!("{}", FormatEnv::default().to(&e))
 }
 
 // Translate a logical funciton into why.
@@ -139,19 +142,25 @@ pub fn logic_to_why<'tcx>(
     let mut t = term::Term::from_syn(res, p).unwrap();
 
     pearlite::typing::check_term(&mut tyctx, &mut t, &ret_ty).unwrap();
-    let body = lower_term_to_why(ctx, t);
+    let lowered: Result<_, LowerError> = (|| {
+        let body = lower_term_to_why(ctx, t)?;
+        let retty = lower_type_to_why(ctx, ret_ty)?;
+        let args = entry_ctx
+            .into_iter()
+            .map(|(nm, ty)| Ok((LocalIdent::Name(nm), lower_type_to_why(ctx, ty)?)))
+            .collect::<Result<_, LowerError>>()?;
+        Ok((body, retty, args))
+    })();
+    let (exp, retty, args) = lowered.unwrap_or_else(|err| report_lower_error(ctx, body, err));
 
     let name = crate::translation::translate_value_id(res.2, did);
-    mlcfg::Logic {
-        name,
-        retty: lower_type_to_why(ctx, ret_ty),
-        args: entry_ctx
-            .into_iter()
-            .map(|(nm, ty)| (LocalIdent::Name(nm), lower_type_to_why(ctx, ty)))
-            .collect(),
-        body,
-        contract: mlcfg::Contract::new(),
-    }
+    mlcfg::Logic { name, retty, args, body: exp, contract: mlcfg::Contract::new() }
+}
+
+// Abort with an error naming the item whose specification could not be lowered
+fn report_lower_error(ctx: &Ctx<'_, '_>, body: &Body<'_>, err: LowerError) -> ! {
+    let item = ctx.tcx.def_path_str(body.source.def_id());
+    ctx.crash_and_error(body.span, &format!("{} in the specification of `{}`", err, item))
 }
 
 fn return_ty<'tcx>(tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> pearlite::term::Type {
@@ -325,6 +334,23 @@ pub fn spec_kind(a: Attributes<'_>) -> Result<Spec, SpecAttrError> {
     }
 }
 
+// Collect the raw `meta` directives requested through `#[creusot::why3_meta]`.
+pub fn why3_metas(a: Attributes<'_>) -> Result<Vec<mlcfg::Decl>, SpecAttrError> {
+    let mut metas = Vec::new();
+    for attr in a {
+        if attr.is_doc_comment() {
+            continue;
+        }
+        let attr = attr.get_normal_item();
+
+        if is_attr(attr, "why3_meta") {
+            let meta = ts_to_symbol(attr.args.inner_tokens()).ok_or(SpecAttrError::InvalidTokens)?;
+            metas.push(mlcfg::Decl::parse_meta(&meta).ok_or(SpecAttrError::InvalidTokens)?);
+        }
+    }
+    Ok(metas)
+}
+
 pub fn is_spec_id(tcx: TyCtxt<'_>, def_id: DefId) -> Result<bool, SpecAttrError> {
     match spec_kind(tcx.get_attrs(def_id))? {
         Spec::Invariant { .. } => Ok(true),
@@ -13,17 +13,18 @@ pub struct RustcResolver<'tcx>(pub Rc<RefCell<BoxedResolver>>, pub DefId, pub Ty
 impl pearlite::parser::Resolver for RustcResolver<'_> {
     fn resolve(&self, path: &[String]) -> Option<pearlite::term::Name> {
         use itertools::*;
+        use rustc_resolve::Namespace::*;
         let name = format!("{}", path.iter().format("::"));
+        // Braced structs only live in the type namespace, so fall back to it when the value
+        // namespace fails.
         let res = self.0.borrow_mut().access(|resolver| {
-            resolver.resolve_str_path_error(
-                rustc_span::DUMMY_SP,
-                &name,
-                rustc_resolve::Namespace::ValueNS,
-                self.1,
+            resolver.resolve_str_path_error(rustc_span::DUMMY_SP, &name, ValueNS, self.1).or_else(
+                |_| resolver.resolve_str_path_error(rustc_span::DUMMY_SP, &name, TypeNS, self.1),
             )
         });
         match res {
-            Ok((_, res)) => Some(defid_to_path(self.2, res.def_id())),
+            // Primitive types resolve without a `DefId`, leave those as identifiers.
+            Ok((_, res)) => res.opt_def_id().map(|did| defid_to_path(self.2, did)),
             Err(_) => None,
         }
     }
@@ -78,6 +79,42 @@ impl pearlite::typing::GlobalContext for RustcContext<'_> {
             t => Some((vec![], t)),
         }
     }
+
+    fn struct_fields(&self, path: &term::Name) -> Option<(Vec<(String, term::Type)>, term::Type)> {
+        use rustc_hir::def::DefKind;
+        if let Name::Path { id, .. } = path {
+            let defid: DefId = id_to_def_id(*id);
+            let (adt_did, variant) = match self.0.def_kind(defid) {
+                DefKind::Struct => (defid, self.0.adt_def(defid).non_enum_variant()),
+                DefKind::Variant => {
+                    let parent = self.0.parent(defid)?;
+                    (parent, self.0.adt_def(parent).variant_with_id(defid))
+                }
+                _ => return None,
+            };
+
+            let fields = variant
+                .fields
+                .iter()
+                .map(|fld| (fld.ident.to_string(), ty_to_pearlite(self.0, self.0.type_of(fld.did))))
+                .collect();
+            Some((fields, ty_to_pearlite(self.0, self.0.type_of(adt_did))))
+        } else {
+            None
+        }
+    }
+
+    fn is_ord_cmp(&self, path: &term::Name) -> bool {
+        match path {
+            Name::Path { id, .. } => is_ord_cmp(self.0, id_to_def_id(*id)),
+            Name::Ident(_) => false,
+        }
+    }
+}
+
+pub fn is_ord_cmp(tcx: TyCtxt<'_>, did: DefId) -> bool {
+    let path = tcx.def_path_str(did);
+    path == "std::cmp::Ord::cmp" || path == "core::cmp::Ord::cmp"
 }
 
 pub fn ty_to_pearlite<'tcx>(tcx: TyCtxt<'tcx>, ty: &TyS<'tcx>) -> pearlite::term::Type {
@@ -87,22 +124,23 @@ pub fn ty_to_pearlite<'tcx>(tcx: TyCtxt<'tcx>, ty: &TyS<'tcx>) -> pearlite::term
 
     match ty.kind() {
         Bool => Type::Lit(LitTy::Boolean),
-        // Char => {}
+        Char => Type::Lit(LitTy::Char),
+        Str => Type::Lit(LitTy::Str),
         Int(size) => match size {
             I8 => Type::Lit(LitTy::I8),
             I16 => Type::Lit(LitTy::I16),
             I32 => Type::Lit(LitTy::I32),
             I64 => Type::Lit(LitTy::I64),
+            I128 => Type::Lit(LitTy::I128),
             Isize => Type::Lit(LitTy::ISIZE),
-            _ => unimplemented!(),
         },
         Uint(size) => match size {
             U8 => Type::Lit(LitTy::U8),
             U16 => Type::Lit(LitTy::U16),
             U32 => Type::Lit(LitTy::U32),
             U64 => Type::Lit(LitTy::U64),
+            U128 => Type::Lit(LitTy::U128),
             Usize => Type::Lit(LitTy::USIZE),
-            _ => unimplemented!(),
         },
         FnDef(did, subst) => {
             use rustc_middle::ty::subst::Subst;
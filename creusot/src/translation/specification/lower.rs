@@ -5,39 +5,120 @@ use rustc_hir::def_id::DefId;
 use why3::mlcfg::QName;
 use why3::mlcfg::{self, Exp};
 
-pub fn lower_term_to_why(ctx: &mut Ctx, t: term::Term) -> Exp {
+#[derive(Debug)]
+pub enum LowerError {
+    // A local variable used where only items are allowed, like the function of a call
+    LocalAsPath(String),
+    // A `x @ p` pattern whose subpattern binds or destructures the value
+    UnsupportedBinding(String),
+}
+
+impl std::fmt::Display for LowerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LowerError::LocalAsPath(id) => {
+                write!(f, "`{}` is a local variable, but only items may be used here", id)
+            }
+            LowerError::UnsupportedBinding(id) => write!(
+                f,
+                "`{} @ ..` is only supported with literal, range or wildcard subpatterns",
+                id
+            ),
+        }
+    }
+}
+
+// Terms are cached along with the types their lowering named: a cache hit names them again, so
+// lowering has the same effect on the context whether or not it was cached. The cache is keyed by
+// the term itself, so a hit is only ever for an equal term.
+// Failures aren't cached, so the error of a term is reported every time it is lowered.
+pub fn lower_term_to_why(ctx: &mut Ctx, t: term::Term) -> Result<Exp, LowerError> {
+    if let Some((exp, tys)) = ctx.lowered_terms.get(&t) {
+        let (exp, tys) = (exp.clone(), tys.clone());
+        for did in tys {
+            crate::ty::translate_ty_name(ctx, did);
+        }
+        if cfg!(debug_assertions) {
+            let outer = ctx.named_tys.take();
+            let fresh = lower_term(ctx, t);
+            ctx.named_tys = outer;
+            debug_assert!(fresh.map_or(false, |fresh| fresh.alpha_eq(&exp)));
+        }
+        return Ok(exp);
+    }
+
+    let outer = ctx.named_tys.replace(Vec::new());
+    let exp = lower_term(ctx, t.clone());
+    let tys = std::mem::replace(&mut ctx.named_tys, outer).unwrap_or_default();
+    if let Some(outer) = &mut ctx.named_tys {
+        outer.extend(tys.iter().copied());
+    }
+
+    let exp = exp?;
+    ctx.lowered_terms.insert(t, (exp.clone(), tys));
+    Ok(exp)
+}
+
+fn lower_term(ctx: &mut Ctx, t: term::Term) -> Result<Exp, LowerError> {
     use term::Term::*;
-    match t {
-        Match { box expr, arms } => Exp::Match(
-            box lower_term_to_why(ctx, expr),
-            arms.into_iter().map(|t| lower_arm_to_why(ctx, t)).collect(),
-        ),
+    let exp = match t {
+        Match { box expr, arms } => {
+            let scrut = lower_term(ctx, expr)?;
+            if let Some(path) = variant_test(&arms) {
+                let ctor = lower_value_path(ctx, path.clone())?;
+                let decls: Vec<_> = ctx.ty_decls().collect();
+                if let Some(test) = Exp::is_variant(scrut.clone(), ctor, &decls) {
+                    return Ok(test);
+                }
+            }
+            let arms =
+                arms.into_iter().map(|t| lower_arm_to_why(ctx, t)).collect::<Result<_, _>>()?;
+            let decls: Vec<_> = ctx.ty_decls().collect();
+            Exp::match_or_absurd(scrut, arms, &decls)
+        }
         Binary { box left, op: pearlite::term::BinOp::Impl, box right } => {
-            Exp::Impl(box lower_term_to_why(ctx, left), box lower_term_to_why(ctx, right))
+            Exp::Impl(box lower_term(ctx, left)?, box lower_term(ctx, right)?)
         }
+        Binary { box left, op: pearlite::term::BinOp::Xor, box right } => Exp::not(Exp::BinaryOp(
+            mlcfg::BinOp::Iff,
+            box lower_term(ctx, left)?,
+            box lower_term(ctx, right)?,
+        )),
         Binary { box left, op, box right } => {
             let op = op_to_op(op);
-            Exp::BinaryOp(op, box lower_term_to_why(ctx, left), box lower_term_to_why(ctx, right))
+            Exp::BinaryOp(op, box lower_term(ctx, left)?, box lower_term(ctx, right)?)
         }
         Unary { op, box expr } => {
-            let expr = box lower_term_to_why(ctx, expr);
+            let expr = box lower_term(ctx, expr)?;
             match op {
                 term::UnOp::Final => Exp::Final(expr),
+                term::UnOp::Old => Exp::Old(expr),
                 term::UnOp::Deref(Some(DerefKind::Ref(RefKind::Mut))) => Exp::Current(expr),
-                term::UnOp::Deref(Some(_)) => *expr,
-                term::UnOp::Deref(None) => unreachable!(),
+                term::UnOp::Deref(Some(DerefKind::Ref(RefKind::Not))) => *expr,
+                // Boxes are erased by `translate_ty`, so their contents need no projection
+                term::UnOp::Deref(Some(DerefKind::Box)) => *expr,
+                term::UnOp::Deref(None) => {
+                    unreachable!("dereference of unknown kind in `{:?}`, is it well-typed?", expr)
+                }
                 term::UnOp::Neg => Exp::UnaryOp(mlcfg::UnOp::Neg, expr),
                 term::UnOp::Not => Exp::UnaryOp(mlcfg::UnOp::Not, expr),
             }
         }
         Variable { path } => match path {
-            Name::Path { .. } => Exp::QVar(lower_value_path(ctx, path)),
+            Name::Path { .. } => Exp::QVar(lower_value_path(ctx, path)?),
             Name::Ident(i) => Exp::Var(i.into()),
         },
+        Call { func, args } if is_ord_cmp(ctx, &func) => {
+            let mut args = args.into_iter().map(|t| lower_term(ctx, t));
+            let (l, r) = (args.next().unwrap()?, args.next().unwrap()?);
+            lower_cmp(ctx, func, l, r)
+        }
+        // Boxes are erased by `translate_ty`, so allocating one is the identity
+        Call { func, mut args } if is_box_new(ctx, &func) => lower_term(ctx, args.remove(0))?,
         Call { func, args } => {
             let is_c = is_constructor(ctx, &func);
-            let name = lower_value_path(ctx, func);
-            let args = args.into_iter().map(|t| lower_term_to_why(ctx, t)).collect();
+            let name = lower_value_path(ctx, func)?;
+            let args = args.into_iter().map(|t| lower_term(ctx, t)).collect::<Result<_, _>>()?;
 
             if is_c {
                 Exp::Constructor { ctor: name, args }
@@ -45,71 +126,110 @@ pub fn lower_term_to_why(ctx: &mut Ctx, t: term::Term) -> Exp {
                 Exp::Call(box Exp::QVar(name), args)
             }
         }
-        Lit { lit } => Exp::Const(lit_to_const(lit)),
-        Forall { args, box body } => {
+        // Structs are declared with a positional constructor, and typing has already put the
+        // fields in declaration order.
+        Struct { path, fields } => {
+            let ctor = lower_value_path(ctx, path)?;
             let args =
-                args.into_iter().map(|(i, t)| (i.0.into(), lower_type_to_why(ctx, t))).collect();
+                fields.into_iter().map(|(_, t)| lower_term(ctx, t)).collect::<Result<_, _>>()?;
 
-            Exp::Forall(args, box lower_term_to_why(ctx, body))
+            Exp::Constructor { ctor, args }
+        }
+        Lit { lit } => Exp::Const(lit_to_const(lit)),
+        Forall { args, box body } => {
+            let args = lower_binders(ctx, args)?;
+            Exp::Forall(args, box lower_term(ctx, body)?)
         }
         Exists { args, box body } => {
-            let args =
-                args.into_iter().map(|(i, t)| (i.0.into(), lower_type_to_why(ctx, t))).collect();
-
-            Exp::Exists(args, box lower_term_to_why(ctx, body))
+            let args = lower_binders(ctx, args)?;
+            Exp::Exists(args, box lower_term(ctx, body)?)
+        }
+        // Why3 lambdas take a single argument, so closures are curried
+        Lambda { args, box body } => {
+            let body = lower_term(ctx, body)?;
+            args.into_iter().try_rfold(body, |body, (id, ty)| {
+                let ty = ty.map(|ty| lower_type_to_why(ctx, ty)).transpose()?;
+                Ok::<_, LowerError>(Exp::Abs(id.0.into(), ty, box body))
+            })?
+        }
+        Let { pat, box arg, box body } => {
+            let mut lits = Vec::new();
+            let pattern = lower_pattern_to_why(ctx, pat, &mut lits)?;
+            let (arg, body) = (lower_term(ctx, arg)?, lower_term(ctx, body)?);
+            match literal_guard(lits, None) {
+                Some(guard) => {
+                    let absurd = (mlcfg::Pattern::Wildcard, None, Exp::Absurd);
+                    Exp::Match(box arg, vec![(pattern, Some(guard), body), absurd])
+                }
+                None => {
+                    let decls: Vec<_> = ctx.ty_decls().collect();
+                    Exp::let_or_match(pattern, arg, body, &decls)
+                }
+            }
         }
-        Let { pat, box arg, box body } => Exp::Let {
-            pattern: lower_pattern_to_why(ctx, pat),
-            arg: box lower_term_to_why(ctx, arg),
-            body: box lower_term_to_why(ctx, body),
-        },
         Absurd => Exp::Absurd,
-        Cast { box expr, ty: _ } => lower_term_to_why(ctx, expr),
-        Tuple { elems } => {
-            Exp::Tuple(elems.into_iter().map(|t| lower_term_to_why(ctx, t)).collect())
+        Index { box base, box index } => {
+            Exp::Index { base: box lower_term(ctx, base)?, index: box lower_term(ctx, index)? }
         }
+        Cast { box expr, ty: _ } => lower_term(ctx, expr)?,
+        Tuple { elems } => Exp::Tuple(
+            elems.into_iter().map(|t| lower_term(ctx, t)).collect::<Result<_, _>>()?,
+        ),
         If { box cond, box then_branch, box else_branch } => {
             use mlcfg::Pattern;
             Exp::Match(
-                box lower_term_to_why(ctx, cond),
+                box lower_term(ctx, cond)?,
                 vec![
-                    (Pattern::mk_true(), lower_term_to_why(ctx, then_branch)),
-                    (Pattern::mk_false(), lower_term_to_why(ctx, else_branch)),
+                    (Pattern::mk_true(), None, lower_term(ctx, then_branch)?),
+                    (Pattern::mk_false(), None, lower_term(ctx, else_branch)?),
                 ],
             )
         }
-    }
+    };
+    Ok(exp)
 }
 
-pub fn lower_type_to_why(ctx: &mut Ctx, ty: pearlite::term::Type) -> why3::mlcfg::Type {
+fn lower_binders(
+    ctx: &mut Ctx,
+    args: Vec<(term::Ident, term::Type)>,
+) -> Result<Vec<(mlcfg::LocalIdent, mlcfg::Type)>, LowerError> {
+    args.into_iter().map(|(i, t)| Ok((i.0.into(), lower_type_to_why(ctx, t)?))).collect()
+}
+
+pub fn lower_type_to_why(
+    ctx: &mut Ctx,
+    ty: pearlite::term::Type,
+) -> Result<why3::mlcfg::Type, LowerError> {
     use pearlite::term::*;
     use why3::mlcfg::Type::*;
 
-    match ty {
-        term::Type::Path { path } => TConstructor(lower_type_path(ctx, path)),
-        term::Type::Box { box ty } => lower_type_to_why(ctx, ty),
+    let ty = match ty {
+        term::Type::Path { path } => TConstructor(lower_type_path(ctx, path)?),
+        term::Type::Box { box ty } => lower_type_to_why(ctx, ty)?,
         term::Type::Reference { kind: RefKind::Mut, box ty } => {
-            MutableBorrow(box lower_type_to_why(ctx, ty))
-        }
-        term::Type::Reference { kind: _, box ty } => lower_type_to_why(ctx, ty),
-        term::Type::Tuple { elems } => {
-            Tuple(elems.into_iter().map(|t| lower_type_to_why(ctx, t)).collect())
+            MutableBorrow(box lower_type_to_why(ctx, ty)?)
         }
+        term::Type::Reference { kind: _, box ty } => lower_type_to_why(ctx, ty)?,
+        term::Type::Tuple { elems } => Tuple(
+            elems.into_iter().map(|t| lower_type_to_why(ctx, t)).collect::<Result<_, _>>()?,
+        ),
         term::Type::Lit(lit) => lit_ty_to_ty(lit),
         term::Type::App { box func, args } => TApp(
-            box lower_type_to_why(ctx, func),
-            args.into_iter().map(|t| lower_type_to_why(ctx, t)).collect(),
+            box lower_type_to_why(ctx, func)?,
+            args.into_iter().map(|t| lower_type_to_why(ctx, t)).collect::<Result<_, _>>()?,
         ),
         term::Type::Function { args, box res } => {
-            args.into_iter().rfold(lower_type_to_why(ctx, res), |acc, arg| {
-                TFun(box lower_type_to_why(ctx, arg), box acc)
-            })
+            let res = lower_type_to_why(ctx, res)?;
+            args.into_iter().try_rfold(res, |acc, arg| {
+                Ok::<_, LowerError>(TFun(box lower_type_to_why(ctx, arg)?, box acc))
+            })?
         }
         term::Type::Var(tyvar) => TVar(('a'..).nth(tyvar.0 as usize).unwrap().to_string()),
         term::Type::Unknown(_) => {
             panic!()
         } // _ => panic!("{:?}", ty),
-    }
+    };
+    Ok(ty)
 }
 
 fn lit_ty_to_ty(litty: pearlite::term::LitTy) -> mlcfg::Type {
@@ -123,6 +243,7 @@ fn lit_ty_to_ty(litty: pearlite::term::LitTy) -> mlcfg::Type {
             Sixteen => i16_ty(),
             ThirtyTwo => i32_ty(),
             SixtyFour => i64_ty(),
+            OneTwentyEight => i128_ty(),
             Mach => isize_ty(),
             Unknown => unimplemented!("integers"),
         },
@@ -131,13 +252,16 @@ fn lit_ty_to_ty(litty: pearlite::term::LitTy) -> mlcfg::Type {
             Sixteen => u16_ty(),
             ThirtyTwo => u32_ty(),
             SixtyFour => u64_ty(),
+            OneTwentyEight => u128_ty(),
             Mach => usize_ty(),
             Unknown => unimplemented!("integers"),
         },
-        term::LitTy::Float => TConstructor(QName { module: vec![], name: vec!["single".into()] }),
-        term::LitTy::Double => TConstructor(QName { module: vec![], name: vec!["double".into()] }),
+        term::LitTy::Float => TConstructor(QName::from("single")),
+        term::LitTy::Double => TConstructor(QName::from("double")),
         term::LitTy::Boolean => Bool,
-        term::LitTy::Integer => TConstructor(QName { module: vec![], name: vec!["int".into()]})
+        term::LitTy::Char => Char,
+        term::LitTy::Str => TConstructor(QName::from("string")),
+        term::LitTy::Integer => TConstructor(QName::from("int"))
     }
 }
 
@@ -149,14 +273,18 @@ fn lit_to_const(lit: pearlite::term::Literal) -> why3::mlcfg::Constant {
         term::Literal::U16(u) => Uint(u as u128, Some(u16_ty())),
         term::Literal::U32(u) => Uint(u as u128, Some(u32_ty())),
         term::Literal::U64(u) => Uint(u as u128, Some(u64_ty())),
+        term::Literal::U128(u) => Uint(u, Some(u128_ty())),
         term::Literal::Usize(u) => Uint(u as u128, Some(usize_ty())),
-        term::Literal::Int(u) => Int(u as i128, None),
-        term::Literal::F32(_) => {
-            unimplemented!()
-        }
-        term::Literal::F64(_) => {
-            unimplemented!()
+        // Literals without a machine type are mathematical integers
+        term::Literal::Int(u, None) => Int(u, None),
+        term::Literal::Int(u, Some(ty @ term::LitTy::Unsigned(_))) => {
+            Uint(u as u128, Some(lit_ty_to_ty(ty)))
         }
+        term::Literal::Int(u, Some(ty)) => Int(u, Some(lit_ty_to_ty(ty))),
+        term::Literal::F32(f) => Float(f as f64, Some(lit_ty_to_ty(term::LitTy::Float))),
+        term::Literal::F64(f) => Float(f, Some(lit_ty_to_ty(term::LitTy::Double))),
+        term::Literal::Char(c) => Constant::Char(c),
+        term::Literal::Str(s) => Constant::Str(s),
         term::Literal::Bool(b) => {
             if b {
                 Constant::const_true()
@@ -180,29 +308,84 @@ fn op_to_op(op: term::BinOp) -> mlcfg::BinOp {
         term::BinOp::Ge => Ge,
         term::BinOp::Gt => Gt,
         term::BinOp::Lt => Lt,
-        term::BinOp::And => And,
-        term::BinOp::Or => Or,
-        term::BinOp::Impl => {
+        term::BinOp::And => LAnd,
+        term::BinOp::Or => LOr,
+        term::BinOp::Iff => Iff,
+        term::BinOp::Impl | term::BinOp::Xor => {
             panic!()
         }
     }
 }
 
-fn lower_arm_to_why(ctx: &mut Ctx, a: term::MatchArm) -> (mlcfg::Pattern, Exp) {
-    (lower_pattern_to_why(ctx, a.pat), lower_term_to_why(ctx, *a.body))
+// The constructor tested by `matches!(e, C(..))`, which parses to a match with a `true` arm for
+// `C` and a `false` fallback
+fn variant_test(arms: &[term::MatchArm]) -> Option<&Name> {
+    use term::{Literal::Bool, MatchArm, Pattern::{TupleStruct, Wild}, Term::Lit};
+    match arms {
+        [
+            MatchArm { pat: TupleStruct { path, fields }, guard: None, body: box Lit { lit: t } },
+            MatchArm { pat: Wild, guard: None, body: box Lit { lit: f } },
+        ] if *t == Bool(true) && *f == Bool(false) && fields.iter().all(|p| *p == Wild) => {
+            Some(path)
+        }
+        _ => None,
+    }
+}
+
+fn lower_arm_to_why(
+    ctx: &mut Ctx,
+    a: term::MatchArm,
+) -> Result<(mlcfg::Pattern, Option<Exp>, Exp), LowerError> {
+    let mut lits = Vec::new();
+    let pat = lower_pattern_to_why(ctx, a.pat, &mut lits)?;
+    let guard = a.guard.map(|box g| lower_term(ctx, g)).transpose()?;
+    Ok((pat, literal_guard(lits, guard), lower_term(ctx, *a.body)?))
+}
+
+// Why3 patterns cannot match literals, so each literal is bound to a fresh variable and the
+// arm is guarded by the equalities between those variables and the literals.
+fn literal_guard(lits: Vec<Exp>, guard: Option<Exp>) -> Option<Exp> {
+    let and = |l, r| Exp::BinaryOp(mlcfg::BinOp::LAnd, box l, box r);
+    lits.into_iter().chain(guard).fold_first(and)
+}
+
+// The comparisons a literal or range pattern makes of the value bound to `var`
+fn literal_test(var: &mlcfg::LocalIdent, pat: term::Pattern) -> Exp {
+    use mlcfg::BinOp::*;
+    let var = || box Exp::Var(var.clone());
+    let lit = |lit| box Exp::Const(lit_to_const(lit));
+    match pat {
+        term::Pattern::Lit(l) => Exp::BinaryOp(Eq, var(), lit(l)),
+        term::Pattern::Range { lo, hi, inclusive } => {
+            let hi_op = if inclusive { Le } else { Lt };
+            let lo = Exp::BinaryOp(Le, lit(lo), var());
+            Exp::BinaryOp(LAnd, box lo, box Exp::BinaryOp(hi_op, var(), lit(hi)))
+        }
+        _ => unreachable!("not a literal pattern: {:?}", pat),
+    }
 }
 
-fn lower_pattern_to_why(ctx: &mut Ctx, p: term::Pattern) -> mlcfg::Pattern {
+fn lower_pattern_to_why(
+    ctx: &mut Ctx,
+    p: term::Pattern,
+    lits: &mut Vec<Exp>,
+) -> Result<mlcfg::Pattern, LowerError> {
     use mlcfg::Pattern;
-    match p {
+    let pat = match p {
         term::Pattern::Var(x) => Pattern::VarP(x.0.into()),
         // term::Pattern::Struct { path, fields } => {}
         term::Pattern::TupleStruct { path, fields } => {
-            let name = lower_value_path(ctx, path);
-            let fields = fields.into_iter().map(|p| lower_pattern_to_why(ctx, p)).collect();
+            let name = lower_value_path(ctx, path)?;
+            let fields = fields
+                .into_iter()
+                .map(|p| lower_pattern_to_why(ctx, p, lits))
+                .collect::<Result<_, _>>()?;
 
             Pattern::ConsP(name, fields)
         }
+        term::Pattern::Tuple(pats) => Pattern::TupleP(
+            pats.into_iter().map(|p| lower_pattern_to_why(ctx, p, lits)).collect::<Result<_, _>>()?,
+        ),
         term::Pattern::Boolean(b) => {
             if b {
                 Pattern::mk_true()
@@ -210,11 +393,31 @@ fn lower_pattern_to_why(ctx: &mut Ctx, p: term::Pattern) -> mlcfg::Pattern {
                 Pattern::mk_false()
             }
         }
+        term::Pattern::Lit(_) | term::Pattern::Range { .. } => {
+            let var: mlcfg::LocalIdent = format!("lit'{}", lits.len()).into();
+            lits.push(literal_test(&var, p));
+            Pattern::VarP(var)
+        }
+        // Why3 has no `as` patterns, so `x @ p` binds the value to `x` directly and tests `x`
+        // against `p` in the guard, like a literal. This only works when `p` binds nothing and
+        // doesn't destructure the value, so `p` must be a literal, a range or a wildcard.
+        term::Pattern::Binding { name, box pat } => {
+            let var: mlcfg::LocalIdent = name.0.into();
+            match pat {
+                term::Pattern::Wild => {}
+                term::Pattern::Lit(_) | term::Pattern::Range { .. } => {
+                    lits.push(literal_test(&var, pat))
+                }
+                _ => return Err(LowerError::UnsupportedBinding(var.to_string())),
+            }
+            Pattern::VarP(var)
+        }
         term::Pattern::Wild => Pattern::Wildcard,
         _ => {
             unimplemented!()
         }
-    }
+    };
+    Ok(pat)
 }
 
 fn is_constructor(ctx: &mut Ctx, path: &Name) -> bool {
@@ -231,20 +434,75 @@ fn is_constructor(ctx: &mut Ctx, path: &Name) -> bool {
     }
 }
 
-fn lower_value_path(ctx: &mut Ctx, path: Name) -> QName {
-    if let Name::Path { id, .. } = path {
-        let defid: DefId = super::id_to_def_id(id);
-        crate::translation::translate_value_id(ctx.tcx, defid)
-    } else {
-        panic!("cannot lower a local identifier to a qualified name");
+fn is_ord_cmp(ctx: &mut Ctx, path: &Name) -> bool {
+    match path {
+        Name::Ident(_) => false,
+        Name::Path { id, .. } => super::is_ord_cmp(ctx.tcx, super::id_to_def_id(*id)),
     }
 }
 
-fn lower_type_path(ctx: &mut Ctx, path: Name) -> QName {
-    if let Name::Path { id, .. } = path {
-        let defid: DefId = super::id_to_def_id(id);
-        crate::ty::translate_ty_name(ctx, defid)
-    } else {
-        panic!("cannot lower a local identifier to a qualified name");
+fn is_box_new(ctx: &mut Ctx, path: &Name) -> bool {
+    match path {
+        Name::Ident(_) => false,
+        Name::Path { id, .. } => {
+            let path = ctx.tcx.def_path_str(super::id_to_def_id(*id));
+            path == "std::boxed::Box::<T>::new" || path == "alloc::boxed::Box::<T>::new"
+        }
+    }
+}
+
+// `Ord::cmp` is modeled as a three-way comparison of its operands:
+// `Less` when `l < r`, `Equal` when `l = r` and `Greater` otherwise.
+fn lower_cmp(ctx: &mut Ctx, func: Name, l: Exp, r: Exp) -> Exp {
+    use mlcfg::{BinOp, Pattern};
+    use rustc_middle::ty::TyKind;
+
+    let defid = match func {
+        Name::Path { id, .. } => super::id_to_def_id(id),
+        Name::Ident(_) => unreachable!(),
+    };
+    let ordering = ctx.tcx.fn_sig(defid).output().skip_binder();
+    let def = match ordering.kind() {
+        TyKind::Adt(def, _) => def,
+        _ => unreachable!("`Ord::cmp` should return an `Ordering`"),
+    };
+    // Make sure the declaration of `Ordering` is emitted.
+    crate::ty::translate_ty_name(ctx, def.did);
+
+    let tcx = ctx.tcx;
+    let variant = |name: &str| {
+        let var = def.variants.iter().find(|v| v.ident.name.as_str() == name).unwrap();
+        let ctor = crate::translation::translate_value_id(tcx, var.def_id);
+        Exp::Constructor { ctor, args: vec![] }
+    };
+    let (less, equal, greater) = (variant("Less"), variant("Equal"), variant("Greater"));
+
+    let is_equal = Exp::Match(
+        box Exp::BinaryOp(BinOp::Eq, box l.clone(), box r.clone()),
+        vec![(Pattern::mk_true(), None, equal), (Pattern::mk_false(), None, greater)],
+    );
+    Exp::Match(
+        box Exp::BinaryOp(BinOp::Lt, box l, box r),
+        vec![(Pattern::mk_true(), None, less), (Pattern::mk_false(), None, is_equal)],
+    )
+}
+
+fn lower_value_path(ctx: &mut Ctx, path: Name) -> Result<QName, LowerError> {
+    match path {
+        Name::Path { id, .. } => {
+            let defid: DefId = super::id_to_def_id(id);
+            Ok(crate::translation::translate_value_id(ctx.tcx, defid))
+        }
+        Name::Ident(id) => Err(LowerError::LocalAsPath(id)),
+    }
+}
+
+fn lower_type_path(ctx: &mut Ctx, path: Name) -> Result<QName, LowerError> {
+    match path {
+        Name::Path { id, .. } => {
+            let defid: DefId = super::id_to_def_id(id);
+            Ok(crate::ty::translate_ty_name(ctx, defid))
+        }
+        Name::Ident(id) => Err(LowerError::LocalAsPath(id)),
     }
 }
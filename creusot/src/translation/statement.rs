@@ -1,10 +1,13 @@
+use std::collections::HashSet;
+
 use rustc_errors::DiagnosticId;
 use rustc_middle::mir::{
-    BorrowKind::*, Operand::*, Place, Rvalue, SourceInfo, Statement, StatementKind,
+    BorrowKind::*, CastKind, Operand::*, Place, Rvalue, SourceInfo, Statement, StatementKind,
 };
 
 use why3::mlcfg::{
         // Constant,
+        numeric_cast,
         Exp::{self, *},
         Statement::*,
     };
@@ -14,7 +17,7 @@ use crate::{
 };
 
 use super::specification::Spec;
-use super::{specification, FunctionTranslator};
+use super::{specification, ty, FunctionTranslator};
 
 impl<'tcx> FunctionTranslator<'_, '_, 'tcx> {
     pub fn translate_statement(&mut self, statement: &'_ Statement<'tcx>) {
@@ -99,7 +102,13 @@ impl<'tcx> FunctionTranslator<'_, '_, 'tcx> {
                                     si,
                                     expression,
                                 );
-                                self.emit_statement(Invariant(name, Verbatim(invariant)));
+                                let id = self.invariant_count;
+                                self.invariant_count += 1;
+                                self.emit_statement(Invariant {
+                                    label: name,
+                                    id,
+                                    exp: Verbatim(invariant, HashSet::new()),
+                                });
                                 return;
                             }
                             Ok(_) => self.sess.span_fatal_with_code(
@@ -125,6 +134,19 @@ impl<'tcx> FunctionTranslator<'_, '_, 'tcx> {
             Rvalue::Len(pl) => {
                 RecField { record: box self.translate_rplace(&simplify_place(self.tcx, self.body, pl)), label: "length".into() }
             }
+            Rvalue::Cast(CastKind::Misc, op, ty) => {
+                let span = si.span;
+                let from = ty::translate_ty(&mut self.ty_ctx, span, op.ty(self.body, self.tcx));
+                let to = ty::translate_ty(&mut self.ty_ctx, span, ty);
+                match numeric_cast(self.translate_operand(op), &from, &to) {
+                    Some(cast) => cast,
+                    None => self.sess.span_fatal_with_code(
+                        span,
+                        &format!("unsupported cast from {:?} to {:?}", from, to),
+                        DiagnosticId::Error(String::from("creusot")),
+                    ),
+                }
+            }
             Rvalue::Cast(_, _, _)
             | Rvalue::NullaryOp(_, _)
             | Rvalue::Repeat(_, _)
@@ -3,17 +3,17 @@ use std::collections::HashMap;
 use rustc_errors::DiagnosticId;
 use rustc_hir::def_id::DefId;
 use rustc_middle::{
-    mir::{SourceInfo, SwitchTargets},
+    mir::{BasicBlock, SourceInfo, SwitchTargets},
     ty::AdtDef,
 };
 use rustc_middle::{
-    mir::{Location, Operand, Terminator, TerminatorKind::*},
+    mir::{AssertKind, Location, Operand, Terminator, TerminatorKind::*},
     ty,
 };
 use rustc_session::Session;
 use rustc_target::abi::VariantIdx;
 
-use why3::mlcfg::{Constant, Exp, Pattern, Terminator as MlT, Statement, BinOp, BlockId};
+use why3::mlcfg::{Constant, Exp, Pattern, Terminator as MlT, Statement, BinOp, BlockId, UnOp};
 
 use crate::{
     place::simplify_place,
@@ -32,6 +32,11 @@ impl<'tcx> FunctionTranslator<'_, '_, 'tcx> {
         match &terminator.kind {
             Goto { target } => self.emit_terminator(mk_goto(*target)),
             SwitchInt { discr, targets, .. } => {
+                if let Some((goal, target)) = self.assertion(discr, targets) {
+                    self.emit_statement(Statement::Assert { goal, by: None });
+                    return self.emit_terminator(mk_goto(target));
+                }
+
                 let real_discr =
                     discriminator_for_switch(&self.body.basic_blocks()[location.block])
                         .map(Operand::Move)
@@ -77,21 +82,44 @@ impl<'tcx> FunctionTranslator<'_, '_, 'tcx> {
                     Exp::Call(box Exp::QVar(fname), func_args)
                 };
 
-                if destination.is_none() {
+                match destination {
                     // If we have no target block after the call, then we cannot move past it.
-                    self.emit_terminator(MlT::Absurd);
-                } else {
-                    let (loc, bb) = destination.unwrap();
-                    self.emit_assignment(&simplify_place(self.tcx, self.body, &loc), call_exp);
-                    self.emit_terminator(MlT::Goto(BlockId(bb.into())));
+                    None => self.emit_terminator(MlT::Absurd),
+                    Some((loc, bb)) => {
+                        let diverges = loc
+                            .ty(self.body, self.tcx)
+                            .ty
+                            .conservative_is_privately_uninhabited(self.tcx);
+
+                        let dest = simplify_place(self.tcx, self.body, loc);
+                        match call_exp {
+                            // Calls into plain locals don't need to rebuild their destination.
+                            Exp::Call(box Exp::QVar(func), args) if dest.proj.is_empty() => {
+                                let dest = self.translate_local(dest.local);
+                                self.emit_statement(Statement::Call { dest, func, args });
+                            }
+                            _ => self.emit_assignment(&dest, call_exp),
+                        }
+
+                        // A call producing an uninhabited value can't return either.
+                        if diverges {
+                            self.emit_terminator(MlT::Absurd);
+                        } else {
+                            self.emit_terminator(MlT::Goto(BlockId((*bb).into())));
+                        }
+                    }
                 }
             }
-            Assert { cond, expected, msg: _, target, cleanup: _ } => {
+            Assert { cond, expected, msg, target, cleanup: _ } => {
                 let mut ass = self.translate_operand(cond);
                 if !expected {
                     ass = Exp::UnaryOp(why3::mlcfg::UnOp::Not, box ass);
                 }
-                self.emit_statement(Statement::Assert(ass));
+                // Bounds checks have no static description
+                if !matches!(msg, AssertKind::BoundsCheck { .. }) {
+                    ass = Exp::Attr(format!("expl:{}", msg.description()), box ass);
+                }
+                self.emit_statement(Statement::Assert { goal: ass, by: None });
                 self.emit_terminator(mk_goto(*target))
             }
 
@@ -114,6 +142,61 @@ impl<'tcx> FunctionTranslator<'_, '_, 'tcx> {
     fn is_box_new(&self, def_id: DefId) -> bool {
         self.tcx.def_path_str(def_id) == "std::boxed::Box::<T>::new"
     }
+
+    // `assert!(cond)` expands to a branch on `cond` into a block which panics. Rather than a
+    // switch with an absurd branch, this is translated to an assertion of the condition under
+    // which the branch doesn't panic, followed by a jump to the other target.
+    fn assertion(
+        &mut self,
+        discr: &Operand<'tcx>,
+        targets: &SwitchTargets,
+    ) -> Option<(Exp, BasicBlock)> {
+        if !discr.ty(self.body, self.tcx).is_bool() {
+            return None;
+        }
+        let (false_tgt, true_tgt) = match targets.all_targets() {
+            [false_tgt, true_tgt] => (*false_tgt, *true_tgt),
+            _ => return None,
+        };
+
+        let (panics_on_true, msg) = match self.panic_message(true_tgt) {
+            Some(msg) => (true, msg),
+            None => (false, self.panic_message(false_tgt)?),
+        };
+
+        let mut goal = self.translate_operand(discr);
+        if panics_on_true {
+            goal = Exp::UnaryOp(UnOp::Not, box goal);
+        }
+        if let Some(msg) = msg {
+            goal = Exp::Attr(format!("expl:{}", msg), box goal);
+        }
+        Some((goal, if panics_on_true { false_tgt } else { true_tgt }))
+    }
+
+    // If `bb` ends by panicking, the message it panics with when that message is a literal.
+    // `assert!(cond)` panics through `core::panicking::panic` with a generated message, while
+    // `assert!(cond, "msg")` passes `"msg"` to `begin_panic`.
+    fn panic_message(&self, bb: BasicBlock) -> Option<Option<String>> {
+        let (func, args) = match &self.body.basic_blocks()[bb].terminator().kind {
+            Call { func, args, destination: None, .. } => (func, args),
+            _ => return None,
+        };
+
+        match &self.tcx.def_path_str(func_defid(func)?)[..] {
+            "core::panicking::panic" => Some(None),
+            "std::rt::begin_panic" | "std::panicking::begin_panic" => {
+                let msg = match super::from_mir_constant(self.tcx, args.first()?.constant()?) {
+                    Constant::Other(lit) => {
+                        lit.strip_prefix('"').and_then(|l| l.strip_suffix('"')).map(str::to_owned)
+                    }
+                    _ => None,
+                };
+                Some(msg)
+            }
+            _ => None,
+        }
+    }
 }
 
 // Try to extract a function defid from an operand
@@ -219,10 +302,7 @@ where
     T: Iterator<Item = (Constant, MlT)> + DoubleEndedIterator,
 {
     targets.rfold(default, |acc, (val, term)| {
-        MlT::Switch(
-            Exp::BinaryOp(BinOp::Eq, box discr.clone(), box Exp::Const(val)),
-            vec![(Pattern::mk_true(), term), (Pattern::mk_false(), acc)],
-        )
+        MlT::if_(Exp::BinaryOp(BinOp::Eq, box discr.clone(), box Exp::Const(val)), term, acc)
     })
 }
 
@@ -1,5 +1,5 @@
 use indexmap::{IndexMap, IndexSet};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 use rustc_errors::DiagnosticId;
 use rustc_hir::def_id::DefId;
@@ -19,11 +19,25 @@ pub struct Ctx<'a, 'tcx> {
     sess: &'a Session,
 
     results: IndexMap<DefId, (TyDecl, Predicate)>,
+
+    // Specification terms which have already been lowered, along with their lowering and the
+    // types it named, so that a cache hit can declare them again.
+    pub lowered_terms: HashMap<pearlite::term::Term, (MlE, Vec<DefId>)>,
+
+    // The types named by the term currently being lowered, if any
+    pub named_tys: Option<Vec<DefId>>,
 }
 
 impl<'a, 'tcx> Ctx<'a, 'tcx> {
     pub fn new(tcx: TyCtxt<'tcx>, sess: &'a Session) -> Self {
-        Self { tcx, translated_tys: IndexSet::new(), sess, results: IndexMap::new() }
+        Self {
+            tcx,
+            translated_tys: IndexSet::new(),
+            sess,
+            results: IndexMap::new(),
+            lowered_terms: HashMap::new(),
+            named_tys: None,
+        }
     }
 
     /// Gather the translated types and predicates into a module.
@@ -33,24 +47,24 @@ impl<'a, 'tcx> Ctx<'a, 'tcx> {
         }
     }
 
-    fn crash_and_error(&self, span: Span, msg: &str) -> ! {
+    /// The type declarations translated so far
+    pub fn ty_decls(&self) -> impl Iterator<Item = &TyDecl> {
+        self.results.values().map(|(decl, _)| decl)
+    }
+
+    pub fn crash_and_error(&self, span: Span, msg: &str) -> ! {
         self.sess.span_fatal_with_code(span, msg, DiagnosticId::Error(String::from("creusot")))
     }
 }
 
 /// Translate a Rust type into an MLW one.
 pub fn translate_ty<'tcx>(ctx: &mut Ctx<'_, 'tcx>, span: Span, ty: Ty<'tcx>) -> MlT {
-    use rustc_middle::ty::FloatTy::*;
-
     match ty.kind() {
         Bool => MlT::Bool,
         Char => MlT::Char,
         Int(ity) => intty_to_ty(ity),
         Uint(uity) => uintty_to_ty(uity),
-        Float(flty) => match flty {
-            F32 => MlT::TConstructor(QName { module: vec![], name: vec!["single".into()] }),
-            F64 => MlT::TConstructor(QName { module: vec![], name: vec!["double".into()] }),
-        },
+        Float(flty) => floatty_to_ty(flty),
         Adt(def, s) => {
             if def.is_box() {
                 return translate_ty(ctx, span, s[0].expect_ty());
@@ -131,6 +145,9 @@ pub fn check_not_mutally_recursive<'tcx>(ctx: &mut Ctx<'_, 'tcx>, ty_id: DefId,
 }
 
 pub fn translate_ty_name(ctx: &mut Ctx<'_, '_>, did: DefId) -> QName {
+    if let Some(named) = &mut ctx.named_tys {
+        named.push(did);
+    }
     // Check if we've already translated this type before.
     if !ctx.translated_tys.contains(&did) {
         translate_tydecl(ctx, rustc_span::DUMMY_SP, did);
@@ -186,7 +203,13 @@ pub fn translate_tydecl(ctx: &mut Ctx<'_, '_>, span: Span, did: DefId) {
 
     let pred = drop_pred_decl(ctx, &ty_args, adt, did);
 
-    let ty_decl = TyDecl { ty_name, ty_params: ty_args, ty_constructors: ml_ty_def };
+    let ty_decl = TyDecl {
+        ty_name,
+        ty_params: ty_args,
+        ty_constructors: ml_ty_def,
+        ty_fields: Vec::new(),
+        ty_alias: None,
+    };
     ctx.results.insert(did, (ty_decl, pred));
 }
 
@@ -203,9 +226,9 @@ pub fn drop_predicate<'tcx>(ctx: &mut Ctx<'_, 'tcx>, ty: Ty<'tcx>) -> MlE {
 }
 
 fn drop_pred_name<'tcx>(ctx: &mut Ctx<'_, 'tcx>, did: DefId) -> QName {
-    let mut name = translate_ty_name(ctx, did);
-    name.name.insert(0, "drop".to_owned());
-    name
+    let name = translate_ty_name(ctx, did);
+    let segs = std::iter::once("drop".to_owned()).chain(name.name.iter().cloned());
+    QName::new(name.module.iter().cloned(), segs)
 }
 
 // Generate the drop predicate for a specific type
@@ -224,21 +247,21 @@ fn drop_pred_decl(
 
         let field_names: Vec<_> = ('a'..).take(variant.fields.len()).collect();
 
-        let drop_variant = field_names
-            .iter()
-            .map(|c| MlE::Var(c.to_string().into()))
-            .zip(drop_fields)
-            .map(|(arg, field_drop)| field_drop.app_to(arg))
-            .fold_first(MlE::conj)
-            .unwrap_or_else(MlE::mk_true);
-        branches.push((variant_pattern(ctx.tcx, variant), drop_variant));
+        let drop_variant = MlE::conj_all(
+            field_names
+                .iter()
+                .map(|c| MlE::Var(c.to_string().into()))
+                .zip(drop_fields)
+                .map(|(arg, field_drop)| field_drop.app_to(arg)),
+        );
+        branches.push((variant_pattern(ctx.tcx, variant), None, drop_variant));
     }
 
     let drop_arg = MlE::Var("self".into());
 
     let type_drop = if branches.len() == 1 {
-        let (pat, variant) = branches.remove(0);
-        MlE::Let { pattern: pat, arg: box drop_arg, body: box variant }
+        let (pat, _, variant) = branches.remove(0);
+        MlE::Let { pattern: pat, arg: box drop_arg, body: box variant, ghost: false }
     } else {
         MlE::Match(box drop_arg, branches)
     };
@@ -286,18 +309,23 @@ pub fn drop_pred_body<'tcx>(
             let field_names: Vec<LocalIdent> =
                 ('a'..).map(|c| c.to_string().into()).take(s.types().count()).collect();
 
-            let body = s
-                .types()
-                .zip(field_names.iter())
-                .map(|(ty, v)| drop_pred_body(ctx, ty, rec_call_did).app_to(v.clone().into()))
-                .fold_first(MlE::conj)
-                .unwrap_or_else(MlE::mk_true);
+            let body = MlE::conj_all(
+                s.types()
+                    .zip(field_names.iter())
+                    .map(|(ty, v)| drop_pred_body(ctx, ty, rec_call_did).app_to(v.clone().into())),
+            );
 
             let field_pat = Pattern::TupleP(field_names.into_iter().map(VarP).collect());
 
             MlE::Abs(
                 binder_name.clone(),
-                box MlE::Let { pattern: field_pat, arg: box MlE::Var(binder_name), body: box body },
+                None,
+                box MlE::Let {
+                    pattern: field_pat,
+                    arg: box MlE::Var(binder_name),
+                    body: box body,
+                    ghost: false,
+                },
             )
         }
         Param(s) => MlE::Var(format!("drop_{}", translate_ty_param(s.name)).into()),
@@ -311,7 +339,8 @@ pub fn drop_pred_body<'tcx>(
     }
 }
 
-fn intty_to_ty(ity: &rustc_middle::ty::IntTy) -> MlT {
+/// The machine integer type corresponding to a signed Rust integer type.
+pub fn intty_to_ty(ity: &rustc_middle::ty::IntTy) -> MlT {
     use rustc_middle::ty::IntTy::*;
     match ity {
         Isize => isize_ty(),
@@ -319,11 +348,12 @@ fn intty_to_ty(ity: &rustc_middle::ty::IntTy) -> MlT {
         I16 => i16_ty(),
         I32 => i32_ty(),
         I64 => i64_ty(),
-        I128 => unimplemented!("128 bit integers not yet implemented"),
+        I128 => i128_ty(),
     }
 }
 
-fn uintty_to_ty(ity: &rustc_middle::ty::UintTy) -> MlT {
+/// The machine integer type corresponding to an unsigned Rust integer type.
+pub fn uintty_to_ty(ity: &rustc_middle::ty::UintTy) -> MlT {
     use rustc_middle::ty::UintTy::*;
     match ity {
         Usize => usize_ty(),
@@ -331,46 +361,63 @@ fn uintty_to_ty(ity: &rustc_middle::ty::UintTy) -> MlT {
         U16 => u16_ty(),
         U32 => u32_ty(),
         U64 => u64_ty(),
-        U128 => unimplemented!("128 bit integers not yet implemented"),
+        U128 => u128_ty(),
+    }
+}
+
+/// The IEEE float type corresponding to a Rust float type.
+pub fn floatty_to_ty(fty: &rustc_middle::ty::FloatTy) -> MlT {
+    use rustc_middle::ty::FloatTy::*;
+    match fty {
+        F32 => MlT::TConstructor(QName::from("single")),
+        F64 => MlT::TConstructor(QName::from("double")),
     }
 }
 
 pub fn u8_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["uint8".into()] })
+    MlT::TConstructor(QName::from("uint8"))
 }
 
 pub fn u16_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["uint16".into()] })
+    MlT::TConstructor(QName::from("uint16"))
 }
 
 pub fn u32_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["uint32".into()] })
+    MlT::TConstructor(QName::from("uint32"))
 }
 
 pub fn u64_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["uint64".into()] })
+    MlT::TConstructor(QName::from("uint64"))
+}
+
+pub fn u128_ty() -> MlT {
+    MlT::TConstructor(QName::from("uint128"))
 }
 
 pub fn usize_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["usize".into()] })
+    MlT::TConstructor(QName::from("usize"))
 }
 
 pub fn i8_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["int8".into()] })
+    MlT::TConstructor(QName::from("int8"))
 }
 
 pub fn i16_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["int16".into()] })
+    MlT::TConstructor(QName::from("int16"))
 }
 
 pub fn i32_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["int32".into()] })
+    MlT::TConstructor(QName::from("int32"))
 }
 
 pub fn i64_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["int64".into()] })
+    MlT::TConstructor(QName::from("int64"))
+}
+
+pub fn i128_ty() -> MlT {
+    MlT::TConstructor(QName::from("int128"))
 }
 
 pub fn isize_ty() -> MlT {
-    MlT::TConstructor(QName { module: vec![], name: vec!["isize".into()] })
+    MlT::TConstructor(QName::from("isize"))
 }
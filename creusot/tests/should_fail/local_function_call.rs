@@ -0,0 +1,18 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+extern crate creusot_contracts;
+use creusot_contracts::*;
+
+#[logic]
+fn double(x: Int) -> Int {
+    x + x
+}
+
+// Only items can be called in specifications, calling a local should be reported as an error
+#[logic]
+fn apply(x: Int) -> Int {
+    let f = double;
+    f(x)
+}
+
+fn main() {}
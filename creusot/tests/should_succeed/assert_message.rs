@@ -0,0 +1,5 @@
+// SHOULD_SUCCEED: parse-print
+// The message of an assertion explains the generated Why3 assertion
+fn main () {
+	assert!(!false, "false is false");
+}
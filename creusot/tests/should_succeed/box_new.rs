@@ -0,0 +1,12 @@
+// SHOULD_SUCCEED: parse-print
+#![feature(register_tool)]
+#![register_tool(creusot)]
+extern crate creusot_contracts;
+use creusot_contracts::*;
+
+#[logic]
+fn unbox(x: u32) -> u32 {
+  *Box::new(x)
+}
+
+fn main () {}
@@ -0,0 +1,9 @@
+// SHOULD_SUCCEED: parse-print
+// A call to a function returning `!` can't return, so its block ends with `absurd`
+fn diverge() -> ! {
+    panic!()
+}
+
+fn main() {
+    diverge()
+}
@@ -0,0 +1,14 @@
+// Casts between floats and integers
+fn to_double(x: i64) -> f64 {
+    x as f64
+}
+
+fn truncate(x: f64) -> u32 {
+    x as u32
+}
+
+fn narrow(x: f64) -> f32 {
+    x as f32
+}
+
+fn main () { }
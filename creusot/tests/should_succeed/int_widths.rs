@@ -0,0 +1,4 @@
+// SHOULD_SUCCEED: parse-print
+struct Widths(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+fn main () {}
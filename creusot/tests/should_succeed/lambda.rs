@@ -0,0 +1,12 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+extern crate creusot_contracts;
+use creusot_contracts::*;
+
+#[logic]
+fn positive(x: u32) -> bool {
+    let gt = |y: u32| y > 0u32;
+    x > 0u32
+}
+
+fn main () {}
@@ -0,0 +1,19 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+
+extern crate creusot_contracts;
+
+use creusot_contracts::*;
+
+enum U { C }
+
+enum T { A, B }
+
+#[ensures(matches!(T::B, T::A))]
+fn test_variant() {}
+
+// `C` is the only constructor of `U`, so the test is simply `true`
+#[ensures(matches!(U::C, U::C))]
+fn test_single_variant() {}
+
+fn main(){}
@@ -0,0 +1,16 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+extern crate creusot_contracts;
+use creusot_contracts::*;
+
+use std::cmp::Ordering;
+
+#[logic]
+fn is_less(a: u32, b: u32) -> bool {
+    match a.cmp(&b) {
+        Ordering::Less => true,
+        _ => false,
+    }
+}
+
+fn main () {}
@@ -0,0 +1,19 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+
+extern crate creusot_contracts;
+
+use creusot_contracts::*;
+
+enum T { A, B }
+
+// The contract of `second` is lowered from the cache, and must match the contract of `first`
+#[ensures(T::A == T::A)]
+#[ensures(match T::B { T::A => false, T::B => true })]
+fn first() {}
+
+#[ensures(T::A == T::A)]
+#[ensures(match T::B { T::A => false, T::B => true })]
+fn second() {}
+
+fn main(){}
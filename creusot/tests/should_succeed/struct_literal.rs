@@ -0,0 +1,14 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+#![feature(proc_macro_hygiene, stmt_expr_attributes)]
+
+extern crate creusot_contracts;
+
+use creusot_contracts::*;
+
+struct Point { x: u32, y: u32 }
+
+#[ensures(Point { y: 1u32, x: 0u32 } == Point { x: 0u32, y: 1u32 })]
+fn test_struct_literal() {}
+
+fn main(){}
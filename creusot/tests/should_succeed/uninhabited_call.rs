@@ -0,0 +1,11 @@
+// SHOULD_SUCCEED: parse-print
+// `Void` has no values, so a call returning it can't return even though it has a destination
+enum Void {}
+
+fn f() -> Void {
+    panic!()
+}
+
+fn main() {
+    let v: Void = f();
+}
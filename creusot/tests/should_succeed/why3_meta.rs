@@ -0,0 +1,12 @@
+#![feature(register_tool)]
+#![register_tool(creusot)]
+
+extern crate creusot_contracts;
+
+use creusot_contracts::*;
+
+// The directive is emitted just before the function it annotates
+#[why3_meta("compute_max_steps" 1000000)]
+fn f() {}
+
+fn main(){}
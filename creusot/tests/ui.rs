@@ -138,9 +138,20 @@ fn should_succeed_case(output: std::process::Output, stdout: &Path, _stderr: &Pa
     Ok((success, buf))
 }
 
-fn should_fail_case(output: std::process::Output, _stdout: &Path, _stderr: &Path) -> Result<(bool, Buffer), Box<dyn Error>> {
-    let buf = Buffer::ansi();
-    Ok((!output.status.success(), buf))
+// When a `.stderr` file is present, each of its lines must also be reported by the failure.
+// The whole output isn't compared, since it contains paths which vary between machines.
+fn should_fail_case(output: std::process::Output, _stdout: &Path, stderr: &Path) -> Result<(bool, Buffer), Box<dyn Error>> {
+    let mut buf = Buffer::ansi();
+    let mut success = !output.status.success();
+
+    let expect = std::fs::read_to_string(stderr).unwrap_or_else(|_| String::new());
+    let gotten = std::str::from_utf8(&output.stderr)?;
+    for line in expect.lines().filter(|l| !gotten.contains(*l)) {
+        writeln!(&mut buf, "missing from stderr: {}", line)?;
+        success = false;
+    }
+
+    Ok((success, buf))
 }
 
 fn print_diff<W : WriteColor>(mut buf: W, diff: Vec<Chunk>) {
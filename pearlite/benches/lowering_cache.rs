@@ -0,0 +1,59 @@
+#![feature(test, box_syntax, box_patterns)]
+extern crate test;
+
+use pearlite::term::{BinOp, Literal, Name, Term};
+use std::collections::HashMap;
+use test::{black_box, Bencher};
+use why3::mlcfg::{self, Constant, Exp, QName, Type};
+
+// A balanced tree of additions with `2^depth` leaves, like a large specification
+fn sum(depth: u32) -> Term {
+    if depth == 0 {
+        return Term::Variable { path: Name::Ident("x".into()) };
+    }
+    Term::Binary {
+        left: box sum(depth - 1),
+        op: BinOp::Add,
+        right: box Term::Binary {
+            left: box sum(depth - 1),
+            op: BinOp::Mul,
+            right: box Term::Lit { lit: Literal::U32(depth) },
+        },
+    }
+}
+
+// The part of `lower_term` which the terms above exercise
+fn lower(t: Term) -> Exp {
+    match t {
+        Term::Binary { box left, op, box right } => {
+            let op = match op {
+                BinOp::Add => mlcfg::BinOp::Add,
+                BinOp::Mul => mlcfg::BinOp::Mul,
+                _ => unreachable!(),
+            };
+            Exp::BinaryOp(op, box lower(left), box lower(right))
+        }
+        Term::Variable { path: Name::Ident(i) } => Exp::Var(i.into()),
+        Term::Lit { lit: Literal::U32(u) } => {
+            let ty = Type::TConstructor(QName::from("uint32"));
+            Exp::Const(Constant::Uint(u as u128, Some(ty)))
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Lowering a term every time it is used...
+#[bench]
+fn uncached(b: &mut Bencher) {
+    let term = sum(10);
+    b.iter(|| lower(black_box(&term).clone()));
+}
+
+// ...compared to finding it in the cache of `lower_term_to_why`
+#[bench]
+fn cached(b: &mut Bencher) {
+    let term = sum(10);
+    let mut cache = HashMap::new();
+    cache.insert(term.clone(), lower(term.clone()));
+    b.iter(|| cache.get(black_box(&term)).unwrap().clone());
+}
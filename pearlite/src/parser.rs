@@ -27,8 +27,9 @@ use ParseError::*;
 impl Term {
     pub fn from_syn<R: Resolver>(res: &R, term: RT) -> Result<Term, ParseError> {
         use syn::term::{
-            TermBinary, TermBlock, TermCall, TermCast, TermExists, TermFinal, TermForall,
-            TermImpl, TermLit, TermMatch, TermParen, TermPath, TermTuple, TermUnary, TermIf,
+            TermBinary, TermBlock, TermCall, TermCast, TermClosure, TermExists, TermFinal,
+            TermForall, TermIf, TermImpl, TermIndex, TermLit, TermMacro, TermMatch, TermMethodCall,
+            TermParen, TermPath, TermReference, TermStruct, TermTuple, TermUnary,
         };
         match term {
             RT::Match(TermMatch { box expr, arms, .. }) => Ok(Match {
@@ -43,20 +44,15 @@ impl Term {
                 use crate::term::Literal::*;
                 use syn::{Lit as RL, LitBool};
                 match lit {
-                    RL::Int(lit) => match lit.suffix() {
-                        "u8" => Ok(Term::Lit { lit: U8(lit.base10_parse()?) }),
-                        "u16" => Ok(Term::Lit { lit: U16(lit.base10_parse()?) }),
-                        "u32" => Ok(Term::Lit { lit: U32(lit.base10_parse()?) }),
-                        "u64" => Ok(Term::Lit { lit: U64(lit.base10_parse()?) }),
-                        "usize" => Ok(Term::Lit { lit: Usize(lit.base10_parse()?) }),
-                        _ => Ok(Term::Lit { lit: Int(lit.base10_parse()?) }),
-                    },
+                    RL::Int(lit) => Ok(Term::Lit { lit: int_lit(&lit, false)? }),
                     RL::Float(lit) => match lit.suffix() {
                         "f32" => Ok(Term::Lit { lit: F32(lit.base10_parse()?) }),
                         "f64" => Ok(Term::Lit { lit: F64(lit.base10_parse()?) }),
                         _ => Err(Other("unsupported float type".into())),
                     },
                     RL::Bool(LitBool { value, .. }) => Ok(Term::Lit { lit: Bool(value) }),
+                    RL::Char(lit) => Ok(Term::Lit { lit: Char(lit.value()) }),
+                    RL::Str(lit) => Ok(Term::Lit { lit: Str(lit.value()) }),
                     _ => Err(Other("unsupported literal".into())),
                 }
             }
@@ -94,12 +90,37 @@ impl Term {
 
                 Ok(Exists { args: targs, body: box Term::from_syn(res, term)? })
             }
+            RT::Closure(TermClosure { inputs, box body, .. }) => {
+                let mut args = Vec::new();
+                for input in inputs {
+                    let (pat, ty) = match input {
+                        syn::Pat::Type(syn::PatType { box pat, box ty, .. }) => {
+                            (pat, Some(Type::from_syn(res, ty)?))
+                        }
+                        pat => (pat, None),
+                    };
+                    match pat {
+                        syn::Pat::Ident(pat) if pat.subpat.is_none() => {
+                            args.push((Ident::from_syn(pat.ident)?, ty))
+                        }
+                        _ => return Err(Other("closure arguments must be variables".into())),
+                    }
+                }
+
+                Ok(Lambda { args, body: box Term::from_syn(res, body)? })
+            }
             RT::Block(TermBlock { block, .. }) => {
                 Self::from_tblock(res, block)
             }
             RT::Paren(TermParen { box expr, .. }) => Term::from_syn(res, expr),
             RT::Call(TermCall { box func, args, .. }) => {
                 if let RT::Path(TermPath { path, .. }) = func {
+                    // `old(e)` refers to the value of `e` before the call
+                    if path.is_ident("old") && args.len() == 1 {
+                        let arg = args.into_iter().next().unwrap();
+                        return Ok(Unary { op: UnOp::Old, expr: box Term::from_syn(res, arg)? });
+                    }
+
                     Ok(Call {
                         func: Name::from_syn(res, path)?,
                         args: args
@@ -111,6 +132,35 @@ impl Term {
                     Err(Generic)
                 }
             }
+            RT::MethodCall(TermMethodCall { box receiver, method, args, .. }) => {
+                // Only `cmp` is supported for now, it is desugared to a call to `Ord::cmp`.
+                if method != "cmp" || args.len() != 1 {
+                    return Err(Other(format!("unsupported method call: {}", method)));
+                }
+                let path: Vec<String> =
+                    ["std", "cmp", "Ord", "cmp"].iter().map(|s| s.to_string()).collect();
+                let func = res.resolve(&path).ok_or(UnknownIdentifier(path))?;
+
+                let arg = match args.into_iter().next().unwrap() {
+                    RT::Reference(TermReference { box expr, mutability: None, .. }) => expr,
+                    arg => arg,
+                };
+
+                let args = vec![Term::from_syn(res, receiver)?, Term::from_syn(res, arg)?];
+                Ok(Call { func, args })
+            }
+            RT::Struct(TermStruct { path, fields, rest: None, .. }) => {
+                let mut tfields = Vec::new();
+                for fld in fields {
+                    if let syn::Member::Named(id) = fld.member {
+                        tfields.push((Ident::from_syn(id)?, Term::from_syn(res, fld.expr)?));
+                    } else {
+                        return Err(Generic);
+                    }
+                }
+
+                Ok(Struct { path: Name::from_syn(res, path)?, fields: tfields })
+            }
             RT::Final(TermFinal { box term, .. }) => {
                 Ok(Unary { op: UnOp::Final, expr: box Term::from_syn(res, term)? })
             }
@@ -126,6 +176,10 @@ impl Term {
                 Ok(Unary { op, expr: box expr })
             }
             RT::Absurd(_) => Ok(Absurd),
+            RT::Index(TermIndex { box expr, box index, .. }) => Ok(Index {
+                base: box Term::from_syn(res, expr)?,
+                index: box Term::from_syn(res, index)?,
+            }),
             RT::Cast(TermCast { box expr, box ty, .. }) => {
                 Ok(Cast { expr: box Term::from_syn(res, expr)?, ty: Type::from_syn(res, ty)? })
             }
@@ -136,6 +190,35 @@ impl Term {
 
                 Ok(If { cond: box cond, then_branch: box then_branch, else_branch: box else_branch})
             }
+            // `matches!(e, p)` is sugar for `match e { p => true, _ => false }`
+            RT::Macro(TermMacro { mac, .. }) if mac.path.is_ident("matches") => {
+                let (expr, pat, guard) = mac.parse_body_with(|input: syn::parse::ParseStream| {
+                    let expr: RT = input.parse()?;
+                    input.parse::<syn::Token![,]>()?;
+                    let pat: syn::Pat = input.parse()?;
+                    let guard = if input.peek(syn::Token![if]) {
+                        input.parse::<syn::Token![if]>()?;
+                        Some(input.parse::<RT>()?)
+                    } else {
+                        None
+                    };
+                    Ok((expr, pat, guard))
+                })?;
+
+                let arm = |pat, guard, b| MatchArm {
+                    pat,
+                    guard,
+                    body: box Term::Lit { lit: Literal::Bool(b) },
+                };
+                let guard = guard.map(|g| Term::from_syn(res, g)).transpose()?.map(Box::new);
+                Ok(Match {
+                    expr: box Term::from_syn(res, expr)?,
+                    arms: vec![
+                        arm(Pattern::from_syn(res, pat)?, guard, true),
+                        arm(Pattern::Wild, None, false),
+                    ],
+                })
+            }
             t => unimplemented!("{:?}", t),
         }
     }
@@ -189,15 +272,18 @@ impl Type {
                 match name {
                     Name::Ident(name) => match &name[..] {
                         "bool" => Ok(Type::Lit(Boolean)),
+                        "char" => Ok(Type::Lit(Char)),
                         "u8" => Ok(Type::Lit(Unsigned(Eight))),
                         "u16" => Ok(Type::Lit(Unsigned(Sixteen))),
                         "u32" => Ok(Type::Lit(Unsigned(ThirtyTwo))),
                         "u64" => Ok(Type::Lit(Unsigned(SixtyFour))),
+                        "u128" => Ok(Type::Lit(Unsigned(OneTwentyEight))),
                         "usize" => Ok(Type::Lit(Unsigned(Mach))),
                         "i8" => Ok(Type::Lit(Signed(Eight))),
                         "i16" => Ok(Type::Lit(Signed(Sixteen))),
                         "i32" => Ok(Type::Lit(Signed(ThirtyTwo))),
                         "i64" => Ok(Type::Lit(Signed(SixtyFour))),
+                        "i128" => Ok(Type::Lit(Signed(OneTwentyEight))),
                         "f32" => Ok(Type::Lit(Float)),
                         "f64" => Ok(Type::Lit(Double)),
                         _ => Err(Generic),
@@ -295,9 +381,13 @@ impl Pattern {
         match pat {
             // Core
             syn::Pat::Ident(pat) => {
-                if pat.by_ref.is_some() || pat.mutability.is_some() || pat.subpat.is_some() {
+                if pat.by_ref.is_some() || pat.mutability.is_some() {
                     return Err(Generic);
                 }
+                if let Some((_, box subpat)) = pat.subpat {
+                    let name = Ident::from_syn(pat.ident)?;
+                    return Ok(Pattern::Binding { name, pat: box Pattern::from_syn(res, subpat)? });
+                }
 
                 match res.resolve(&[pat.ident.to_string()]) {
                     Some(path) => Ok(Pattern::TupleStruct { path, fields: vec![] }),
@@ -316,7 +406,9 @@ impl Pattern {
 
                 Ok(Pattern::Struct { path: Name::from_syn(res, pat.path)?, fields })
             }
-            // syn::Pat::Tuple(PatTuple {..}) => {}
+            syn::Pat::Tuple(PatTuple { elems, .. }) => Ok(Pattern::Tuple(
+                elems.into_iter().map(|p| Pattern::from_syn(res, p)).collect::<Result<_, _>>()?,
+            )),
             syn::Pat::TupleStruct(PatTupleStruct { path, pat: PatTuple { elems, .. }, .. }) => {
                 let fields = elems
                     .into_iter()
@@ -330,6 +422,24 @@ impl Pattern {
                 expr: box syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Bool(b), .. }),
                 ..
             }) => Ok(Pattern::Boolean(b.value)),
+            syn::Pat::Lit(syn::PatLit {
+                expr: box syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }),
+                ..
+            }) => Ok(Pattern::Lit(int_lit(&lit, false)?)),
+            syn::Pat::Lit(syn::PatLit {
+                expr:
+                    box syn::Expr::Unary(syn::ExprUnary {
+                        op: syn::UnOp::Neg(_),
+                        expr: box syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }),
+                        ..
+                    }),
+                ..
+            }) => Ok(Pattern::Lit(int_lit(&lit, true)?)),
+            syn::Pat::Range(syn::PatRange { box lo, limits, box hi, .. }) => Ok(Pattern::Range {
+                lo: range_bound(lo)?,
+                hi: range_bound(hi)?,
+                inclusive: matches!(limits, syn::RangeLimits::Closed(_)),
+            }),
 
             // Medium or less useful
             syn::Pat::Path(_) | syn::Pat::Or(_) | syn::Pat::Type(_) => Err(Other("medium".into())),
@@ -339,21 +449,62 @@ impl Pattern {
             | syn::Pat::Reference(_)
             | syn::Pat::Box(_)
             | syn::Pat::Slice(_)
-            | syn::Pat::Rest(_)
-            | syn::Pat::Range(_) => Err(Other("hard".into())),
+            | syn::Pat::Rest(_) => Err(Other("hard".into())),
 
             _ => Err(Other(format!("{:?}", pat))),
         }
     }
 }
 
+// Parse an integer literal, negating it when it appears under a unary minus
+fn int_lit(lit: &syn::LitInt, negate: bool) -> Result<Literal, ParseError> {
+    use crate::term::Literal::*;
+    let signed = |ty| -> Result<Literal, ParseError> {
+        let value: i128 = lit.base10_parse()?;
+        Ok(Int(if negate { -value } else { value }, ty))
+    };
+    if negate && lit.suffix().starts_with('u') {
+        return Err(Other("negative unsigned literal".into()));
+    }
+    match lit.suffix() {
+        "u8" => Ok(U8(lit.base10_parse()?)),
+        "u16" => Ok(U16(lit.base10_parse()?)),
+        "u32" => Ok(U32(lit.base10_parse()?)),
+        "u64" => Ok(U64(lit.base10_parse()?)),
+        "u128" => Ok(U128(lit.base10_parse()?)),
+        "usize" => Ok(Usize(lit.base10_parse()?)),
+        "i8" => signed(Some(LitTy::I8)),
+        "i16" => signed(Some(LitTy::I16)),
+        "i32" => signed(Some(LitTy::I32)),
+        "i64" => signed(Some(LitTy::I64)),
+        "i128" => signed(Some(LitTy::I128)),
+        "isize" => signed(Some(LitTy::ISIZE)),
+        _ => signed(None),
+    }
+}
+
+// A bound of a range pattern, an integer literal which may be negated
+fn range_bound(bound: syn::Expr) -> Result<Literal, ParseError> {
+    match bound {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }) => int_lit(&lit, false),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr: box syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }),
+            ..
+        }) => int_lit(&lit, true),
+        _ => Err(Other("range bounds must be integer literals".into())),
+    }
+}
+
 impl MatchArm {
     pub fn from_syn<R: Resolver>(res: &R, arm: syn::TermArm) -> Result<Self, ParseError> {
-        if arm.guard.is_some() {
-            return Err(Other("guards are unsupported".into()));
-        }
+        let guard = match arm.guard {
+            Some((_, box guard)) => Some(box Term::from_syn(res, guard)?),
+            None => None,
+        };
         Ok(MatchArm {
             pat: Pattern::from_syn(res, arm.pat)?,
+            guard,
             body: box Term::from_syn(res, *arm.body)?,
         })
     }
@@ -380,4 +531,172 @@ mod tests {
 
         Term::from_syn(&DummyR, term).unwrap();
     }
+
+    #[test]
+    fn parse_cmp() {
+        struct DummyR;
+        impl super::Resolver for DummyR {
+            fn resolve(&self, p: &[String]) -> Option<Name> {
+                match p {
+                    [_, _, _, cmp] => Some(Name::Path { path: vec![], name: cmp.clone(), id: 0 }),
+                    _ => None,
+                }
+            }
+        }
+        let term = syn::parse_quote! {
+            match a.cmp(&b) {
+                Less => true,
+                _ => false,
+            }
+        };
+
+        match Term::from_syn(&DummyR, term).unwrap() {
+            Term::Match { box expr, .. } => match expr {
+                Term::Call { func, args } => {
+                    assert_eq!(func, Name::Path { path: vec![], name: "cmp".into(), id: 0 });
+                    assert_eq!(args.len(), 2);
+                }
+                t => panic!("expected a call to `cmp`, got {:?}", t),
+            },
+            t => panic!("expected a match, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn parse_signed_literals() {
+        struct DummyR;
+        impl super::Resolver for DummyR {
+            fn resolve(&self, _: &[String]) -> Option<Name> {
+                None
+            }
+        }
+        let lit = |src: &str| match Term::from_syn(&DummyR, syn::parse_str(src).unwrap()) {
+            Ok(Term::Lit { lit }) => lit,
+            t => panic!("expected a literal, got {:?}", t),
+        };
+
+        assert_eq!(lit("5i8"), Literal::Int(5, Some(LitTy::I8)));
+        assert_eq!(lit("5i16"), Literal::Int(5, Some(LitTy::I16)));
+        assert_eq!(lit("5i32"), Literal::Int(5, Some(LitTy::I32)));
+        assert_eq!(lit("5i64"), Literal::Int(5, Some(LitTy::I64)));
+        assert_eq!(lit("5isize"), Literal::Int(5, Some(LitTy::ISIZE)));
+    }
+
+    #[test]
+    fn parse_integer_patterns() {
+        struct DummyR;
+        impl super::Resolver for DummyR {
+            fn resolve(&self, p: &[String]) -> Option<Name> {
+                Some(Name::Ident(p[0].clone()))
+            }
+        }
+        let src = "match x { 0 => true, -1 => false, 5u32 => true, _ => false }";
+        let pats: Vec<_> = match Term::from_syn(&DummyR, syn::parse_str(src).unwrap()) {
+            Ok(Term::Match { arms, .. }) => arms.into_iter().map(|arm| arm.pat).collect(),
+            t => panic!("expected a match, got {:?}", t),
+        };
+
+        assert_eq!(
+            pats,
+            vec![
+                Pattern::Lit(Literal::Int(0, None)),
+                Pattern::Lit(Literal::Int(-1, None)),
+                Pattern::Lit(Literal::U32(5)),
+                Pattern::Wild,
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_binding_range_patterns() {
+        struct DummyR;
+        impl super::Resolver for DummyR {
+            fn resolve(&self, p: &[String]) -> Option<Name> {
+                Some(Name::Ident(p[0].clone()))
+            }
+        }
+        let src = "match x { n @ 0..=9 => true, -5..5 => true, _ => false }";
+        let pats: Vec<_> = match Term::from_syn(&DummyR, syn::parse_str(src).unwrap()) {
+            Ok(Term::Match { arms, .. }) => arms.into_iter().map(|arm| arm.pat).collect(),
+            t => panic!("expected a match, got {:?}", t),
+        };
+
+        let range = |lo, hi, inclusive| Pattern::Range {
+            lo: Literal::Int(lo, None),
+            hi: Literal::Int(hi, None),
+            inclusive,
+        };
+        assert_eq!(
+            pats,
+            vec![
+                Pattern::Binding { name: Ident("n".into()), pat: box range(0, 9, true) },
+                range(-5, 5, false),
+                Pattern::Wild,
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lambda() {
+        struct DummyR;
+        impl super::Resolver for DummyR {
+            fn resolve(&self, _: &[String]) -> Option<Name> {
+                None
+            }
+        }
+        let lambda = |ty| Term::Lambda {
+            args: vec![(Ident("x".into()), ty)],
+            body: box Term::Binary {
+                left: box Term::Variable { path: Name::Ident("x".into()) },
+                op: BinOp::Gt,
+                right: box Term::Lit { lit: Literal::Int(0, None) },
+            },
+        };
+
+        let term = Term::from_syn(&DummyR, syn::parse_quote! { |x| x > 0 }).unwrap();
+        assert_eq!(term, lambda(None));
+
+        // Annotations are kept, so lowering can print them
+        let term = Term::from_syn(&DummyR, syn::parse_quote! { |x: u32| x > 0 }).unwrap();
+        assert_eq!(term, lambda(Some(Type::Lit(LitTy::Unsigned(Size::ThirtyTwo)))));
+    }
+
+    #[test]
+    fn parse_matches() {
+        struct DummyR;
+        impl super::Resolver for DummyR {
+            fn resolve(&self, p: &[String]) -> Option<Name> {
+                match p {
+                    [s] if s == "Some" => Some(Name::Path { path: vec![], name: s.clone(), id: 0 }),
+                    _ => None,
+                }
+            }
+        }
+        let x = || box Term::Variable { path: Name::Ident("x".into()) };
+        let some = Pattern::TupleStruct {
+            path: Name::Path { path: vec![], name: "Some".into(), id: 0 },
+            fields: vec![Pattern::Wild],
+        };
+        let arm = |pat, guard, b| MatchArm {
+            pat,
+            guard,
+            body: box Term::Lit { lit: Literal::Bool(b) },
+        };
+
+        let term = Term::from_syn(&DummyR, syn::parse_quote! { matches!(x, Some(_)) }).unwrap();
+        let expected = Term::Match {
+            expr: x(),
+            arms: vec![arm(some.clone(), None, true), arm(Pattern::Wild, None, false)],
+        };
+        assert_eq!(term, expected);
+
+        let guarded = syn::parse_quote! { matches!(x, Some(_) if b) };
+        let term = Term::from_syn(&DummyR, guarded).unwrap();
+        let guard = Some(box Term::Variable { path: Name::Ident("b".into()) });
+        let expected = Term::Match {
+            expr: x(),
+            arms: vec![arm(some, guard, true), arm(Pattern::Wild, None, false)],
+        };
+        assert_eq!(term, expected);
+    }
 }
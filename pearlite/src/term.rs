@@ -1,3 +1,4 @@
+use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 
 use ena::unify::{EqUnifyValue, UnifyKey};
@@ -5,10 +6,10 @@ use indexmap::IndexMap;
 
 // AST definitions for Creusot specification language expressions
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Ident(pub String);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Name {
     Path {
         path: Vec<String>,
@@ -19,7 +20,7 @@ pub enum Name {
     Ident(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Term {
     Match { expr: Box<Term>, arms: Vec<MatchArm> },
     If { cond: Box<Term>, then_branch: Box<Term>, else_branch: Box<Term> },
@@ -28,11 +29,16 @@ pub enum Term {
     Variable { path: Name },
     Forall { args: Vec<(Ident, Type)>, body: Box<Term> },
     Exists { args: Vec<(Ident, Type)>, body: Box<Term> },
+    /// A closure `|x, y: T| body`, parameters without annotations get their types inferred
+    Lambda { args: Vec<(Ident, Option<Type>)>, body: Box<Term> },
     Tuple { elems: Vec<Term> },
     Let { pat: Pattern, arg: Box<Term>, body: Box<Term> },
     Call { func: Name, args: Vec<Term> },
+    /// A struct literal `S { f: t, .. }`, typing puts the fields in declaration order
+    Struct { path: Name, fields: Vec<(Ident, Term)> },
     Unary { op: UnOp, expr: Box<Term> },
     Cast { expr: Box<Term>, ty: Type },
+    Index { base: Box<Term>, index: Box<Term> },
     Absurd,
 }
 
@@ -42,34 +48,88 @@ impl Term {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Literal {
     U8(u8),
     U16(u16),
     U32(u32),
     U64(u64),
+    U128(u128),
     Usize(usize),
     // TODO make this bigint
-    Int(i128),
+    // An unsuffixed integer, along with the machine type inferred for it.
+    // `None` means the literal is a mathematical `int`.
+    Int(i128, Option<LitTy>),
     F32(f32),
     F64(f64),
     Bool(bool),
+    Char(char),
+    Str(String),
 }
 
-#[derive(Debug)]
+// Floats are compared bitwise so that literals can be used as keys
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        use Literal::*;
+        match (self, other) {
+            (U8(l), U8(r)) => l == r,
+            (U16(l), U16(r)) => l == r,
+            (U32(l), U32(r)) => l == r,
+            (U64(l), U64(r)) => l == r,
+            (U128(l), U128(r)) => l == r,
+            (Usize(l), Usize(r)) => l == r,
+            (Int(l, lt), Int(r, rt)) => l == r && lt == rt,
+            (F32(l), F32(r)) => l.to_bits() == r.to_bits(),
+            (F64(l), F64(r)) => l.to_bits() == r.to_bits(),
+            (Bool(l), Bool(r)) => l == r,
+            (Char(l), Char(r)) => l == r,
+            (Str(l), Str(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Literal {}
+
+impl Hash for Literal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Literal::*;
+        std::mem::discriminant(self).hash(state);
+        match self {
+            U8(u) => u.hash(state),
+            U16(u) => u.hash(state),
+            U32(u) => u.hash(state),
+            U64(u) => u.hash(state),
+            U128(u) => u.hash(state),
+            Usize(u) => u.hash(state),
+            Int(i, ty) => {
+                i.hash(state);
+                ty.hash(state)
+            }
+            F32(f) => f.to_bits().hash(state),
+            F64(f) => f.to_bits().hash(state),
+            Bool(b) => b.hash(state),
+            Char(c) => c.hash(state),
+            Str(s) => s.hash(state),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DerefKind {
     Box, Ref(RefKind),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UnOp {
     Deref(Option<DerefKind>),
     Final,
+    Old,
     Neg,
     Not,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum BinOp {
     Add,
     Sub,
@@ -84,41 +144,53 @@ pub enum BinOp {
     And,
     Or,
     Impl,
+    // Equality and disequality of booleans, introduced during typing
+    Iff,
+    Xor,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MatchArm {
     pub pat: Pattern,
+    pub guard: Option<Box<Term>>,
     pub body: Box<Term>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Pattern {
     Var(Ident),
     Struct { path: Name, fields: Vec<(Ident, Pattern)> },
     TupleStruct { path: Name, fields: Vec<Pattern> },
+    Tuple(Vec<Pattern>),
     Boolean(bool),
+    // An integer literal, possibly negative
+    Lit(Literal),
+    // An integer range, `lo..hi` or `lo..=hi`
+    Range { lo: Literal, hi: Literal, inclusive: bool },
+    // `name @ pat`, which also binds the whole matched value to `name`
+    Binding { name: Ident, pat: Box<Pattern> },
     Wild,
 }
 
 // We only keep around immutable references for debugging purposes
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum RefKind {
     Mut,
     Not,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Size {
     Eight,
     Sixteen,
     ThirtyTwo,
     SixtyFour,
+    OneTwentyEight,
     Unknown,
     Mach,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum LitTy {
     Signed(Size),
     Unsigned(Size),
@@ -126,6 +198,9 @@ pub enum LitTy {
     Float,
     Double,
     Boolean,
+    Char,
+    /// The unsized `str`, string literals have type `&str`
+    Str,
 }
 
 impl LitTy {
@@ -133,12 +208,14 @@ impl LitTy {
     pub const I16: Self = Self::Signed(Size::Sixteen);
     pub const I32: Self = Self::Signed(Size::ThirtyTwo);
     pub const I64: Self = Self::Signed(Size::SixtyFour);
+    pub const I128: Self = Self::Signed(Size::OneTwentyEight);
     pub const ISIZE: Self = Self::Signed(Size::Mach);
 
     pub const U8: Self = Self::Unsigned(Size::Eight);
     pub const U16: Self = Self::Unsigned(Size::Sixteen);
     pub const U32: Self = Self::Unsigned(Size::ThirtyTwo);
     pub const U64: Self = Self::Unsigned(Size::SixtyFour);
+    pub const U128: Self = Self::Unsigned(Size::OneTwentyEight);
     pub const USIZE: Self = Self::Unsigned(Size::Mach);
 }
 
@@ -170,7 +247,7 @@ impl UnifyKey for TyUnk {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Path { path: Name },
     Box { ty: Box<Type> },
@@ -266,3 +343,27 @@ impl Type {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn lit(lit: Literal) -> Term {
+        Term::Binary {
+            left: box Term::Variable { path: Name::Ident("x".into()) },
+            op: BinOp::Add,
+            right: box Term::Lit { lit },
+        }
+    }
+
+    #[test]
+    fn terms_are_structurally_hashed() {
+        let mut seen = HashSet::new();
+        assert!(seen.insert(lit(Literal::F32(0.0))));
+        assert!(!seen.insert(lit(Literal::F32(0.0))));
+        assert!(seen.insert(lit(Literal::F32(-0.0))));
+        assert!(seen.insert(lit(Literal::U32(0))));
+        assert!(seen.insert(lit(Literal::Int(0, None))));
+    }
+}
@@ -4,6 +4,12 @@ use std::collections::HashMap;
 pub trait GlobalContext {
     fn resolve_name(&self, path: &Name) -> Option<Type>;
     fn constructor_type(&self, path: &Name) -> Option<(Vec<Type>, Type)>;
+    fn struct_fields(&self, path: &Name) -> Option<(Vec<(String, Type)>, Type)>;
+
+    /// Whether `path` is `Ord::cmp`, which specifications can only apply to integers
+    fn is_ord_cmp(&self, _: &Name) -> bool {
+        false
+    }
 }
 
 type LocalIdent = String;
@@ -77,6 +83,18 @@ impl<G: GlobalContext> TypeContext<G> {
 
         sub.subst(ty);
     }
+    // Substitute the solution of `ty`, if it is a solved variable, leaving its components as
+    // they are. Unlike zonking, unsolved variables stay unknown.
+    fn shallow_resolve(&mut self, ty: &Type) -> Type {
+        match ty {
+            Type::Unknown(uk) => match self.unif.probe_value(*uk) {
+                Some(t) => self.shallow_resolve(&t),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
     // Substitute in all solved variables
     pub fn zonk(&mut self, ty: &mut Type) {
         use Type::*;
@@ -115,6 +133,17 @@ impl<G: GlobalContext> TypeContext<G> {
         Some((field_tys, ret_ty))
     }
 
+    fn fresh_struct_type(&mut self, path: &Name) -> Option<(Vec<(String, Type)>, Type)> {
+        let (mut field_tys, mut ret_ty) = self.global_ctx.struct_fields(path)?;
+        let var_subst: VarSubst =
+            ret_ty.fvs().into_iter().map(|fv| (fv, self.fresh_ty())).collect();
+
+        field_tys.iter_mut().for_each(|(_, fld)| var_subst.subst(fld));
+        var_subst.subst(&mut ret_ty);
+
+        Some((field_tys, ret_ty))
+    }
+
     fn scope<F, R>(&mut self, scope: F) -> R
     where
         F: FnOnce(&mut Self) -> R,
@@ -135,7 +164,7 @@ impl<G: GlobalContext> TypeContext<G> {
                     Ok(()) => Ok(()),
                 }
             }
-            (Tuple { elems: a }, Tuple { elems: b }) => {
+            (Tuple { elems: a }, Tuple { elems: b }) if a.len() == b.len() => {
                 for (t1, t2) in a.iter().zip(b.iter()) {
                     self.unify(&t1, &t2)?;
                 }
@@ -176,10 +205,16 @@ pub enum TypeError {
     UnificationError(Type, Type),
     UnknownVariable(Name),
     UnknownConstructor(Name),
+    UnknownField(Name, Ident),
+    DuplicateField(Name, Ident),
+    MissingField(Name, Ident),
     NotFunction(Type, Name),
     InvalidDeref(Type),
     NoFuture(Type),
     InvalidOp(BinOp, Type, Type),
+    InvalidCmp(Type),
+    NotIndexable(Type),
+    InvalidIndex(Type),
 }
 
 use ena::unify::InPlaceUnificationTable;
@@ -195,9 +230,12 @@ where
             let scrut_ty = infer_term(ctx, expr)?;
             let body_ty = ctx.fresh_ty();
 
-            for MatchArm { pat, box body } in arms {
+            for MatchArm { pat, guard, box body } in arms {
                 ctx.scope(|ctx| {
                     check_pattern(ctx, pat, &scrut_ty)?;
+                    if let Some(box guard) = guard {
+                        check_term(ctx, guard, &Type::BOOLEAN)?;
+                    }
                     check_term(ctx, body, &body_ty)?;
                     Ok(())
                 })?;
@@ -213,13 +251,28 @@ where
             Ok(ty)
         }
         Binary { left, op, right } => {
-            let mut left_ty = infer_term(ctx, left)?;
-            let mut right_ty = infer_term(ctx, right)?;
+            // Infer the other operand first so an unsuffixed literal can take on its type
+            let (mut left_ty, mut right_ty) = if is_unsuffixed_int(left) {
+                let right_ty = infer_term(ctx, right)?;
+                (infer_with_hint(ctx, left, &right_ty)?, right_ty)
+            } else {
+                let left_ty = infer_term(ctx, left)?;
+                let right_ty = infer_with_hint(ctx, right, &left_ty)?;
+                (left_ty, right_ty)
+            };
             let res_ty = binop_type(ctx, &op, &mut left_ty, &mut right_ty)?;
 
+            // Equality of booleans is logical equivalence
+            if matches!(op, BinOp::Eq | BinOp::Ne) {
+                ctx.zonk(&mut left_ty);
+                if left_ty == Type::BOOLEAN {
+                    *op = if *op == BinOp::Eq { BinOp::Iff } else { BinOp::Xor };
+                }
+            }
+
             Ok(res_ty)
         }
-        Lit { lit } => Ok(Type::Lit(typecheck_lit(lit))),
+        Lit { lit } => Ok(typecheck_lit(lit)),
         Variable { path } => ctx.resolve_name(path).ok_or_else(|| UnknownVariable(path.clone())),
         Tuple { elems } => {
             let elem_tys =
@@ -234,13 +287,54 @@ where
 
             if let Type::Function { args: arg_tys, box res } = fty {
                 for (arg, ty) in args.iter_mut().zip(arg_tys.iter()) {
-                    check_term(ctx, arg, ty)?;
+                    match ty {
+                        Type::Reference { kind: RefKind::Not, box ty } => {
+                            check_ref_arg(ctx, arg, ty)?
+                        }
+                        _ => check_term(ctx, arg, ty)?,
+                    }
+                }
+
+                // Lowering compares the operands of `cmp` with `<` and `=`
+                if ctx.global_ctx.is_ord_cmp(func) {
+                    let mut operand = match &arg_tys[0] {
+                        Type::Reference { box ty, .. } => ty.clone(),
+                        ty => ty.clone(),
+                    };
+                    ctx.zonk(&mut operand);
+                    match operand {
+                        Type::Lit(LitTy::Signed(_))
+                        | Type::Lit(LitTy::Unsigned(_))
+                        | Type::Lit(LitTy::Integer) => {}
+                        _ => return Err(InvalidCmp(operand)),
+                    }
                 }
                 Ok(res)
             } else {
                 Err(NotFunction(fty, func.clone()))
             }
         }
+        Struct { path, fields } => {
+            let (field_tys, ret_ty) =
+                ctx.fresh_struct_type(path).ok_or_else(|| UnknownConstructor(path.clone()))?;
+
+            let position = |id: &Ident| field_tys.iter().position(|(nm, _)| *nm == id.0);
+            for (id, fld) in fields.iter_mut() {
+                let ix = position(id).ok_or_else(|| UnknownField(path.clone(), id.clone()))?;
+                check_term(ctx, fld, &field_tys[ix].1)?;
+            }
+
+            // Lowering builds the value positionally, so every field must be given exactly once
+            fields.sort_by_key(|(id, _)| position(id));
+            if let Some(w) = fields.windows(2).find(|w| w[0].0 == w[1].0) {
+                return Err(DuplicateField(path.clone(), w[0].0.clone()));
+            }
+            let missing = field_tys.iter().find(|(nm, _)| fields.iter().all(|(id, _)| id.0 != *nm));
+            if let Some((nm, _)) = missing {
+                return Err(MissingField(path.clone(), Ident(nm.clone())));
+            }
+            Ok(ret_ty)
+        }
         Let { pat, box arg, box body } => {
             let ty = infer_term(ctx, arg)?;
 
@@ -257,6 +351,17 @@ where
             check_term(ctx, body, &Type::Lit(LitTy::Boolean))?;
             Ok(Type::Lit(LitTy::Boolean))
         }),
+        Lambda { args, box body } => ctx.scope(|ctx| {
+            let mut arg_tys = Vec::new();
+            for (id, ty) in args.iter() {
+                let ty = ty.clone().unwrap_or_else(|| ctx.fresh_ty());
+                ctx.register_var(id, ty.clone());
+                arg_tys.push(ty);
+            }
+
+            let res = infer_term(ctx, body)?;
+            Ok(Type::Function { args: arg_tys, res: box res })
+        }),
         Unary { op: UnOp::Deref(k), box expr } => {
             let mut inner = infer_term(ctx, expr)?;
 
@@ -281,6 +386,7 @@ where
                 _ => Err(NoFuture(inner)),
             }
         }
+        Unary { op: UnOp::Old, box expr } => infer_term(ctx, expr),
         Unary { op: UnOp::Not, box expr } => {
             check_term(ctx, expr, &Type::Lit(LitTy::Boolean))?;
             Ok(Type::Lit(LitTy::Boolean))
@@ -299,6 +405,33 @@ where
             }
         }
         Absurd => Ok(ctx.fresh_ty()),
+        Index { box base, box index } => {
+            let base_ty = infer_term(ctx, base)?;
+            let elem_ty = element_type(ctx, &base_ty).ok_or_else(|| NotIndexable(base_ty))?;
+
+            let index_ty = infer_term(ctx, index)?;
+            match ctx.shallow_resolve(&index_ty) {
+                Type::Lit(LitTy::Signed(_))
+                | Type::Lit(LitTy::Unsigned(_))
+                | Type::Lit(LitTy::Integer) => {}
+                Type::Unknown(_) => ctx.unify(&index_ty, &Type::Lit(LitTy::Integer))?,
+                ty => return Err(InvalidIndex(ty)),
+            }
+            Ok(elem_ty)
+        }
+    }
+}
+
+// Sequences are generic in the type of their elements, which comes first like in `Vec<T, A>`.
+// Indexing sees through references and boxes.
+fn element_type<G>(ctx: &mut TypeContext<G>, ty: &Type) -> Option<Type>
+where
+    G: GlobalContext,
+{
+    match ctx.shallow_resolve(ty) {
+        Type::Reference { box ty, .. } | Type::Box { box ty } => element_type(ctx, &ty),
+        Type::App { args, .. } => args.into_iter().next(),
+        _ => None,
     }
 }
 
@@ -310,11 +443,58 @@ pub fn check_term<G>(
 where
     G: GlobalContext,
 {
-    let inferred = infer_term(ctx, term)?;
+    let inferred = infer_with_hint(ctx, term, expected)?;
     ctx.unify(expected, &inferred)?;
     Ok(())
 }
 
+fn is_unsuffixed_int(term: &Term) -> bool {
+    matches!(term, Term::Lit { lit: Literal::Int(_, None) })
+}
+
+// Infer the type of `term`, giving unsuffixed integer literals the machine type of `hint`.
+fn infer_with_hint<G>(
+    ctx: &mut TypeContext<G>,
+    term: &mut Term,
+    hint: &Type,
+) -> Result<Type, TypeError>
+where
+    G: GlobalContext,
+{
+    if let Term::Lit { lit: Literal::Int(_, width @ None) } = term {
+        let mut hint = hint.clone();
+        ctx.zonk(&mut hint);
+        match hint {
+            Type::Lit(lit @ LitTy::Signed(_)) | Type::Lit(lit @ LitTy::Unsigned(_)) => {
+                *width = Some(lit)
+            }
+            _ => {}
+        }
+    }
+    infer_term(ctx, term)
+}
+
+// Check an argument passed for a parameter of type `&pointee`. Shared references are erased
+// during lowering, so the argument is automatically referenced when it isn't a reference
+// already, the same way method receivers are.
+fn check_ref_arg<G>(
+    ctx: &mut TypeContext<G>,
+    arg: &mut Term,
+    pointee: &Type,
+) -> Result<(), TypeError>
+where
+    G: GlobalContext,
+{
+    let inferred = infer_with_hint(ctx, arg, pointee)?;
+    match inferred {
+        Type::Reference { .. } | Type::Unknown(_) => {
+            let expected = Type::Reference { kind: RefKind::Not, ty: box pointee.clone() };
+            ctx.unify(&expected, &inferred)
+        }
+        _ => ctx.unify(pointee, &inferred),
+    }
+}
+
 fn check_pattern<G>(
     ctx: &mut TypeContext<G>,
     pat: &mut Pattern,
@@ -349,11 +529,49 @@ where
             }
             Ok(())
         }
+        Tuple(pats) => {
+            let elems: Vec<_> = pats.iter().map(|_| ctx.fresh_ty()).collect();
+            ctx.unify(&Type::Tuple { elems: elems.clone() }, expected)?;
+
+            for (pat, ty) in pats.iter_mut().zip(elems.iter()) {
+                check_pattern(ctx, pat, ty)?;
+            }
+            Ok(())
+        }
         Boolean(_) => ctx.unify(&Type::BOOLEAN, expected),
+        Lit(lit) => check_lit_pattern(ctx, lit, expected),
+        Range { lo, hi, .. } => {
+            check_lit_pattern(ctx, lo, expected)?;
+            check_lit_pattern(ctx, hi, expected)
+        }
+        Binding { name, pat } => {
+            ctx.register_var(name, expected.clone());
+            check_pattern(ctx, pat, expected)
+        }
         Wild => Ok(()),
     }
 }
 
+fn check_lit_pattern<G: GlobalContext>(
+    ctx: &mut TypeContext<G>,
+    lit: &mut Literal,
+    expected: &Type,
+) -> Result<(), TypeError> {
+    // Like unsuffixed literal terms, unsuffixed literal patterns take the machine type
+    // of the scrutinee
+    if let Literal::Int(_, width @ None) = lit {
+        let mut hint = expected.clone();
+        ctx.zonk(&mut hint);
+        match hint {
+            Type::Lit(lit @ LitTy::Signed(_)) | Type::Lit(lit @ LitTy::Unsigned(_)) => {
+                *width = Some(lit)
+            }
+            _ => {}
+        }
+    }
+    ctx.unify(&typecheck_lit(lit), expected)
+}
+
 fn binop_type<G: GlobalContext>(
     ctx: &mut TypeContext<G>,
     op: &BinOp,
@@ -379,28 +597,36 @@ fn binop_type<G: GlobalContext>(
                 Err(InvalidOp(*op, left_ty.clone(), right_ty.clone()))
             }
         }
-        And | Or | Impl => {
+        And | Or | Impl | Iff | Xor => {
             ctx.unify(left_ty, &Type::Lit(LitTy::Boolean))?;
             Ok(Type::Lit(LitTy::Boolean))
         }
     }
 }
 
-fn typecheck_lit(lit: &Literal) -> LitTy {
+fn typecheck_lit(lit: &Literal) -> Type {
     use LitTy::*;
     use Literal::*;
 
-    match lit {
+    let lit_ty = match lit {
         U32(_) => Unsigned(Size::ThirtyTwo),
         U64(_) => Unsigned(Size::SixtyFour),
+        U128(_) => Unsigned(Size::OneTwentyEight),
         Usize(_) => Unsigned(Size::Mach),
         U8(_) => Unsigned(Size::Eight),
         U16(_) => Unsigned(Size::Sixteen),
-        Int(_) => Integer,
+        Int(_, Some(ty)) => *ty,
+        Int(_, None) => Integer,
         F32(_) => Float,
         F64(_) => Double,
         Bool(_) => Boolean,
-    }
+        Literal::Char(_) => LitTy::Char,
+        // String literals are borrowed, like in Rust
+        Literal::Str(_) => {
+            return Type::Reference { kind: RefKind::Not, ty: box Type::Lit(LitTy::Str) };
+        }
+    };
+    Type::Lit(lit_ty)
 }
 
 #[cfg(test)]
@@ -417,6 +643,10 @@ mod check {
         fn constructor_type(&self, _: &Name) -> Option<(Vec<Type>, Type)> {
             None
         }
+
+        fn struct_fields(&self, _: &Name) -> Option<(Vec<(String, Type)>, Type)> {
+            None
+        }
     }
     use crate::term::{BinOp::*, LitTy::*, Literal::*, Term::*, Type};
 
@@ -431,6 +661,89 @@ mod check {
         .unwrap();
     }
 
+    #[test]
+    fn test_literal_width() {
+        let mut ctx = TypeContext::new(DummyG);
+        ctx.register_var(&Ident("x".into()), Type::Lit(LitTy::U32));
+
+        let mut t = Binary {
+            left: box Variable { path: Name::Ident("x".into()) },
+            op: Lt,
+            right: box Lit { lit: Int(10, None) },
+        };
+        check_term(&mut ctx, &mut t, &Type::BOOLEAN).unwrap();
+        assert!(matches!(t, Binary { right: box Lit { lit: Int(10, Some(LitTy::U32)) }, .. }));
+
+        let mut t = Lit { lit: Int(1, None) };
+        check_term(&mut ctx, &mut t, &Type::Lit(LitTy::Integer)).unwrap();
+        assert!(matches!(t, Lit { lit: Int(1, None) }));
+    }
+
+    #[test]
+    fn test_boolean_equality() {
+        let mut ctx = TypeContext::new(DummyG);
+        ctx.register_var(&Ident("b".into()), Type::BOOLEAN);
+        let b = || box Variable { path: Name::Ident("b".into()) };
+
+        let mut t = Binary { left: b(), op: Eq, right: box Lit { lit: Bool(true) } };
+        check_term(&mut ctx, &mut t, &Type::BOOLEAN).unwrap();
+        assert!(matches!(t, Binary { op: Iff, .. }));
+
+        let mut t = Binary { left: b(), op: Ne, right: b() };
+        check_term(&mut ctx, &mut t, &Type::BOOLEAN).unwrap();
+        assert!(matches!(t, Binary { op: Xor, .. }));
+
+        let mut t =
+            Binary { left: box Lit { lit: U32(0) }, op: Eq, right: box Lit { lit: U32(1) } };
+        check_term(&mut ctx, &mut t, &Type::BOOLEAN).unwrap();
+        assert!(matches!(t, Binary { op: Eq, .. }));
+    }
+
+    #[test]
+    fn test_deref_kinds() {
+        let mut ctx = TypeContext::new(DummyG);
+        let int = Type::Lit(LitTy::Integer);
+        let reference = |kind| Type::Reference { kind, ty: box int.clone() };
+        ctx.register_var(&Ident("r".into()), reference(RefKind::Not));
+        ctx.register_var(&Ident("m".into()), reference(RefKind::Mut));
+        ctx.register_var(&Ident("b".into()), Type::Box { ty: box int.clone() });
+
+        let deref = |v: &str| Unary {
+            op: UnOp::Deref(None),
+            expr: box Variable { path: Name::Ident(v.into()) },
+        };
+
+        let mut t = deref("r");
+        check_term(&mut ctx, &mut t, &int).unwrap();
+        assert!(matches!(t, Unary { op: UnOp::Deref(Some(DerefKind::Ref(RefKind::Not))), .. }));
+
+        let mut t = deref("m");
+        check_term(&mut ctx, &mut t, &int).unwrap();
+        assert!(matches!(t, Unary { op: UnOp::Deref(Some(DerefKind::Ref(RefKind::Mut))), .. }));
+
+        let mut t = deref("b");
+        check_term(&mut ctx, &mut t, &int).unwrap();
+        assert!(matches!(t, Unary { op: UnOp::Deref(Some(DerefKind::Box)), .. }));
+    }
+
+    #[test]
+    fn test_lambda() {
+        let mut ctx = TypeContext::new(DummyG);
+        let mut t = Lambda {
+            args: vec![(Ident("x".into()), None)],
+            body: box Binary {
+                left: box Variable { path: Name::Ident("x".into()) },
+                op: Gt,
+                right: box Lit { lit: U32(0) },
+            },
+        };
+
+        let mut ty = infer_term(&mut ctx, &mut t).unwrap();
+        ctx.zonk(&mut ty);
+        let u32 = Type::Lit(LitTy::Unsigned(Size::ThirtyTwo));
+        assert_eq!(ty, Type::Function { args: vec![u32], res: box Type::BOOLEAN });
+    }
+
     #[test]
     fn test_failure() {
         let mut ctx = TypeContext::new(DummyG);
@@ -446,6 +759,245 @@ mod check {
         .is_err());
     }
 
+    #[test]
+    fn test_signed_literal_width() {
+        let mut ctx = TypeContext::new(DummyG);
+        let mut t = Lit { lit: Int(5, Some(LitTy::I16)) };
+        check_term(&mut ctx, &mut t, &Type::Lit(LitTy::I16)).unwrap();
+        assert!(matches!(t, Lit { lit: Int(5, Some(LitTy::I16)) }));
+
+        let mut t = Lit { lit: Int(5, Some(LitTy::I16)) };
+        assert!(check_term(&mut ctx, &mut t, &Type::Lit(LitTy::I32)).is_err());
+    }
+
+    #[test]
+    fn test_integer_patterns() {
+        let mut ctx = TypeContext::new(DummyG);
+        let i32 = Type::Lit(LitTy::I32);
+
+        let mut zero = Pattern::Lit(Int(0, None));
+        check_pattern(&mut ctx, &mut zero, &i32).unwrap();
+        assert_eq!(zero, Pattern::Lit(Int(0, Some(LitTy::I32))));
+
+        let mut minus_one = Pattern::Lit(Int(-1, None));
+        check_pattern(&mut ctx, &mut minus_one, &Type::Lit(LitTy::Integer)).unwrap();
+        assert_eq!(minus_one, Pattern::Lit(Int(-1, None)));
+
+        let mut five = Pattern::Lit(U32(5));
+        check_pattern(&mut ctx, &mut five, &Type::Lit(LitTy::U32)).unwrap();
+        assert!(check_pattern(&mut ctx, &mut five, &i32).is_err());
+    }
+
+    #[test]
+    fn test_128_bit_literals() {
+        let mut ctx = TypeContext::new(DummyG);
+        let mut t = Lit { lit: U128(u128::MAX) };
+        check_term(&mut ctx, &mut t, &Type::Lit(LitTy::U128)).unwrap();
+
+        ctx.register_var(&Ident("x".into()), Type::Lit(LitTy::I128));
+        let mut t = Binary {
+            left: box Variable { path: Name::Ident("x".into()) },
+            op: Lt,
+            right: box Lit { lit: Int(-1, None) },
+        };
+        check_term(&mut ctx, &mut t, &Type::BOOLEAN).unwrap();
+        assert!(matches!(t, Binary { right: box Lit { lit: Int(-1, Some(LitTy::I128)) }, .. }));
+    }
+
+    #[test]
+    fn test_nested_tuple_pattern() {
+        let mut ctx = TypeContext::new(DummyG);
+        let pair = Type::Tuple { elems: vec![Type::BOOLEAN, Type::Lit(LitTy::U32)] };
+        ctx.register_var(
+            &Ident("x".into()),
+            Type::Tuple { elems: vec![Type::Lit(LitTy::I8), pair] },
+        );
+
+        let var = |v: &str| Pattern::Var(Ident(v.into()));
+        let mut t = Let {
+            pat: Pattern::Tuple(vec![var("a"), Pattern::Tuple(vec![var("b"), var("c")])]),
+            arg: box Variable { path: Name::Ident("x".into()) },
+            body: box Variable { path: Name::Ident("b".into()) },
+        };
+        let mut ty = infer_term(&mut ctx, &mut t).unwrap();
+        ctx.zonk(&mut ty);
+        assert_eq!(ty, Type::BOOLEAN);
+
+        let mut t = Let {
+            pat: Pattern::Tuple(vec![var("a"), var("b"), var("c")]),
+            arg: box Variable { path: Name::Ident("x".into()) },
+            body: box Lit { lit: Bool(true) },
+        };
+        assert!(infer_term(&mut ctx, &mut t).is_err());
+    }
+
+    #[test]
+    fn test_binding_range_pattern() {
+        let mut ctx = TypeContext::new(DummyG);
+        let i32 = Type::Lit(LitTy::I32);
+
+        let mut digit = Pattern::Binding {
+            name: Ident("n".into()),
+            pat: box Pattern::Range { lo: Int(0, None), hi: Int(9, None), inclusive: true },
+        };
+        check_pattern(&mut ctx, &mut digit, &i32).unwrap();
+        let range = Pattern::Range {
+            lo: Int(0, Some(LitTy::I32)),
+            hi: Int(9, Some(LitTy::I32)),
+            inclusive: true,
+        };
+        assert_eq!(digit, Pattern::Binding { name: Ident("n".into()), pat: box range });
+
+        let mut n = Variable { path: Name::Ident("n".into()) };
+        let mut ty = infer_term(&mut ctx, &mut n).unwrap();
+        ctx.zonk(&mut ty);
+        assert_eq!(ty, i32);
+
+        let mut mixed = Pattern::Range { lo: U32(0), hi: Int(9, None), inclusive: false };
+        assert!(check_pattern(&mut ctx, &mut mixed, &i32).is_err());
+    }
+
+    #[test]
+    fn test_struct_fields() {
+        struct PointG;
+        impl GlobalContext for PointG {
+            fn resolve_name(&self, _: &Name) -> Option<Type> {
+                None
+            }
+
+            fn constructor_type(&self, _: &Name) -> Option<(Vec<Type>, Type)> {
+                None
+            }
+
+            fn struct_fields(&self, _: &Name) -> Option<(Vec<(String, Type)>, Type)> {
+                let fields = vec![("x".into(), Type::Lit(LitTy::U32)), ("y".into(), Type::BOOLEAN)];
+                Some((fields, Type::Path { path: Name::Ident("Point".into()) }))
+            }
+        }
+
+        let point = |fields: Vec<(&str, Term)>| Struct {
+            path: Name::Ident("Point".into()),
+            fields: fields.into_iter().map(|(f, t)| (Ident(f.into()), t)).collect(),
+        };
+        let x = || Lit { lit: Int(1, None) };
+        let y = || Lit { lit: Bool(true) };
+
+        let mut t = point(vec![("y", y()), ("x", x())]);
+        infer_term(&mut TypeContext::new(PointG), &mut t).unwrap();
+        let names = match t {
+            Struct { fields, .. } => fields.into_iter().map(|(id, _)| id.0).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        assert_eq!(names, vec!["x", "y"]);
+
+        let mut t = point(vec![("x", x())]);
+        assert_eq!(
+            infer_term(&mut TypeContext::new(PointG), &mut t),
+            Err(MissingField(Name::Ident("Point".into()), Ident("y".into())))
+        );
+
+        let mut t = point(vec![("x", x()), ("y", y()), ("x", x())]);
+        assert_eq!(
+            infer_term(&mut TypeContext::new(PointG), &mut t),
+            Err(DuplicateField(Name::Ident("Point".into()), Ident("x".into())))
+        );
+    }
+
+    #[test]
+    fn test_call_arguments() {
+        struct CmpG;
+        impl GlobalContext for CmpG {
+            fn resolve_name(&self, path: &Name) -> Option<Type> {
+                let by_ref = |ty| Type::Reference { kind: RefKind::Not, ty: box ty };
+                match path {
+                    Name::Ident(f) if f == "cmp" => Some(Type::Function {
+                        args: vec![by_ref(Type::Var(TyVar(0))), by_ref(Type::Var(TyVar(0)))],
+                        res: box Type::Path { path: Name::Ident("Ordering".into()) },
+                    }),
+                    Name::Ident(f) if f == "is_zero" => Some(Type::Function {
+                        args: vec![Type::Lit(LitTy::U32)],
+                        res: box Type::BOOLEAN,
+                    }),
+                    _ => None,
+                }
+            }
+
+            fn constructor_type(&self, _: &Name) -> Option<(Vec<Type>, Type)> {
+                None
+            }
+
+            fn struct_fields(&self, _: &Name) -> Option<(Vec<(String, Type)>, Type)> {
+                None
+            }
+
+            fn is_ord_cmp(&self, path: &Name) -> bool {
+                *path == Name::Ident("cmp".into())
+            }
+        }
+
+        let ctx = || {
+            let r = Type::Reference { kind: RefKind::Not, ty: box Type::Lit(LitTy::U32) };
+            let vars = vec![
+                ("a".into(), Type::Lit(LitTy::U32)),
+                ("b".into(), Type::BOOLEAN),
+                ("r".into(), r),
+            ];
+            TypeContext::new_with_ctx(CmpG, vars)
+        };
+        let call = |f: &str, args| Call { func: Name::Ident(f.into()), args };
+        let var = |x: &str| Variable { path: Name::Ident(x.into()) };
+
+        // Arguments for reference parameters are referenced, literals take the pointee's width
+        let mut t = call("cmp", vec![var("a"), Lit { lit: Int(5, None) }]);
+        infer_term(&mut ctx(), &mut t).unwrap();
+        match t {
+            Call { args, .. } => {
+                assert!(matches!(args[1], Lit { lit: Int(5, Some(LitTy::U32)) }))
+            }
+            _ => unreachable!(),
+        }
+        infer_term(&mut ctx(), &mut call("cmp", vec![var("r"), var("a")])).unwrap();
+
+        // ...but other parameters must be given a value of their type
+        infer_term(&mut ctx(), &mut call("is_zero", vec![var("a")])).unwrap();
+        assert!(infer_term(&mut ctx(), &mut call("is_zero", vec![var("r")])).is_err());
+
+        // `cmp` only compares integers
+        assert_eq!(
+            infer_term(&mut ctx(), &mut call("cmp", vec![var("b"), var("b")])),
+            Err(InvalidCmp(Type::BOOLEAN))
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        let vec = |elem| {
+            let func = box Type::Path { path: Name::Ident("Vec".into()) };
+            Type::App { func, args: vec![elem] }
+        };
+        let vars = || {
+            let r = Type::Reference { kind: RefKind::Not, ty: box vec(Type::BOOLEAN) };
+            vec![
+                ("v".into(), vec(Type::Lit(LitTy::U32))),
+                ("w".into(), vec(vec(Type::Lit(LitTy::U32)))),
+                ("r".into(), r),
+                ("i".into(), Type::Lit(LitTy::USIZE)),
+            ]
+        };
+        let var = |x: &str| Variable { path: Name::Ident(x.into()) };
+        let index = |base, index| Index { base: box base, index: box index };
+        let infer = |mut t| infer_term(&mut TypeContext::new_with_ctx(DummyG, vars()), &mut t);
+
+        // Machine integers and unsuffixed literals can be used as indices
+        assert_eq!(infer(index(var("v"), var("i"))), Ok(Type::Lit(LitTy::U32)));
+        assert_eq!(infer(index(var("r"), Lit { lit: Int(0, None) })), Ok(Type::BOOLEAN));
+        assert_eq!(infer(index(index(var("w"), var("i")), var("i"))), Ok(Type::Lit(LitTy::U32)));
+
+        let by_bool = index(var("v"), Lit { lit: Bool(true) });
+        assert_eq!(infer(by_bool), Err(InvalidIndex(Type::BOOLEAN)));
+        assert_eq!(infer(index(var("i"), var("i"))), Err(NotIndexable(Type::Lit(LitTy::USIZE))));
+    }
+
     #[test]
     fn test_tuple() {
         let mut ctx = TypeContext::new(DummyG);
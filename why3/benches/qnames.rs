@@ -0,0 +1,45 @@
+#![feature(test)]
+extern crate test;
+
+use test::{black_box, Bencher};
+use why3::mlcfg::QName;
+
+// The names of a large module: every item of a few nested scopes, each named many times over
+const ITEMS: usize = 1000;
+const USES: usize = 10;
+
+fn segments(i: usize) -> (Vec<String>, Vec<String>) {
+    let module = vec!["Crate".to_string(), format!("Module{}", i % 10)];
+    let name = vec![format!("item_{}", i), "inner".to_string()];
+    (module, name)
+}
+
+#[bench]
+fn build(b: &mut Bencher) {
+    b.iter(|| {
+        (0..ITEMS)
+            .map(|i| {
+                let (module, name) = segments(i);
+                QName::new(module, name)
+            })
+            .collect::<Vec<_>>()
+    });
+}
+
+#[bench]
+fn clone(b: &mut Bencher) {
+    let names: Vec<_> = (0..ITEMS)
+        .map(|i| {
+            let (module, name) = segments(i);
+            QName::new(module, name)
+        })
+        .collect();
+    b.iter(|| black_box(&names).iter().flat_map(|n| (0..USES).map(move |_| n.clone())).count());
+}
+
+// The same names as owned segments, as they were stored before being shared
+#[bench]
+fn clone_unshared(b: &mut Bencher) {
+    let names: Vec<_> = (0..ITEMS).map(segments).collect();
+    b.iter(|| black_box(&names).iter().flat_map(|n| (0..USES).map(move |_| n.clone())).count());
+}
@@ -1,43 +1,287 @@
 use std::collections::HashSet;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
+pub mod imports;
 pub mod printer;
 
 pub fn drop_fix() -> QName {
-    QName { module: vec![], name: vec!["drop_fix".into()] }
+    QName::from("drop_fix")
 }
 pub fn drop_uint() -> QName {
-    QName { module: vec![], name: vec!["drop_uint".into()] }
+    QName::from("drop_uint")
 }
 pub fn drop_int() -> QName {
-    QName { module: vec![], name: vec!["drop_int".into()] }
+    QName::from("drop_int")
 }
 pub fn drop_float() -> QName {
-    QName { module: vec![], name: vec!["drop_float".into()] }
+    QName::from("drop_float")
 }
 pub fn drop_bool() -> QName {
-    QName { module: vec![], name: vec!["drop_bool".into()] }
+    QName::from("drop_bool")
 }
 pub fn drop_mut_ref() -> QName {
-    QName { module: vec![], name: vec!["drop_mut_ref".into()] }
+    QName::from("drop_mut_ref")
 }
 pub fn drop_ref() -> QName {
-    QName { module: vec![], name: vec!["drop_ref".into()] }
+    QName::from("drop_ref")
 }
 
 #[derive(Default)]
 pub struct Module {
+    pub name: String,
     pub decls: Vec<Decl>,
 }
 
+/// Sort type declarations so that each comes after the types it uses. Mutually recursive
+/// declarations are grouped together, and must be declared jointly using `with`.
+pub fn sort_ty_decls(decls: Vec<TyDecl>) -> Vec<Vec<TyDecl>> {
+    let groups = ty_decl_sccs(&decls.iter().collect::<Vec<_>>());
+    let mut decls: Vec<_> = decls.into_iter().map(Some).collect();
+    groups
+        .into_iter()
+        .map(|group| group.into_iter().map(|ix| decls[ix].take().unwrap()).collect())
+        .collect()
+}
+
+// The strongly connected components of the dependency graph between `decls`, as indices into
+// `decls`. Components are listed after the ones they depend on.
+fn ty_decl_sccs(decls: &[&TyDecl]) -> Vec<Vec<usize>> {
+    use petgraph::algo::tarjan_scc;
+    use petgraph::graphmap::DiGraphMap;
+
+    let positions: HashMap<_, _> =
+        decls.iter().enumerate().map(|(ix, decl)| (&decl.ty_name, ix)).collect();
+
+    let mut graph = DiGraphMap::<usize, ()>::new();
+    for (ix, decl) in decls.iter().enumerate() {
+        graph.add_node(ix);
+        let mut deps: Vec<_> =
+            decl.used_types().iter().filter_map(|ty| positions.get(ty).copied()).collect();
+        deps.sort_unstable();
+        for dep in deps {
+            graph.add_edge(ix, dep, ());
+        }
+    }
+
+    // The components come in postorder, so dependencies first
+    let mut sccs = tarjan_scc(&graph);
+    sccs.iter_mut().for_each(|scc| scc.sort_unstable());
+    sccs
+}
+
 pub enum Decl {
     FunDecl(Function),
     LogicDecl(Logic),
-    // TyDecl(TyDecl),
+    // A program function whose body is a single expression rather than a CFG
+    LetFun(Logic),
+    ValDecl(ValDecl),
+    // A `meta` directive, used to pass hints to Why3
+    Meta { name: String, args: Vec<MetaArg> },
+    // A statement Why3 has to prove, which later goals may then use
+    Lemma { name: QName, body: Exp },
+    TyDecl(TyDecl),
     // PredDecl(Predicate),
 }
 
+/// An argument of a `meta` directive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaArg {
+    String(String),
+    /// Printed as is, like `prop lemma_name` or `type t`
+    Ident(String),
+    Integer(i128),
+}
+
+impl MetaArg {
+    fn parse(src: &str) -> Option<Self> {
+        if src.len() >= 2 && src.starts_with('"') && src.ends_with('"') {
+            let s = &src[1..src.len() - 1];
+            if s.contains('"') {
+                None
+            } else {
+                Some(MetaArg::String(s.to_owned()))
+            }
+        } else if src.contains('"') {
+            None
+        } else if let Ok(i) = src.parse() {
+            Some(MetaArg::Integer(i))
+        } else if !src.is_empty() {
+            Some(MetaArg::Ident(src.to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+// Split the arguments of a `meta` directive at the commas outside of strings, or `None` if one of
+// the strings isn't closed
+fn split_meta_args(src: &str) -> Option<Vec<&str>> {
+    let (mut args, mut start, mut quoted) = (Vec::new(), 0, false);
+    for (i, c) in src.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                args.push(src[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return None;
+    }
+    args.push(src[start..].trim());
+    Some(args)
+}
+
+impl Decl {
+    /// Parse a `meta` directive written as its quoted name followed by comma separated
+    /// arguments, like `"compute_max_steps" 1000000`
+    pub fn parse_meta(src: &str) -> Option<Self> {
+        let src = src.trim().strip_prefix('"')?;
+        let end = src.find('"')?;
+        let rest = src[end + 1..].trim();
+        let args = if rest.is_empty() {
+            Vec::new()
+        } else {
+            split_meta_args(rest)?.into_iter().map(MetaArg::parse).collect::<Option<_>>()?
+        };
+        Some(Decl::Meta { name: src[..end].to_owned(), args })
+    }
+
+    /// Check the raw Why3 in the `Verbatim` expressions of this declaration for obvious
+    /// syntax errors, like unbalanced parentheses or a `match` without an `end`
+    pub fn check_verbatims(&self) -> Result<(), MalformedVerbatim> {
+        let (item, exps): (_, Vec<&Exp>) = match self {
+            Decl::FunDecl(fun) => {
+                let mut exps = contract_exps(&fun.contract);
+                exps.extend(fun.invariants.values().flatten().map(|(_, inv)| inv));
+                for block in fun.blocks.values() {
+                    for stmt in &block.statements {
+                        match stmt {
+                            Statement::Assign { rhs, .. } => exps.push(rhs),
+                            Statement::Call { args, .. } => exps.extend(args),
+                            Statement::Invariant { exp, .. } | Statement::Assume(exp) => {
+                                exps.push(exp)
+                            }
+                            Statement::Assert { goal, by } => {
+                                exps.push(goal);
+                                exps.extend(by)
+                            }
+                        }
+                    }
+                    exps.extend(block.terminator.scrutinees());
+                }
+                (&fun.name, exps)
+            }
+            Decl::LogicDecl(logic) | Decl::LetFun(logic) => {
+                let body = std::iter::once(&logic.body);
+                (&logic.name, body.chain(contract_exps(&logic.contract)).collect())
+            }
+            Decl::ValDecl(val) => (&val.name, contract_exps(&val.contract)),
+            Decl::Lemma { name, body } => (name, vec![body]),
+            Decl::Meta { .. } | Decl::TyDecl(_) => return Ok(()),
+        };
+
+        let mut verbatims = Vec::new();
+        exps.into_iter().for_each(|e| e.collect_verbatims(&mut verbatims));
+        for verbatim in verbatims {
+            check_verbatim(verbatim).map_err(|problem| MalformedVerbatim {
+                item: item.name(),
+                verbatim: verbatim.to_owned(),
+                problem,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn contract_exps(contract: &Contract) -> Vec<&Exp> {
+    contract.requires.iter().chain(&contract.ensures).chain(&contract.variant).collect()
+}
+
+// Lightweight syntactic check of raw Why3: brackets and the `begin` or `match` blocks closed by
+// `end` must be balanced. Comments, which nest, and string literals are skipped.
+fn check_verbatim(src: &str) -> Result<(), String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut open: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            // Comments nest, the comment ends at the `*)` closing its opening `(*`
+            '(' if chars.get(i + 1) == Some(&'*') => {
+                let mut depth = 1;
+                i += 2;
+                while depth > 0 {
+                    if i + 1 >= chars.len() {
+                        return Err("unterminated comment".into());
+                    }
+                    match (chars[i], chars[i + 1]) {
+                        ('(', '*') => depth += 1,
+                        ('*', ')') => depth -= 1,
+                        _ => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    i += 2;
+                }
+                i -= 1;
+            }
+            '"' => {
+                let mut j = i + 1;
+                while j < chars.len() && chars[j] != '"' {
+                    j += if chars[j] == '\\' { 2 } else { 1 };
+                }
+                if j >= chars.len() {
+                    return Err("unterminated string literal".into());
+                }
+                i = j;
+            }
+            c @ '(' | c @ '[' | c @ '{' => open.push(c.to_string()),
+            c @ ')' | c @ ']' | c @ '}' => {
+                let expected = match c {
+                    ')' => "(",
+                    ']' => "[",
+                    _ => "{",
+                };
+                match open.pop() {
+                    Some(o) if o == expected => {}
+                    Some(o) => return Err(format!("`{}` closes `{}`", c, o)),
+                    None => return Err(format!("unmatched `{}`", c)),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+                while i + 1 < chars.len() && is_ident(chars[i + 1]) {
+                    i += 1;
+                }
+                let word: String = chars[start..=i].iter().collect();
+                match &word[..] {
+                    "begin" | "match" => open.push(word),
+                    "end" => match open.pop() {
+                        Some(o) if o == "begin" || o == "match" => {}
+                        Some(o) => return Err(format!("`end` closes `{}`", o)),
+                        None => return Err("unmatched `end`".into()),
+                    },
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    match open.pop() {
+        Some(o) => Err(format!("unclosed `{}`", o)),
+        None => Ok(()),
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Contract {
     pub requires: Vec<Exp>,
@@ -50,6 +294,11 @@ impl Contract {
         Self::default()
     }
 
+    /// Every clause of the contract: requires, then ensures, then the variant
+    pub fn clauses(&self) -> impl Iterator<Item = &Exp> {
+        self.requires.iter().chain(&self.ensures).chain(&self.variant)
+    }
+
     pub fn subst(&mut self, subst: &HashMap<LocalIdent, Exp>) {
         for req in self.requires.iter_mut() {
             req.subst(subst);
@@ -71,6 +320,40 @@ pub struct Logic {
     pub contract: Contract,
 }
 
+impl Logic {
+    /// Whether this function returns a `bool`, so should be declared as a predicate
+    pub fn is_predicate(&self) -> bool {
+        self.retty == Type::Bool
+    }
+
+    /// Whether the body of this function calls the function itself
+    pub fn is_recursive(&self) -> bool {
+        self.body.mentions(&self.name)
+    }
+
+    /// Check that the body and contract only use constructs which are meaningful in logic
+    pub fn check_logic(&self) -> Result<(), ProgramOnlyConstruct> {
+        let contract = &self.contract;
+        let mut exps = std::iter::once(&self.body)
+            .chain(&contract.requires)
+            .chain(&contract.ensures)
+            .chain(&contract.variant);
+        match exps.find_map(|e| e.program_only_construct()) {
+            Some(construct) => Err(ProgramOnlyConstruct { function: self.name.name(), construct }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An abstract function, only known through its contract
+#[derive(Debug)]
+pub struct ValDecl {
+    pub name: QName,
+    pub retty: Type,
+    pub args: Vec<(LocalIdent, Type)>,
+    pub contract: Contract,
+}
+
 #[derive(Debug)]
 pub struct Function {
     pub name: QName,
@@ -78,9 +361,94 @@ pub struct Function {
     pub args: Vec<(LocalIdent, Type)>,
     pub vars: Vec<(LocalIdent, Type)>,
     pub blocks: BTreeMap<BlockId, Block>,
+    /// The labelled invariants of each loop, keyed by the header block of the loop
+    pub invariants: BTreeMap<BlockId, Vec<(String, Exp)>>,
     pub contract: Contract,
 }
 
+impl Function {
+    /// The body of this function as a single expression, following the blocks from `BB0`.
+    /// This is only possible when the block graph is a tree without loop invariants, otherwise
+    /// the function has to be printed as a CFG. A block with
+    /// several predecessors would be copied into each of them, which can blow up exponentially,
+    /// and it can't be shared as it reads the locals assigned on the way there.
+    pub fn structured_body(&self) -> Option<Exp> {
+        if !self.invariants.is_empty() {
+            return None;
+        }
+        let mut preds: HashMap<BlockId, usize> = HashMap::new();
+        for block in self.blocks.values() {
+            for tgt in block.terminator.targets() {
+                *preds.entry(tgt).or_insert(0) += 1;
+            }
+        }
+        if preds.values().any(|n| *n > 1) {
+            return None;
+        }
+        self.structure_block(BlockId(0), &mut Vec::new())
+    }
+
+    /// This function as a declaration with an expression body, if it can be structured
+    pub fn into_structured(self) -> Result<Logic, Self> {
+        match self.structured_body() {
+            Some(body) => Ok(Logic {
+                name: self.name,
+                retty: self.retty,
+                args: self.args,
+                body,
+                contract: self.contract,
+            }),
+            None => Err(self),
+        }
+    }
+
+    fn structure_block(&self, id: BlockId, path: &mut Vec<BlockId>) -> Option<Exp> {
+        // Reaching a block we are still structuring means it is the header of a loop
+        if path.contains(&id) {
+            return None;
+        }
+        let block = self.blocks.get(&id)?;
+
+        path.push(id);
+        let body = self.structure_terminator(&block.terminator, path);
+        path.pop();
+
+        block.statements.iter().rev().try_fold(body?, |body, stmt| {
+            let (lhs, rhs) = match stmt {
+                Statement::Assign { lhs, rhs } => (lhs, rhs.clone()),
+                Statement::Call { dest, func, args } => {
+                    (dest, Exp::Call(box Exp::QVar(func.clone()), args.clone()))
+                }
+                Statement::Assume(assump) => return Some(Exp::Assume(box assump.clone(), box body)),
+                Statement::Assert { goal, by } => {
+                    let (goal, by) = (box goal.clone(), by.clone().map(Box::new));
+                    return Some(Exp::Assert { goal, by, body: box body });
+                }
+                // Invariants only occur in loops, which can't be structured anyway
+                Statement::Invariant { .. } => return None,
+            };
+            Some(Exp::let_(Pattern::VarP(lhs.clone()), rhs, body))
+        })
+    }
+
+    fn structure_terminator(&self, term: &Terminator, path: &mut Vec<BlockId>) -> Option<Exp> {
+        match term {
+            Terminator::Goto(tgt) => self.structure_block(*tgt, path),
+            Terminator::Absurd => Some(Exp::Absurd),
+            Terminator::Return => Some(Exp::Var(LocalIdent::Anon(0, None))),
+            Terminator::Switch(scrut, brs) => {
+                let brs = brs
+                    .iter()
+                    .map(|(pat, tgt)| {
+                        Some((pat.clone(), None, self.structure_terminator(tgt, path)?))
+                    })
+                    .collect::<Option<_>>()?;
+                Some(Exp::Match(box scrut.clone(), brs))
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Predicate {
     pub name: QName,
@@ -94,9 +462,100 @@ pub struct Block {
     pub terminator: Terminator,
 }
 
+impl Block {
+    /// The locals mentioned by this block, including those bound by switch patterns
+    pub fn locals(&self) -> HashSet<LocalIdent> {
+        let mut locals = self.terminator.locals();
+        for stmt in &self.statements {
+            match stmt {
+                Statement::Assign { lhs, rhs } => {
+                    locals.insert(lhs.clone());
+                    locals.extend(rhs.fvs());
+                }
+                Statement::Call { dest, args, .. } => {
+                    locals.insert(dest.clone());
+                    locals.extend(args.iter().flat_map(|a| a.fvs()));
+                }
+                Statement::Invariant { exp, .. } => locals.extend(exp.fvs()),
+                Statement::Assume(exp) => locals.extend(exp.fvs()),
+                Statement::Assert { goal, by } => {
+                    locals.extend(goal.fvs());
+                    locals.extend(by.iter().flat_map(|p| p.fvs()));
+                }
+            }
+        }
+        locals
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub struct BlockId(pub usize);
 
+/// Merge each block into its predecessor when that predecessor unconditionally jumps to it
+/// and is its only predecessor. The `entry` block is never merged away.
+pub fn merge_goto_chains(entry: BlockId, blocks: &mut BTreeMap<BlockId, Block>) {
+    let mut preds: HashMap<BlockId, usize> = HashMap::new();
+    for block in blocks.values() {
+        for tgt in block.terminator.targets() {
+            *preds.entry(tgt).or_insert(0) += 1;
+        }
+    }
+
+    let ids: Vec<_> = blocks.keys().copied().collect();
+    for id in ids {
+        loop {
+            let next = match blocks.get(&id) {
+                Some(Block { terminator: Terminator::Goto(next), .. }) => *next,
+                _ => break,
+            };
+            if next == id || next == entry || preds.get(&next) != Some(&1) {
+                break;
+            }
+            let Block { statements, terminator } = match blocks.remove(&next) {
+                Some(block) => block,
+                None => break,
+            };
+            let block = blocks.get_mut(&id).unwrap();
+            block.statements.extend(statements);
+            block.terminator = terminator;
+        }
+    }
+}
+
+/// Check that `entry` and every block jumped to, including from nested switches, exist
+pub fn validate_cfg(entry: BlockId, blocks: &BTreeMap<BlockId, Block>) -> Result<(), CfgError> {
+    if !blocks.contains_key(&entry) {
+        return Err(CfgError::MissingEntry(entry));
+    }
+    for (id, block) in blocks {
+        let mut targets = block.terminator.targets().into_iter();
+        if let Some(target) = targets.find(|t| !blocks.contains_key(t)) {
+            return Err(CfgError::DanglingTarget { block: *id, target });
+        }
+    }
+    Ok(())
+}
+
+/// Remove the blocks which can't be reached from `entry`
+pub fn prune_unreachable(entry: BlockId, blocks: &mut BTreeMap<BlockId, Block>) {
+    let mut reached = HashSet::new();
+    let mut todo = vec![entry];
+
+    while let Some(id) = todo.pop() {
+        if !reached.insert(id) {
+            continue;
+        }
+        if let Some(block) = blocks.get(&id) {
+            todo.extend(block.terminator.targets());
+        }
+    }
+
+    let unreached: Vec<_> = blocks.keys().filter(|id| !reached.contains(id)).copied().collect();
+    for id in unreached {
+        blocks.remove(&id);
+    }
+}
+
 #[derive(Debug)]
 pub enum Terminator {
     Goto(BlockId),
@@ -105,15 +564,67 @@ pub enum Terminator {
     Switch(Exp, Vec<(Pattern, Terminator)>),
 }
 
+impl Terminator {
+    /// A two-way branch on a boolean condition.
+    pub fn if_(cond: Exp, then: Terminator, else_: Terminator) -> Self {
+        Terminator::Switch(cond, vec![(Pattern::mk_true(), then), (Pattern::mk_false(), else_)])
+    }
+
+    /// The expressions switched on by this terminator, including by nested switches
+    pub fn scrutinees(&self) -> Vec<&Exp> {
+        match self {
+            Terminator::Goto(_) | Terminator::Absurd | Terminator::Return => Vec::new(),
+            Terminator::Switch(scrut, brs) => {
+                let nested = brs.iter().flat_map(|(_, tgt)| tgt.scrutinees());
+                std::iter::once(scrut).chain(nested).collect()
+            }
+        }
+    }
+
+    /// The blocks this terminator can jump to, including through nested switches
+    pub fn targets(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(tgt) => vec![*tgt],
+            Terminator::Absurd | Terminator::Return => Vec::new(),
+            Terminator::Switch(_, brs) => brs.iter().flat_map(|(_, tgt)| tgt.targets()).collect(),
+        }
+    }
+
+    fn locals(&self) -> HashSet<LocalIdent> {
+        match self {
+            Terminator::Goto(_) | Terminator::Absurd | Terminator::Return => HashSet::new(),
+            Terminator::Switch(scrut, brs) => brs
+                .iter()
+                .fold(scrut.fvs(), |acc, (pat, tgt)| &(&acc | &pat.binders()) | &tgt.locals()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     Assign { lhs: LocalIdent, rhs: Exp },
-    Invariant(String, Exp),
+    /// A call to a program function whose result is stored in `dest`
+    Call { dest: LocalIdent, func: QName, args: Vec<Exp> },
+    /// A loop invariant, `id` distinguishes invariants sharing the same `label`
+    Invariant { label: String, id: usize, exp: Exp },
     Assume(Exp),
-    Assert(Exp),
+    /// An assertion, optionally along with a proof hint for it
+    Assert { goal: Exp, by: Option<Exp> },
 }
 
-#[derive(Debug, Clone)]
+impl Statement {
+    /// Short-circuit the program expressions of this statement, see [`Exp::short_circuit`].
+    /// Invariants, assumptions and assertions are logical and keep their plain connectives.
+    pub fn short_circuit(&mut self) {
+        match self {
+            Statement::Assign { rhs, .. } => rhs.short_circuit(),
+            Statement::Call { args, .. } => args.iter_mut().for_each(Exp::short_circuit),
+            Statement::Invariant { .. } | Statement::Assume(_) | Statement::Assert { .. } => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Bool,
     Char,
@@ -131,6 +642,30 @@ impl Type {
         Self::TFun(box ty, box Self::Bool)
     }
 
+    /// Instantiate the type variables bound in `map`, leaving the others as they are
+    pub fn subst(&mut self, map: &HashMap<String, Type>) {
+        use Type::*;
+
+        match self {
+            TVar(v) => {
+                if let Some(ty) = map.get(v) {
+                    *self = ty.clone();
+                }
+            }
+            MutableBorrow(t) => t.subst(map),
+            TApp(f, args) => {
+                f.subst(map);
+                args.iter_mut().for_each(|arg| arg.subst(map));
+            }
+            Tuple(args) => args.iter_mut().for_each(|arg| arg.subst(map)),
+            TFun(a, b) => {
+                a.subst(map);
+                b.subst(map);
+            }
+            Bool | Char | Integer | TConstructor(_) => {}
+        }
+    }
+
     fn complex(&self) -> bool {
         use Type::*;
         !matches!(
@@ -139,7 +674,7 @@ impl Type {
         )
     }
 
-    fn find_used_types(&self, tys: &mut HashSet<QName>) {
+    fn find_used_types(&self, tys: &mut BTreeSet<QName>) {
         use Type::*;
 
         match self {
@@ -163,21 +698,32 @@ impl Type {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TyDecl {
     pub ty_name: QName,
     pub ty_params: Vec<String>,
     pub ty_constructors: Vec<(String, Vec<Type>)>,
+    // When non-empty, the type is declared as a record with these fields instead of
+    // through `ty_constructors`
+    pub ty_fields: Vec<(String, Type)>,
+    // When set, the type is declared as a synonym of this type instead
+    pub ty_alias: Option<Type>,
 }
 
 impl TyDecl {
-    pub fn used_types(&self) -> HashSet<QName> {
-        let mut used = HashSet::new();
+    pub fn used_types(&self) -> BTreeSet<QName> {
+        let mut used = BTreeSet::new();
         for (_, var_decl) in &self.ty_constructors {
             for ty in var_decl {
                 ty.find_used_types(&mut used);
             }
         }
+        for (_, ty) in &self.ty_fields {
+            ty.find_used_types(&mut used);
+        }
+        if let Some(ty) = &self.ty_alias {
+            ty.find_used_types(&mut used);
+        }
         used
     }
 }
@@ -214,7 +760,7 @@ impl Display for LocalIdent {
         match self {
             LocalIdent::Anon(l, n) => {
                 if let Some(n) = n {
-                    write!(f, "{}", n)?;
+                    write!(f, "{}", sanitize_ident(n))?;
                 }
                 write!(f, "_{:?}", l)
             }
@@ -223,16 +769,44 @@ impl Display for LocalIdent {
     }
 }
 
+// Turns an arbitrary human-readable name into a valid Why3 lowercase identifier, names generated
+// by rustc like `{closure#0}` contain characters that Why3 won't accept.
+fn sanitize_ident(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '\'' { c } else { '_' })
+        .collect();
+    let mut name = name.trim_start_matches(|c: char| !c.is_ascii_alphabetic()).to_owned();
+    if let Some(first) = name.get_mut(0..1) {
+        first.make_ascii_lowercase();
+    }
+    name
+}
+
 use itertools::*;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A qualified name. The segments are reference counted so that the many clones made while
+/// lowering share a single allocation rather than copying every segment. Names are ordered
+/// lexicographically by module and then by name, so sets of names iterate deterministically.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct QName {
-    pub module: Vec<String>,
-    // TODO: get rid of the vec here!
-    pub name: Vec<String>,
+    pub module: Rc<[String]>,
+    pub name: Rc<[String]>,
 }
 
 impl QName {
+    pub fn new(
+        module: impl IntoIterator<Item = String>,
+        name: impl IntoIterator<Item = String>,
+    ) -> Self {
+        QName { module: module.into_iter().collect(), name: name.into_iter().collect() }
+    }
+
+    /// The name `name` inside the single module `module`
+    pub fn qualified(module: &str, name: &str) -> Self {
+        QName::new(vec![module.to_string()], vec![name.to_string()])
+    }
+
     pub fn name(&self) -> String {
         format!("{}", self.name.iter().format("_"))
     }
@@ -240,14 +814,20 @@ impl QName {
 
 impl From<&str> for QName {
     fn from(nm: &str) -> Self {
-        QName { module: vec![], name: vec![nm.to_string()] }
+        QName::new(vec![], vec![nm.to_string()])
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BinOp {
+    /// Lazy conjunction on booleans (`&&`), for program code
     And,
+    /// Lazy disjunction on booleans (`||`), for program code
     Or,
+    /// Logical conjunction (`/\`), for specifications
+    LAnd,
+    /// Logical disjunction (`\/`), for specifications
+    LOr,
     Add,
     Sub,
     Mul,
@@ -258,31 +838,54 @@ pub enum BinOp {
     Gt,
     Ge,
     Ne,
+    Iff,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UnOp { Not, Neg }
 
 #[derive(Debug, Clone)]
 pub enum Exp {
     Current(Box<Exp>),
     Final(Box<Exp>),
-    Let { pattern: Pattern, arg: Box<Exp>, body: Box<Exp> },
+    // The value of an expression in the pre-state, in postconditions
+    Old(Box<Exp>),
+    // Ghost bindings are erased by Why3 when extracting code
+    Let { pattern: Pattern, arg: Box<Exp>, body: Box<Exp>, ghost: bool },
     Var(LocalIdent),
     QVar(QName),
+    // The value returned by a function, in its postconditions
+    Result,
     RecUp { record: Box<Exp>, label: String, val: Box<Exp> },
     RecField { record: Box<Exp>, label: String },
+    Record { ctor: QName, fields: Vec<(String, Exp)> },
     Tuple(Vec<Exp>),
+    // Projection of element `idx` out of a tuple with `arity` elements
+    TupleField { tuple: Box<Exp>, idx: usize, arity: usize },
+    // Indexing into a sequence, printed using the `s[i]` notation of Why3's `seq.Seq`
+    Index { base: Box<Exp>, index: Box<Exp> },
     Constructor { ctor: QName, args: Vec<Exp> },
     BorrowMut(Box<Exp>),
     Const(Constant),
     BinaryOp(BinOp, Box<Exp>, Box<Exp>),
     UnaryOp(UnOp, Box<Exp>),
     Call(Box<Exp>, Vec<Exp>),
-    Verbatim(String),
-    // Seq(Box<Exp>, Box<Exp>),
-    Abs(LocalIdent, Box<Exp>),
-    Match(Box<Exp>, Vec<(Pattern, Exp)>),
+    // Raw Why3 source, along with the free variables it is known to mention
+    Verbatim(String, HashSet<LocalIdent>),
+    // Raw Why3 source in which each placeholder token stands for a local, printed under its
+    // current name. Unlike `Verbatim`, renamings are applied to the placeholders themselves.
+    Raw { src: String, subst_points: Vec<(String, LocalIdent)> },
+    // Program expressions assuming or asserting a condition before evaluating a body, printed
+    // as `assume { e }; body`
+    Assume(Box<Exp>, Box<Exp>),
+    Assert { goal: Box<Exp>, by: Option<Box<Exp>>, body: Box<Exp> },
+    // A lambda `fun x -> e`, with an optional annotation `fun (x : t) -> e` of its parameter
+    Abs(LocalIdent, Option<Type>, Box<Exp>),
+    // Each arm is a pattern, an optional guard and its body
+    Match(Box<Exp>, Vec<(Pattern, Option<Exp>, Exp)>),
+
+    /// An expression annotated with a Why3 attribute, printed as `[@attr] e`
+    Attr(String, Box<Exp>),
 
     // Predicates
     Absurd,
@@ -291,14 +894,267 @@ pub enum Exp {
     Exists(Vec<(LocalIdent, Type)>, Box<Exp>),
 }
 
+// Call `$f` on each immediate subexpression of `$exp`, in order. Shared by `Exp::subexps` and
+// `Exp::map_subexprs`, with `$iter` being `iter` or `iter_mut`.
+macro_rules! visit_subexps {
+    ($exp:expr, $f:ident, $iter:ident) => {
+        match $exp {
+            Exp::Current(e) | Exp::Final(e) | Exp::Old(e) | Exp::BorrowMut(e) => $f(e),
+            Exp::Attr(_, e) => $f(e),
+            Exp::UnaryOp(_, e) => $f(e),
+            Exp::RecField { record, .. } => $f(record),
+            Exp::TupleField { tuple, .. } => $f(tuple),
+            Exp::Index { base, index } => {
+                $f(base);
+                $f(index)
+            }
+            Exp::Abs(_, _, e) | Exp::Forall(_, e) | Exp::Exists(_, e) => $f(e),
+            Exp::Let { arg, body, .. } | Exp::Assume(arg, body) => {
+                $f(arg);
+                $f(body)
+            }
+            Exp::Assert { goal, by, body } => {
+                $f(goal);
+                by.$iter().for_each(|p| $f(p));
+                $f(body)
+            }
+            Exp::RecUp { record, val, .. } => {
+                $f(record);
+                $f(val)
+            }
+            Exp::BinaryOp(_, l, r) | Exp::Impl(l, r) => {
+                $f(l);
+                $f(r)
+            }
+            Exp::Record { fields, .. } => fields.$iter().for_each(|(_, e)| $f(e)),
+            Exp::Tuple(args) | Exp::Constructor { args, .. } => args.$iter().for_each(|e| $f(e)),
+            Exp::Call(fun, args) => {
+                $f(fun);
+                args.$iter().for_each(|e| $f(e))
+            }
+            Exp::Match(scrut, brs) => {
+                $f(scrut);
+                for (_, guard, br) in brs {
+                    guard.$iter().for_each(|g| $f(g));
+                    $f(br)
+                }
+            }
+            Exp::Var(_)
+            | Exp::QVar(_)
+            | Exp::Const(_)
+            | Exp::Verbatim(_, _)
+            | Exp::Raw { .. }
+            | Exp::Result
+            | Exp::Absurd => {}
+        }
+    };
+}
+
 impl Exp {
+    pub fn let_(pattern: Pattern, arg: Exp, body: Exp) -> Self {
+        Exp::Let { pattern, arg: box arg, body: box body, ghost: false }
+    }
+
+    /// A binding that only exists for the proof and is erased when Why3 extracts code. Nothing
+    /// in translation binds such a value yet: spec closures become invariants or are erased.
+    pub fn ghost_let(pattern: Pattern, arg: Exp, body: Exp) -> Self {
+        Exp::Let { pattern, arg: box arg, body: box body, ghost: true }
+    }
+
+    pub fn forall(binders: Vec<(LocalIdent, Type)>, body: Exp) -> Self {
+        Exp::Forall(binders, box body)
+    }
+
+    pub fn exists(binders: Vec<(LocalIdent, Type)>, body: Exp) -> Self {
+        Exp::Exists(binders, box body)
+    }
+
+    /// Whether `scrut` was built with the constructor `ctor`, like Rust's `matches!(scrut, C(..))`.
+    /// When `ctor` is the only constructor of its type this is simply `true`. Returns `None`
+    /// when `ctor` isn't a constructor of any of `decls`, since its arity is then unknown.
+    pub fn is_variant(scrut: Exp, ctor: QName, decls: &[&TyDecl]) -> Option<Self> {
+        let name = ctor.name();
+        let decl = decls.iter().find(|d| d.ty_constructors.iter().any(|(c, _)| *c == name))?;
+        if decl.ty_constructors.len() == 1 {
+            return Some(Exp::mk_true());
+        }
+        let arity = decl.ty_constructors.iter().find(|(c, _)| *c == name).unwrap().1.len();
+
+        let pattern = Pattern::ConsP(ctor, vec![Pattern::Wildcard; arity]);
+        Some(Exp::Match(
+            box scrut,
+            vec![(pattern, None, Exp::mk_true()), (Pattern::Wildcard, None, Exp::mk_false())],
+        ))
+    }
+
+    /// Build a `match`, adding a final `| _ -> absurd` arm unless the arms provably cover every
+    /// constructor of the scrutinee's type according to `decls`. This lets exhaustive Rust
+    /// matches be lowered even when the Why3 type isn't known to have the same constructors.
+    pub fn match_or_absurd(
+        scrut: Exp,
+        mut brs: Vec<(Pattern, Option<Exp>, Exp)>,
+        decls: &[&TyDecl],
+    ) -> Self {
+        if !arms_cover_type(&brs, decls) {
+            brs.push((Pattern::Wildcard, None, Exp::Absurd));
+        }
+        Exp::Match(box scrut, brs)
+    }
+
+    /// Bind `pattern` in `body`. Why3 only accepts irrefutable patterns in a `let`, so refutable
+    /// ones are matched on instead, with the other cases being absurd.
+    pub fn let_or_match(pattern: Pattern, arg: Exp, body: Exp, decls: &[&TyDecl]) -> Self {
+        if pattern.is_refutable(decls) {
+            Exp::Match(box arg, vec![(pattern, None, body), (Pattern::Wildcard, None, Exp::Absurd)])
+        } else {
+            Exp::let_(pattern, arg, body)
+        }
+    }
+
     pub fn conj(l: Exp, r: Exp) -> Self {
         Exp::BinaryOp(BinOp::And, box l, box r)
     }
 
+    /// The conjunction of all `exps`, `true` if there are none
+    pub fn conj_all(exps: impl IntoIterator<Item = Exp>) -> Self {
+        let mut exps = exps.into_iter();
+        match exps.next() {
+            Some(first) => exps.fold(first, Exp::conj),
+            None => Exp::mk_true(),
+        }
+    }
+
+    /// The disjunction of all `exps`, `false` if there are none
+    pub fn disj_all(exps: impl IntoIterator<Item = Exp>) -> Self {
+        let mut exps = exps.into_iter();
+        match exps.next() {
+            Some(first) => exps.fold(first, |l, r| Exp::BinaryOp(BinOp::Or, box l, box r)),
+            None => Exp::mk_false(),
+        }
+    }
+
+    /// `self && other`, dropping `true` operands and collapsing to `false` if either side is
+    /// `false`. Chains like `a.and(b).and(c)` build up left-nested conjunctions.
+    pub fn and(self, other: Exp) -> Self {
+        match (self, other) {
+            (Exp::Const(Constant::Other(b)), e) | (e, Exp::Const(Constant::Other(b)))
+                if b == "true" =>
+            {
+                e
+            }
+            (Exp::Const(Constant::Other(b)), _) | (_, Exp::Const(Constant::Other(b)))
+                if b == "false" =>
+            {
+                Exp::mk_false()
+            }
+            (l, r) => Exp::conj(l, r),
+        }
+    }
+
+    /// `self || other`, dropping `false` operands and collapsing to `true` if either side is
+    /// `true`
+    pub fn or(self, other: Exp) -> Self {
+        match (self, other) {
+            (Exp::Const(Constant::Other(b)), e) | (e, Exp::Const(Constant::Other(b)))
+                if b == "false" =>
+            {
+                e
+            }
+            (Exp::Const(Constant::Other(b)), _) | (_, Exp::Const(Constant::Other(b)))
+                if b == "true" =>
+            {
+                Exp::mk_true()
+            }
+            (l, r) => Exp::BinaryOp(BinOp::Or, box l, box r),
+        }
+    }
+
+    /// The implication of `goal` by every hypothesis in `hyps`, nested from the right so that
+    /// `[p1, p2]` gives `p1 -> (p2 -> goal)`. Without hypotheses this is just `goal`.
+    pub fn implies(hyps: impl IntoIterator<Item = Exp>, goal: Exp) -> Self {
+        let hyps: Vec<_> = hyps.into_iter().collect();
+        hyps.into_iter().rev().fold(goal, |goal, hyp| Exp::Impl(box hyp, box goal))
+    }
+
     pub fn mk_true() -> Self {
         Exp::Const(Constant::const_true())
     }
+
+    pub fn mk_false() -> Self {
+        Exp::Const(Constant::const_false())
+    }
+
+    // Negate a boolean expression, folding double negations and constants
+    pub fn not(e: Exp) -> Self {
+        match e {
+            Exp::UnaryOp(UnOp::Not, box e) => e,
+            Exp::Const(Constant::Other(b)) if b == "true" => Exp::mk_false(),
+            Exp::Const(Constant::Other(b)) if b == "false" => Exp::mk_true(),
+            e => Exp::UnaryOp(UnOp::Not, box e),
+        }
+    }
+
+    // Negate a numeric expression, folding double negations
+    pub fn neg(e: Exp) -> Self {
+        match e {
+            Exp::UnaryOp(UnOp::Neg, box e) => e,
+            e => Exp::UnaryOp(UnOp::Neg, box e),
+        }
+    }
+
+    /// A conditional expression, as a `match` on `cond`
+    pub fn if_(cond: Exp, then: Exp, else_: Exp) -> Self {
+        Exp::Match(
+            box cond,
+            vec![(Pattern::mk_true(), None, then), (Pattern::mk_false(), None, else_)],
+        )
+    }
+
+    /// Turn the conjunctions and disjunctions in this expression into conditionals, so that the
+    /// obligations of a right operand are only checked when the left one doesn't decide the
+    /// result, as Rust's `&&` and `||` do. Only meant for program code: in logic the operators
+    /// are already equivalent.
+    pub fn short_circuit(&mut self) {
+        self.map_subexprs(&mut |e| e.short_circuit());
+
+        *self = match std::mem::replace(self, Exp::Absurd) {
+            Exp::BinaryOp(BinOp::And, box l, box r) | Exp::BinaryOp(BinOp::LAnd, box l, box r) => {
+                Exp::if_(l, r, Exp::mk_false())
+            }
+            Exp::BinaryOp(BinOp::Or, box l, box r) | Exp::BinaryOp(BinOp::LOr, box l, box r) => {
+                Exp::if_(l, Exp::mk_true(), r)
+            }
+            e => e,
+        };
+    }
+}
+
+// The substitution to apply under the binders `bound` of a body in which `occurs` tells which
+// variables are free. The bound variables shadow `subst`, and those which would capture a free
+// variable of a substituted expression are renamed to fresh names, which are returned so that
+// the binding sites can be updated.
+fn subst_under(
+    subst: &HashMap<LocalIdent, Exp>,
+    bound: &[LocalIdent],
+    occurs: impl Fn(&LocalIdent) -> bool,
+) -> (HashMap<LocalIdent, Exp>, HashMap<LocalIdent, LocalIdent>) {
+    let mut subst: HashMap<_, _> = subst
+        .iter()
+        .filter(|(v, _)| !bound.contains(v) && occurs(v))
+        .map(|(v, e)| (v.clone(), e.clone()))
+        .collect();
+    let captured: HashSet<_> = subst.values().flat_map(|e| e.fvs()).collect();
+
+    let mut renamed = HashMap::new();
+    for b in bound.iter().filter(|b| captured.contains(b)) {
+        let fresh = (0..)
+            .map(|n| LocalIdent::from(format!("{}'{}", b, n)))
+            .find(|v| !captured.contains(v) && !bound.contains(v) && !occurs(v))
+            .unwrap();
+        subst.insert(b.clone(), Exp::Var(fresh.clone()));
+        renamed.insert(b.clone(), fresh);
+    }
+    (subst, renamed)
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -307,9 +1163,12 @@ enum Precedence {
     Any,
     Let,
     Assign,
+    Iff,
     Impl,
     Or,
     And,
+    // Why3's `not` binds tighter than the logical connectives but looser than comparisons
+    Not,
     Compare,
     AddSub,
     Mul,
@@ -324,43 +1183,55 @@ impl Exp {
 
         match self {
             Exp::Current(_) => PrefixOp,
+            Exp::Old(_) => PrefixOp,
             Exp::Final(_) => PrefixOp,
             Exp::Let { .. } => Let,
-            Exp::Abs(_, _) => Let,
+            Exp::Assume(..) | Exp::Assert { .. } => Let,
+            Exp::Abs(..) => Let,
             Exp::Var(_) => Closed,
             Exp::QVar(_) => Closed,
             Exp::RecUp { .. } => Term,
             Exp::RecField { .. } => Any,
+            Exp::Record { .. } => Closed,
             Exp::Tuple(_) => Closed,
+            Exp::TupleField { .. } => Let,
+            // Indexing binds tighter than application
+            Exp::Index { .. } => Closed,
             Exp::Constructor { .. } => Term,
             // Exp::Seq(_, _) => { Term }
-            Exp::Match(_, _) => Term,
+            // Like `let`, a `match` extends as far right as possible
+            Exp::Match(_, _) => Let,
             Exp::BorrowMut(_) => Term,
             Exp::Const(_) => Closed,
             Exp::UnaryOp(UnOp::Neg, _) => PrefixOp,
-            Exp::UnaryOp(UnOp::Not, _) => Call,
+            Exp::UnaryOp(UnOp::Not, _) => Not,
             Exp::BinaryOp(op, _, _) => {
                 match op {
-                    BinOp::And => And,
-                    BinOp::Or => Or,
+                    BinOp::And | BinOp::LAnd => And,
+                    BinOp::Or | BinOp::LOr => Or,
                     BinOp::Add => AddSub,
                     BinOp::Sub => AddSub,
                     BinOp::Mul => Mul,
-                    BinOp::Div => Term,
+                    BinOp::Div => Mul,
                     BinOp::Eq => Compare,
                     BinOp::Lt => Compare,
                     BinOp::Le => Compare,
                     BinOp::Ne => Compare,
                     BinOp::Ge => Compare,
                     BinOp::Gt => Compare,
+                    BinOp::Iff => Iff,
                 }
             }
             Exp::Call(_, _) => Call,
-            Exp::Verbatim(_) => Any,
+            Exp::Verbatim(_, _) => Any,
+            Exp::Raw { .. } => Any,
             Exp::Impl(_, _) => Impl,
             Exp::Forall(_, _) => Any,
             Exp::Exists(_, _) => Any,
+            // Like `let`, an attribute applies to everything on its right
+            Exp::Attr(_, _) => Let,
             Exp::Absurd => Closed,
+            Exp::Result => Closed,
         }
     }
 
@@ -368,7 +1239,8 @@ impl Exp {
         match self {
             Exp::Current(e) => e.fvs(),
             Exp::Final(e) => e.fvs(),
-            Exp::Let { pattern, arg, body } => {
+            Exp::Old(e) => e.fvs(),
+            Exp::Let { pattern, arg, body, .. } => {
                 let bound = pattern.binders();
 
                 &(&body.fvs() - &bound) | &arg.fvs()
@@ -379,22 +1251,69 @@ impl Exp {
                 fvs
             }
             Exp::QVar(_) => HashSet::new(),
-            // Exp::RecUp { record, label, val } => {}
-            // Exp::Tuple(_) => {}
+            Exp::RecUp { record, val, .. } => &record.fvs() | &val.fvs(),
+            Exp::RecField { record, .. } => record.fvs(),
+            Exp::TupleField { tuple, .. } => tuple.fvs(),
+            Exp::Index { base, index } => &base.fvs() | &index.fvs(),
+            Exp::Tuple(elems) => elems.iter().fold(HashSet::new(), |acc, e| &acc | &e.fvs()),
+            Exp::Record { fields, .. } => {
+                fields.iter().fold(HashSet::new(), |acc, (_, v)| &acc | &v.fvs())
+            }
             Exp::Constructor { ctor: _, args } => {
                 args.iter().fold(HashSet::new(), |acc, v| &acc | &v.fvs())
             }
             Exp::Const(_) => HashSet::new(),
             Exp::BinaryOp(_, l, r) => &l.fvs() | &r.fvs(),
+            Exp::UnaryOp(_, e) => e.fvs(),
             Exp::Call(f, args) => args.iter().fold(f.fvs(), |acc, a| &acc | &a.fvs()),
-            Exp::Impl(h, c) => &h.fvs() | &c.fvs(),
-            Exp::Forall(bnds, exp) => bnds.iter().fold(exp.fvs(), |mut acc, (l, _)| {
-                acc.remove(l);
-                acc
+            Exp::Impl(h, c) | Exp::Assume(h, c) => &h.fvs() | &c.fvs(),
+            Exp::Assert { goal, by, body } => {
+                by.iter().fold(&goal.fvs() | &body.fvs(), |acc, p| &acc | &p.fvs())
+            }
+            Exp::Forall(bnds, exp) | Exp::Exists(bnds, exp) => {
+                bnds.iter().fold(exp.fvs(), |mut acc, (l, _)| {
+                    acc.remove(l);
+                    acc
+                })
+            }
+            Exp::BorrowMut(e) | Exp::Attr(_, e) => e.fvs(),
+            Exp::Verbatim(_, fvs) => fvs.clone(),
+            Exp::Raw { subst_points, .. } => subst_points.iter().map(|(_, l)| l.clone()).collect(),
+            Exp::Abs(arg, _, body) => {
+                let mut fvs = body.fvs();
+                fvs.remove(arg);
+                fvs
+            }
+            Exp::Match(scrut, brs) => brs.iter().fold(scrut.fvs(), |acc, (pat, guard, br)| {
+                let arm = guard.iter().fold(br.fvs(), |acc, g| &acc | &g.fvs());
+                &acc | &(&arm - &pat.binders())
             }),
-            Exp::BorrowMut(e) => e.fvs(),
-            Exp::Verbatim(_) => HashSet::new(),
-            _ => unimplemented!(),
+            Exp::Absurd | Exp::Result => HashSet::new(),
+        }
+    }
+
+    /// Whether `v` occurs free in this expression. Unlike checking `fvs`, this stops at the first
+    /// occurrence and doesn't build the set of all free variables.
+    pub fn contains_var(&self, v: &LocalIdent) -> bool {
+        match self {
+            Exp::Var(x) => x == v,
+            Exp::Verbatim(_, fvs) => fvs.contains(v),
+            Exp::Raw { subst_points, .. } => subst_points.iter().any(|(_, l)| l == v),
+            Exp::Let { pattern, arg, body, .. } => {
+                arg.contains_var(v) || (!pattern.binds(v) && body.contains_var(v))
+            }
+            Exp::Abs(x, _, body) => x != v && body.contains_var(v),
+            Exp::Forall(bnds, body) | Exp::Exists(bnds, body) => {
+                bnds.iter().all(|(x, _)| x != v) && body.contains_var(v)
+            }
+            Exp::Match(scrut, brs) => {
+                scrut.contains_var(v)
+                    || brs.iter().any(|(pat, guard, br)| {
+                        !pat.binds(v)
+                            && (guard.iter().any(|g| g.contains_var(v)) || br.contains_var(v))
+                    })
+            }
+            _ => self.subexps().into_iter().any(|e| e.contains_var(v)),
         }
     }
 
@@ -402,14 +1321,12 @@ impl Exp {
         match self {
             Exp::Current(e) => e.subst(subst),
             Exp::Final(e) => e.subst(subst),
-            Exp::Let { pattern, arg, body } => {
+            Exp::Old(e) => e.subst(subst),
+            Exp::Let { pattern, arg, body, .. } => {
                 arg.subst(subst);
-                let mut bound = pattern.binders();
-                let mut subst = subst.clone();
-                bound.drain().for_each(|k| {
-                    subst.remove(&k);
-                });
-
+                let bound: Vec<_> = pattern.binders().into_iter().collect();
+                let (subst, renamed) = subst_under(subst, &bound, |v| body.contains_var(v));
+                pattern.rename_binders(&renamed);
                 body.subst(&subst);
             }
             Exp::Var(v) => {
@@ -424,8 +1341,20 @@ impl Exp {
             Exp::RecField { record, .. } => {
                 record.subst(subst);
             }
-            Exp::Tuple(tuple) => {
-                for t in tuple {
+            Exp::TupleField { tuple, .. } => {
+                tuple.subst(subst);
+            }
+            Exp::Index { base, index } => {
+                base.subst(subst);
+                index.subst(subst);
+            }
+            Exp::Record { fields, .. } => {
+                for (_, f) in fields {
+                    f.subst(subst);
+                }
+            }
+            Exp::Tuple(tuple) => {
+                for t in tuple {
                     t.subst(subst);
                 }
             }
@@ -434,23 +1363,31 @@ impl Exp {
                     a.subst(subst);
                 }
             }
-            Exp::Abs(ident, body) => {
-                let mut subst = subst.clone();
-                subst.remove(ident);
+            Exp::Abs(ident, _, body) => {
+                let bound = [ident.clone()];
+                let (subst, renamed) = subst_under(subst, &bound, |v| body.contains_var(v));
+                if let Some(fresh) = renamed.get(ident) {
+                    *ident = fresh.clone();
+                }
                 body.subst(&subst);
             }
             Exp::Match(box scrut, brs) => {
                 scrut.subst(subst);
 
-                for (pat, br) in brs {
-                    let mut s = subst.clone();
-                    pat.binders().drain().for_each(|b| {
-                        s.remove(&b);
-                    });
+                for (pat, guard, br) in brs {
+                    let bound: Vec<_> = pat.binders().into_iter().collect();
+                    let occurs = |v: &LocalIdent| {
+                        guard.iter().any(|g| g.contains_var(v)) || br.contains_var(v)
+                    };
+                    let (s, renamed) = subst_under(subst, &bound, occurs);
+                    pat.rename_binders(&renamed);
+                    if let Some(guard) = guard {
+                        guard.subst(&s);
+                    }
                     br.subst(&s);
                 }
             }
-            Exp::BorrowMut(e) => e.subst(subst),
+            Exp::BorrowMut(e) | Exp::Attr(_, e) => e.subst(subst),
             Exp::UnaryOp(_, o) => {
                 o.subst(subst);
             }
@@ -458,37 +1395,339 @@ impl Exp {
                 l.subst(subst);
                 r.subst(subst)
             }
-            Exp::Impl(hyp, exp) => {
+            Exp::Impl(hyp, exp) | Exp::Assume(hyp, exp) => {
                 hyp.subst(subst);
                 exp.subst(subst)
             }
-            Exp::Forall(binders, exp) => {
-                let mut subst = subst.clone();
-                binders.iter().for_each(|k| {
-                    subst.remove(&k.0);
-                });
-                exp.subst(&subst);
+            Exp::Assert { goal, by, body } => {
+                goal.subst(subst);
+                by.iter_mut().for_each(|p| p.subst(subst));
+                body.subst(subst)
             }
-            Exp::Exists(binders, exp) => {
-                let mut subst = subst.clone();
-                binders.iter().for_each(|k| {
-                    subst.remove(&k.0);
-                });
+            Exp::Forall(binders, exp) | Exp::Exists(binders, exp) => {
+                let bound: Vec<_> = binders.iter().map(|(b, _)| b.clone()).collect();
+                let (subst, renamed) = subst_under(subst, &bound, |v| exp.contains_var(v));
+                for (b, _) in binders.iter_mut() {
+                    if let Some(fresh) = renamed.get(b) {
+                        *b = fresh.clone();
+                    }
+                }
                 exp.subst(&subst);
             }
-            Exp::Call(_, a) => {
+            Exp::Call(f, a) => {
+                f.subst(subst);
                 for arg in a {
                     arg.subst(subst);
                 }
             }
             Exp::QVar(_) => {}
             Exp::Const(_) => {}
-            Exp::Verbatim(_) => {}
-            Exp::Absurd => {}
+            Exp::Verbatim(_, fvs) => {
+                // We can't rewrite inside raw source, so the substituted variables are bound
+                // around it instead. If a replacement mentions one of them, they are all bound to
+                // fresh names first, so that the bindings don't capture each other.
+                let mut substd: Vec<_> = fvs.iter().filter(|v| subst.contains_key(v)).collect();
+                substd.sort_by_key(|v| v.to_string());
+                let mut avoid: HashSet<_> = fvs.clone();
+                avoid.extend(substd.iter().flat_map(|v| subst[*v].fvs()));
+
+                let mut lets: Vec<(LocalIdent, Exp)> = Vec::new();
+                if substd.iter().any(|v| subst.values().any(|e| e.contains_var(v))) {
+                    let mut renames = Vec::new();
+                    for v in substd {
+                        let fresh = (0..)
+                            .map(|n| LocalIdent::from(format!("{}'{}", v, n)))
+                            .find(|f| !avoid.contains(f) && lets.iter().all(|(l, _)| l != f))
+                            .unwrap();
+                        lets.push((fresh.clone(), subst[v].clone()));
+                        renames.push((v.clone(), Exp::Var(fresh)));
+                    }
+                    lets.extend(renames);
+                } else {
+                    lets.extend(substd.into_iter().map(|v| (v.clone(), subst[v].clone())));
+                }
+
+                for (v, arg) in lets.into_iter().rev() {
+                    let verb = std::mem::replace(self, Exp::Absurd);
+                    *self = Exp::let_(Pattern::VarP(v), arg, verb);
+                }
+            }
+            Exp::Raw { subst_points, .. } => {
+                // Renamings are applied directly to the placeholders. Other replacements are bound
+                // around the fragment to fresh names, which the placeholders are pointed at. The
+                // fresh names are free in no replacement, so the bindings can't capture anything.
+                let mut avoid: HashSet<LocalIdent> =
+                    subst_points.iter().map(|(_, l)| l.clone()).collect();
+                for (_, local) in subst_points.iter() {
+                    avoid.extend(subst.get(local).into_iter().flat_map(|e| e.fvs()));
+                }
+
+                let mut bound: Vec<(LocalIdent, LocalIdent, Exp)> = Vec::new();
+                for (_, local) in subst_points.iter_mut() {
+                    match subst.get(local) {
+                        Some(Exp::Var(v)) => *local = v.clone(),
+                        Some(e) => match bound.iter().find(|(l, _, _)| l == local) {
+                            Some((_, fresh, _)) => *local = fresh.clone(),
+                            None => {
+                                let fresh = (0..)
+                                    .map(|n| LocalIdent::from(format!("{}'{}", local, n)))
+                                    .find(|v| !avoid.contains(v))
+                                    .unwrap();
+                                avoid.insert(fresh.clone());
+                                bound.push((local.clone(), fresh.clone(), e.clone()));
+                                *local = fresh;
+                            }
+                        },
+                        None => {}
+                    }
+                }
+
+                for (_, fresh, arg) in bound.into_iter().rev() {
+                    let raw = std::mem::replace(self, Exp::Absurd);
+                    *self = Exp::let_(Pattern::VarP(fresh), arg, raw);
+                }
+            }
+            Exp::Absurd | Exp::Result => {}
+        }
+    }
+
+    // Rename the free occurrences of `from` to `to`, renaming binders which would capture `to`
+    pub fn rename(&mut self, from: &LocalIdent, to: LocalIdent) {
+        if !self.contains_var(from) {
+            return;
+        }
+
+        let mut subst = HashMap::new();
+        subst.insert(from.clone(), Exp::Var(to));
+        self.subst(&subst);
+    }
+
+    /// Replace the global names in `map`, wherever they are used as variables, call targets,
+    /// constructors or in patterns. Local variables are left alone.
+    pub fn subst_qname(&mut self, map: &HashMap<QName, QName>) {
+        match self {
+            Exp::QVar(qn) | Exp::Constructor { ctor: qn, .. } | Exp::Record { ctor: qn, .. } => {
+                if let Some(new) = map.get(qn) {
+                    *qn = new.clone();
+                }
+            }
+            Exp::Match(_, brs) => brs.iter_mut().for_each(|(pat, _, _)| pat.subst_qname(map)),
+            Exp::Let { pattern, .. } => pattern.subst_qname(map),
+            _ => {}
+        }
+        self.map_subexprs(&mut |e| e.subst_qname(map));
+    }
+
+    /// Structural equality up to the renaming of bound variables, free variables must have the
+    /// same names on both sides.
+    pub fn alpha_eq(&self, other: &Exp) -> bool {
+        self.alpha_eq_in(other, &mut Vec::new())
+    }
+
+    // `env` pairs up the binders in scope on each side, innermost last
+    fn alpha_eq_in(&self, other: &Exp, env: &mut Vec<(LocalIdent, LocalIdent)>) -> bool {
+        use Exp::*;
+        let all = |l: &[Exp], r: &[Exp], env: &mut Vec<_>| {
+            l.len() == r.len() && l.iter().zip(r).all(|(l, r)| l.alpha_eq_in(r, env))
+        };
+
+        match (self, other) {
+            (Current(l), Current(r))
+            | (Final(l), Final(r))
+            | (Old(l), Old(r))
+            | (BorrowMut(l), BorrowMut(r)) => l.alpha_eq_in(r, env),
+            (
+                Let { pattern: p1, arg: a1, body: b1, ghost: g1 },
+                Let { pattern: p2, arg: a2, body: b2, ghost: g2 },
+            ) => {
+                g1 == g2
+                    && a1.alpha_eq_in(a2, env)
+                    && scoped(env, |env| pattern_alpha_eq(p1, p2, env) && b1.alpha_eq_in(b2, env))
+            }
+            (Var(l), Var(r)) => var_alpha_eq(l, r, env),
+            (QVar(l), QVar(r)) => l == r,
+            (
+                RecUp { record: r1, label: l1, val: v1 },
+                RecUp { record: r2, label: l2, val: v2 },
+            ) => {
+                l1 == l2 && r1.alpha_eq_in(r2, env) && v1.alpha_eq_in(v2, env)
+            }
+            (RecField { record: r1, label: l1 }, RecField { record: r2, label: l2 }) => {
+                l1 == l2 && r1.alpha_eq_in(r2, env)
+            }
+            (Record { ctor: c1, fields: f1 }, Record { ctor: c2, fields: f2 }) => {
+                c1 == c2
+                    && f1.len() == f2.len()
+                    && f1
+                        .iter()
+                        .zip(f2)
+                        .all(|((n1, e1), (n2, e2))| n1 == n2 && e1.alpha_eq_in(e2, env))
+            }
+            (Tuple(l), Tuple(r)) => all(l, r, env),
+            (
+                TupleField { tuple: t1, idx: i1, arity: a1 },
+                TupleField { tuple: t2, idx: i2, arity: a2 },
+            ) => i1 == i2 && a1 == a2 && t1.alpha_eq_in(t2, env),
+            (Index { base: b1, index: i1 }, Index { base: b2, index: i2 }) => {
+                b1.alpha_eq_in(b2, env) && i1.alpha_eq_in(i2, env)
+            }
+            (Constructor { ctor: c1, args: a1 }, Constructor { ctor: c2, args: a2 }) => {
+                c1 == c2 && all(a1, a2, env)
+            }
+            (Const(l), Const(r)) => l == r,
+            (BinaryOp(o1, l1, r1), BinaryOp(o2, l2, r2)) => {
+                o1 == o2 && l1.alpha_eq_in(l2, env) && r1.alpha_eq_in(r2, env)
+            }
+            (UnaryOp(o1, l), UnaryOp(o2, r)) => o1 == o2 && l.alpha_eq_in(r, env),
+            (Call(f1, a1), Call(f2, a2)) => f1.alpha_eq_in(f2, env) && all(a1, a2, env),
+            (Verbatim(s1, f1), Verbatim(s2, f2)) => s1 == s2 && f1 == f2,
+            (Raw { src: s1, subst_points: p1 }, Raw { src: s2, subst_points: p2 }) => {
+                s1 == s2
+                    && p1.len() == p2.len()
+                    && p1
+                        .iter()
+                        .zip(p2)
+                        .all(|((t1, l), (t2, r))| t1 == t2 && var_alpha_eq(l, r, env))
+            }
+            (Abs(x1, t1, b1), Abs(x2, t2, b2)) => t1 == t2 && scoped(env, |env| {
+                env.push((x1.clone(), x2.clone()));
+                b1.alpha_eq_in(b2, env)
+            }),
+            (Match(s1, brs1), Match(s2, brs2)) => {
+                s1.alpha_eq_in(s2, env)
+                    && brs1.len() == brs2.len()
+                    && brs1.iter().zip(brs2).all(|((p1, g1, b1), (p2, g2, b2))| {
+                        scoped(env, |env| {
+                            pattern_alpha_eq(p1, p2, env)
+                                && match (g1, g2) {
+                                    (Some(g1), Some(g2)) => g1.alpha_eq_in(g2, env),
+                                    (None, None) => true,
+                                    _ => false,
+                                }
+                                && b1.alpha_eq_in(b2, env)
+                        })
+                    })
+            }
+            (Attr(a1, e1), Attr(a2, e2)) => a1 == a2 && e1.alpha_eq_in(e2, env),
+            (Absurd, Absurd) | (Result, Result) => true,
+            (Impl(l1, r1), Impl(l2, r2)) | (Assume(l1, r1), Assume(l2, r2)) => {
+                l1.alpha_eq_in(l2, env) && r1.alpha_eq_in(r2, env)
+            }
+            (Assert { goal: g1, by: p1, body: b1 }, Assert { goal: g2, by: p2, body: b2 }) => {
+                g1.alpha_eq_in(g2, env)
+                    && match (p1, p2) {
+                        (Some(p1), Some(p2)) => p1.alpha_eq_in(p2, env),
+                        (None, None) => true,
+                        _ => false,
+                    }
+                    && b1.alpha_eq_in(b2, env)
+            }
+            (Forall(a1, b1), Forall(a2, b2)) | (Exists(a1, b1), Exists(a2, b2)) => {
+                a1.len() == a2.len()
+                    && scoped(env, |env| {
+                        for ((x1, t1), (x2, t2)) in a1.iter().zip(a2) {
+                            if t1 != t2 {
+                                return false;
+                            }
+                            env.push((x1.clone(), x2.clone()));
+                        }
+                        b1.alpha_eq_in(b2, env)
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    /// A hash of this expression which agrees with [`Exp::alpha_eq`]: bound variables are
+    /// hashed by the position of their binder rather than by name. The hasher has fixed keys,
+    /// so the hash is the same across runs.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.hash_in(&mut hasher, &mut Vec::new());
+        hasher.finish()
+    }
+
+    // `env` holds the binders in scope, innermost last
+    fn hash_in<H: Hasher>(&self, h: &mut H, env: &mut Vec<LocalIdent>) {
+        use Exp::*;
+        std::mem::discriminant(self).hash(h);
+        let len = env.len();
+
+        match self {
+            Let { pattern, arg, body, ghost } => {
+                ghost.hash(h);
+                arg.hash_in(h, env);
+                pattern_hash(pattern, h, env);
+                body.hash_in(h, env);
+            }
+            Var(v) => var_hash(v, h, env),
+            QVar(qn) => qn.hash(h),
+            RecUp { label, .. } | RecField { label, .. } => label.hash(h),
+            Record { ctor, fields } => {
+                ctor.hash(h);
+                fields.iter().for_each(|(name, _)| name.hash(h));
+            }
+            TupleField { idx, arity, .. } => (idx, arity).hash(h),
+            Constructor { ctor, .. } => ctor.hash(h),
+            // Integers of different types may still be equal, so only their values are hashed
+            Const(Constant::Int(i, _)) => (0u8, i).hash(h),
+            Const(Constant::Uint(u, _)) => (1u8, u).hash(h),
+            Const(Constant::Float(x, _)) => (2u8, x.to_bits()).hash(h),
+            Const(Constant::Char(c)) => (3u8, c).hash(h),
+            Const(Constant::Other(o)) => (4u8, o).hash(h),
+            Const(Constant::Str(s)) => (5u8, s).hash(h),
+            BinaryOp(op, _, _) => op.hash(h),
+            UnaryOp(op, _) => op.hash(h),
+            Verbatim(src, fvs) => {
+                // Sets aren't ordered, so hash the free variables in a canonical order
+                let mut fvs: Vec<_> = fvs.iter().map(|v| v.to_string()).collect();
+                fvs.sort();
+                (src, fvs).hash(h)
+            }
+            Raw { src, subst_points } => {
+                src.hash(h);
+                for (tgt, v) in subst_points {
+                    tgt.hash(h);
+                    var_hash(v, h, env);
+                }
+            }
+            Abs(x, ty, body) => {
+                ty.hash(h);
+                env.push(x.clone());
+                body.hash_in(h, env);
+            }
+            Match(scrut, brs) => {
+                scrut.hash_in(h, env);
+                brs.len().hash(h);
+                for (pat, guard, body) in brs {
+                    pattern_hash(pat, h, env);
+                    guard.is_some().hash(h);
+                    guard.iter().chain(std::iter::once(body)).for_each(|e| e.hash_in(h, env));
+                    env.truncate(len);
+                }
+            }
+            Attr(attr, _) => attr.hash(h),
+            Forall(binders, body) | Exists(binders, body) => {
+                for (x, ty) in binders {
+                    ty.hash(h);
+                    env.push(x.clone());
+                }
+                body.hash_in(h, env);
+            }
+            _ => {}
         }
+
+        // Binders have been handled above, the other subexpressions are hashed in order
+        if !binds(self) {
+            let subexps = self.subexps();
+            subexps.len().hash(h);
+            subexps.into_iter().for_each(|e| e.hash_in(h, env));
+        }
+        env.truncate(len);
     }
 
-    // Construct an application from this expression and an argument
+    // Construct an application from this expression and an argument. Applying an existing
+    // application extends its arguments, so that `f a b c` stays a single `Call`.
     pub fn app_to(mut self, arg: Self) -> Self {
         match self {
             Exp::Call(_, ref mut args) => args.push(arg),
@@ -496,15 +1735,462 @@ impl Exp {
         }
         self
     }
+
+    /// The number of expression nodes in this expression, counting guards and the bodies of
+    /// binders. Patterns and types are not counted.
+    pub fn size(&self) -> usize {
+        1 + self.subexps().into_iter().map(Exp::size).sum::<usize>()
+    }
+
+    /// Lift a `let` found at the start of every arm of a `match` above it, when doing so doesn't
+    /// change what any variable refers to. Applies to nested matches first.
+    pub fn hoist_common_lets(&mut self) {
+        self.map_subexprs(&mut |e| e.hoist_common_lets());
+
+        let (pattern, arg, ghost) = match self {
+            Exp::Match(scrut, brs) => match common_let(scrut, brs) {
+                Some(common) => common,
+                None => return,
+            },
+            _ => return,
+        };
+
+        if let Exp::Match(_, brs) = self {
+            for (_, _, br) in brs.iter_mut() {
+                match std::mem::replace(br, Exp::Absurd) {
+                    Exp::Let { body, .. } => *br = *body,
+                    _ => unreachable!(),
+                }
+            }
+        }
+        let matched = std::mem::replace(self, Exp::Absurd);
+        *self = Exp::Let { pattern, arg: box arg, body: box matched, ghost };
+    }
+
+    /// Print this expression for debugging, eliding the subexpressions nested more than
+    /// `max_depth` levels below it as `…`.
+    pub fn display_short(&self, max_depth: usize) -> String {
+        let mut exp = self.clone();
+        exp.elide_below(max_depth);
+        format!("{}", printer::FormatEnv::default().to(&exp))
+    }
+
+    fn elide_below(&mut self, depth: usize) {
+        self.map_subexprs(&mut |e| {
+            if depth == 0 {
+                *e = Exp::Var("…".into());
+            } else {
+                e.elide_below(depth - 1)
+            }
+        })
+    }
+
+    /// Apply `f` to each immediate subexpression of this expression, including guards and the
+    /// bodies of binders. Passes recurse by calling `map_subexprs` again from within `f`.
+    pub fn map_subexprs(&mut self, f: &mut impl FnMut(&mut Exp)) {
+        visit_subexps!(self, f, iter_mut)
+    }
+
+    /// The qualified names this expression refers to, as variables, constructors or in patterns
+    pub fn qnames(&self) -> BTreeSet<QName> {
+        let mut names = BTreeSet::new();
+        self.collect_qnames(&mut names);
+        names
+    }
+
+    fn collect_qnames(&self, names: &mut BTreeSet<QName>) {
+        match self {
+            Exp::QVar(qn) | Exp::Constructor { ctor: qn, .. } | Exp::Record { ctor: qn, .. } => {
+                names.insert(qn.clone());
+            }
+            Exp::Let { pattern, .. } => pattern.collect_qnames(names),
+            Exp::Match(_, brs) => brs.iter().for_each(|(pat, _, _)| pat.collect_qnames(names)),
+            _ => {}
+        }
+        self.subexps().into_iter().for_each(|e| e.collect_qnames(names))
+    }
+
+    /// Check that no quantifier binds the same name twice, like `forall x x. p`. Why3 reads
+    /// this as the inner binder shadowing the outer one, which is never what a lowering meant.
+    pub fn check_binders(&self) -> Result<(), DuplicateBinder> {
+        if let Exp::Forall(binders, _) | Exp::Exists(binders, _) = self {
+            let mut seen = HashSet::new();
+            if let Some((dup, _)) = binders.iter().find(|(b, _)| !seen.insert(b)) {
+                return Err(DuplicateBinder { binder: dup.clone() });
+            }
+        }
+        self.subexps().into_iter().try_for_each(|e| e.check_binders())
+    }
+
+    // A construct in this expression which only makes sense in program code
+    fn program_only_construct(&self) -> Option<&'static str> {
+        match self {
+            Exp::BorrowMut(_) => Some("borrow_mut"),
+            _ => self.subexps().into_iter().find_map(|e| e.program_only_construct()),
+        }
+    }
+
+    fn collect_verbatims<'a>(&'a self, verbatims: &mut Vec<&'a str>) {
+        match self {
+            Exp::Verbatim(src, _) => verbatims.push(src),
+            _ => self.subexps().into_iter().for_each(|e| e.collect_verbatims(verbatims)),
+        }
+    }
+
+    // Whether `name` occurs as a value anywhere in this expression
+    fn mentions(&self, name: &QName) -> bool {
+        match self {
+            Exp::QVar(qn) => qn == name,
+            _ => self.subexps().into_iter().any(|e| e.mentions(name)),
+        }
+    }
+
+    // The immediate subexpressions of this expression
+    fn subexps<'a>(&'a self) -> Vec<&'a Exp> {
+        let mut subexps = Vec::new();
+        let mut push = |e: &'a Exp| subexps.push(e);
+        visit_subexps!(self, push, iter);
+        subexps
+    }
+
+    /// Check that every `match` in this expression handles all the constructors of the type it
+    /// scrutinizes, looking up types in `decls`. Only the outermost pattern of each arm is
+    /// considered, and matches on types missing from `decls` are assumed to be exhaustive.
+    pub fn check_match_exhaustive(&self, decls: &[TyDecl]) -> Result<(), NonExhaustiveMatch> {
+        if let Exp::Match(_, brs) = self {
+            check_arms_exhaustive(brs, decls)?;
+        }
+        self.subexps().into_iter().try_for_each(|e| e.check_match_exhaustive(decls))
+    }
+}
+
+// The `let` starting every arm of a match, provided it can be moved above the match: its binders
+// mustn't capture variables of the scrutinee or guards, or be shadowed by the arm patterns, and
+// its argument mustn't use the variables bound by the arms.
+fn common_let(scrut: &Exp, brs: &[(Pattern, Option<Exp>, Exp)]) -> Option<(Pattern, Exp, bool)> {
+    let (pattern, arg, ghost) = match brs.first()? {
+        (_, _, Exp::Let { pattern, arg, ghost, .. }) => (pattern, arg, ghost),
+        _ => return None,
+    };
+    let bound = pattern.binders();
+    let arg_fvs = arg.fvs();
+    if !bound.is_disjoint(&scrut.fvs()) {
+        return None;
+    }
+
+    for (pat, guard, br) in brs {
+        match br {
+            Exp::Let { pattern: p, arg: a, ghost: g, .. }
+                if p == pattern && g == ghost && a.alpha_eq(arg) => {}
+            _ => return None,
+        }
+
+        let arm_bound = pat.binders();
+        if !arm_bound.is_disjoint(&bound) || !arm_bound.is_disjoint(&arg_fvs) {
+            return None;
+        }
+        if guard.iter().any(|g| !g.fvs().is_disjoint(&bound)) {
+            return None;
+        }
+    }
+
+    Some((pattern.clone(), (**arg).clone(), *ghost))
+}
+
+// Run `f` with binders pushed onto `env` only for its duration
+fn scoped(
+    env: &mut Vec<(LocalIdent, LocalIdent)>,
+    f: impl FnOnce(&mut Vec<(LocalIdent, LocalIdent)>) -> bool,
+) -> bool {
+    let len = env.len();
+    let res = f(env);
+    env.truncate(len);
+    res
+}
+
+// Two variables are equal if they refer to the same binder, or are both free with the same name
+fn var_alpha_eq(l: &LocalIdent, r: &LocalIdent, env: &[(LocalIdent, LocalIdent)]) -> bool {
+    match (env.iter().rposition(|(b, _)| b == l), env.iter().rposition(|(_, b)| b == r)) {
+        (None, None) => l == r,
+        (bound_l, bound_r) => bound_l == bound_r,
+    }
+}
+
+/// An expression compared up to the renaming of bound variables, so that the results of lowering
+/// identical fragments can be shared through a `HashMap<ExpKey, _>`
+#[derive(Debug, Clone)]
+pub struct ExpKey(pub Exp);
+
+impl PartialEq for ExpKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.alpha_eq(&other.0)
+    }
+}
+
+impl Eq for ExpKey {}
+
+impl Hash for ExpKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.canonical_hash())
+    }
+}
+
+// Expressions whose subexpressions are hashed together with the variables they bind
+fn binds(exp: &Exp) -> bool {
+    matches!(
+        exp,
+        Exp::Let { .. } | Exp::Abs(..) | Exp::Match(..) | Exp::Forall(..) | Exp::Exists(..)
+    )
+}
+
+fn var_hash<H: Hasher>(v: &LocalIdent, h: &mut H, env: &[LocalIdent]) {
+    match env.iter().rposition(|b| b == v) {
+        Some(ix) => (0u8, ix).hash(h),
+        None => (1u8, v).hash(h),
+    }
+}
+
+// Hash the shape of a pattern, adding its binders to `env`
+fn pattern_hash<H: Hasher>(pat: &Pattern, h: &mut H, env: &mut Vec<LocalIdent>) {
+    std::mem::discriminant(pat).hash(h);
+    match pat {
+        Pattern::Wildcard => {}
+        Pattern::VarP(v) => env.push(v.clone()),
+        Pattern::TupleP(pats) => {
+            pats.len().hash(h);
+            pats.iter().for_each(|p| pattern_hash(p, h, env));
+        }
+        Pattern::ConsP(ctor, pats) => {
+            ctor.hash(h);
+            pats.len().hash(h);
+            pats.iter().for_each(|p| pattern_hash(p, h, env));
+        }
+    }
+}
+
+// Compare two patterns, pairing up their binders in `env`
+fn pattern_alpha_eq(l: &Pattern, r: &Pattern, env: &mut Vec<(LocalIdent, LocalIdent)>) -> bool {
+    use Pattern::*;
+    match (l, r) {
+        (Wildcard, Wildcard) => true,
+        (VarP(l), VarP(r)) => {
+            env.push((l.clone(), r.clone()));
+            true
+        }
+        (TupleP(l), TupleP(r)) => {
+            l.len() == r.len() && l.iter().zip(r).all(|(l, r)| pattern_alpha_eq(l, r, env))
+        }
+        (ConsP(c1, l), ConsP(c2, r)) => {
+            c1 == c2
+                && l.len() == r.len()
+                && l.iter().zip(r).all(|(l, r)| pattern_alpha_eq(l, r, env))
+        }
+        _ => false,
+    }
+}
+
+/// A logic function using a construct which only makes sense in program code
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProgramOnlyConstruct {
+    pub function: String,
+    pub construct: &'static str,
+}
+
+impl Display for ProgramOnlyConstruct {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "logic function `{}` uses `{}`, which is only allowed in programs",
+            self.function, self.construct
+        )
+    }
+}
+
+/// A `Verbatim` expression which is obviously not valid Why3
+#[derive(Debug, PartialEq, Eq)]
+pub struct MalformedVerbatim {
+    /// The declaration the expression appears in
+    pub item: String,
+    pub verbatim: String,
+    pub problem: String,
+}
+
+impl Display for MalformedVerbatim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed Why3 in `{}`: {} in `{}`", self.item, self.problem, self.verbatim)
+    }
+}
+
+/// A `match` which doesn't handle one of the constructors of its scrutinee's type
+#[derive(Debug, PartialEq, Eq)]
+pub struct NonExhaustiveMatch {
+    pub ty: String,
+    pub missing: String,
+}
+
+impl Display for NonExhaustiveMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "non-exhaustive match on `{}`: `{}` is not covered", self.ty, self.missing)
+    }
+}
+
+/// A block graph which refers to blocks it doesn't contain
+#[derive(Debug, PartialEq, Eq)]
+pub enum CfgError {
+    MissingEntry(BlockId),
+    DanglingTarget { block: BlockId, target: BlockId },
+}
+
+impl Display for CfgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CfgError::MissingEntry(entry) => write!(f, "entry block {} is missing", entry),
+            CfgError::DanglingTarget { block, target } => {
+                write!(f, "block {} jumps to {}, which doesn't exist", block, target)
+            }
+        }
+    }
+}
+
+/// A quantifier which binds the same name more than once
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateBinder {
+    pub binder: LocalIdent,
+}
+
+impl Display for DuplicateBinder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "quantifier binds `{}` more than once", self.binder)
+    }
+}
+
+/// A constructor pattern with a different number of fields than its constructor
+#[derive(Debug, PartialEq, Eq)]
+pub struct ArityMismatch {
+    pub ctor: String,
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl Display for ArityMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "constructor `{}` has {} fields, but its pattern has {}",
+            self.ctor, self.expected, self.found
+        )
+    }
+}
+
+// The name and constructors of the type `ctor` belongs to, if it is known
+fn type_ctors<'a>(
+    ctor: &str,
+    mut decls: impl Iterator<Item = &'a TyDecl>,
+) -> Option<(String, Vec<String>)> {
+    let bool_ctors = vec!["False".to_owned(), "True".to_owned()];
+    if bool_ctors.iter().any(|c| c == ctor) {
+        return Some(("bool".to_owned(), bool_ctors));
+    }
+    let decl = decls.find(|d| d.ty_constructors.iter().any(|(c, _)| c == ctor))?;
+    Some((decl.ty_name.name(), decl.ty_constructors.iter().map(|(c, _)| c.clone()).collect()))
+}
+
+// Whether the unguarded arms of a match provably cover every value of the scrutinee
+fn arms_cover_type(brs: &[(Pattern, Option<Exp>, Exp)], decls: &[&TyDecl]) -> bool {
+    let unguarded = || brs.iter().filter(|(_, guard, _)| guard.is_none()).map(|(pat, _, _)| pat);
+    if unguarded().any(|pat| !pat.is_refutable(decls)) {
+        return true;
+    }
+
+    let covered: HashSet<_> = unguarded()
+        .filter_map(|pat| match pat {
+            Pattern::ConsP(ctor, _) => Some(ctor.name()),
+            _ => None,
+        })
+        .collect();
+    let first = match covered.iter().next() {
+        Some(first) => first.clone(),
+        None => return false,
+    };
+    match type_ctors(&first, decls.iter().copied()) {
+        Some((_, ctors)) => ctors.iter().all(|c| covered.contains(c)),
+        None => false,
+    }
+}
+
+fn check_arms_exhaustive(
+    brs: &[(Pattern, Option<Exp>, Exp)],
+    decls: &[TyDecl],
+) -> Result<(), NonExhaustiveMatch> {
+    let mut covered = HashSet::new();
+    for (pat, guard, _) in brs {
+        // Guarded arms may fail, so they never count towards exhaustiveness
+        if guard.is_some() {
+            continue;
+        }
+        match pat {
+            Pattern::Wildcard | Pattern::VarP(_) | Pattern::TupleP(_) => return Ok(()),
+            Pattern::ConsP(ctor, _) => {
+                covered.insert(ctor.name());
+            }
+        }
+    }
+
+    let first = brs.iter().find_map(|(pat, _, _)| match pat {
+        Pattern::ConsP(ctor, _) => Some(ctor.name()),
+        _ => None,
+    });
+    let first = match first {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+
+    let (ty, ctors) = match type_ctors(&first, decls.iter()) {
+        Some(ctors) => ctors,
+        None => return Ok(()),
+    };
+
+    match ctors.into_iter().find(|c| !covered.contains(c)) {
+        Some(missing) => Err(NonExhaustiveMatch { ty, missing }),
+        None => Ok(()),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Constant {
     Int(i128, Option<Type>),
     Uint(u128,  Option<Type>),
-    // Float(f64),
+    Char(char),
+    /// A finite float, printed as its exact value
+    Float(f64, Option<Type>),
+    /// A string literal, escaped when printed
+    Str(String),
+    /// A token printed as is, like `true`
     Other(String),
 }
+
+// Integers are equal when their values are, unless both have a type and the types differ: an
+// untyped literal may just have lost its type during folding. This isn't transitive, so
+// `Constant` isn't `Eq`.
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        let same_ty = |l: &Option<Type>, r: &Option<Type>| match (l, r) {
+            (Some(l), Some(r)) => l == r,
+            _ => true,
+        };
+        match (self, other) {
+            (Constant::Int(l, lty), Constant::Int(r, rty)) => l == r && same_ty(lty, rty),
+            (Constant::Uint(l, lty), Constant::Uint(r, rty)) => l == r && same_ty(lty, rty),
+            (Constant::Char(l), Constant::Char(r)) => l == r,
+            (Constant::Float(l, lty), Constant::Float(r, rty)) => {
+                l.to_bits() == r.to_bits() && same_ty(lty, rty)
+            }
+            (Constant::Str(l), Constant::Str(r)) => l == r,
+            (Constant::Other(l), Constant::Other(r)) => l == r,
+            _ => false,
+        }
+    }
+}
 impl Constant {
     pub fn const_true() -> Self {
         Constant::Other("true".to_owned())
@@ -514,7 +2200,88 @@ impl Constant {
     }
 }
 
-#[derive(Clone, Debug)]
+// The Why3 module defining a machine integer type, along with the bounds of the type. The upper
+// bound is unsigned so that it can hold `u128::MAX`.
+fn machine_int(ty: &Type) -> Option<(&'static str, i128, u128)> {
+    let name = match ty {
+        Type::TConstructor(qn) if qn.module.is_empty() => qn.name(),
+        _ => return None,
+    };
+
+    // `usize` and `isize` are 64 bit aliases defined by the prelude
+    let bounds = match &name[..] {
+        "uint8" => ("UInt8", 0, u8::MAX as u128),
+        "uint16" => ("UInt16", 0, u16::MAX as u128),
+        "uint32" => ("UInt32", 0, u32::MAX as u128),
+        "uint64" | "usize" => ("UInt64", 0, u64::MAX as u128),
+        "uint128" => ("UInt128", 0, u128::MAX),
+        "int8" => ("Int8", i8::MIN as i128, i8::MAX as u128),
+        "int16" => ("Int16", i16::MIN as i128, i16::MAX as u128),
+        "int32" => ("Int32", i32::MIN as i128, i32::MAX as u128),
+        "int64" | "isize" => ("Int64", i64::MIN as i128, i64::MAX as u128),
+        "int128" => ("Int128", i128::MIN, i128::MAX as u128),
+        _ => return None,
+    };
+    Some(bounds)
+}
+
+fn float_type(ty: &Type) -> Option<&'static str> {
+    match ty {
+        Type::TConstructor(qn) if qn.module.is_empty() => match &qn.name()[..] {
+            "single" => Some("single"),
+            "double" => Some("double"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Convert `e` from `from` to `to`, when that is a cast between a float and a machine integer
+/// or between two floats. The conversions are the program functions declared by the prelude:
+/// following Rust, floats are truncated toward zero when converted to integers, while integers
+/// and floats are rounded to the nearest float, ties to even, when converted to floats. Unlike
+/// Rust, out of range float to integer casts don't saturate: `of_int` requires the truncated
+/// value to fit. Returns `None` for any other pair of types.
+pub fn numeric_cast(e: Exp, from: &Type, to: &Type) -> Option<Exp> {
+    let call = |func: QName, arg| Exp::Call(box Exp::QVar(func), vec![arg]);
+    let prelude = |func: String| QName::from(&func[..]);
+
+    let cast = match (float_type(from), float_type(to)) {
+        (Some(from), Some(to)) => call(prelude(format!("{}_of_{}", to, from)), e),
+        (Some(from), None) => {
+            let (to, _, _) = machine_int(to)?;
+            let truncated = call(prelude(format!("truncate_{}", from)), e);
+            call(QName::qualified(to, "of_int"), truncated)
+        }
+        (None, Some(to)) => {
+            let (from, _, _) = machine_int(from)?;
+            call(prelude(format!("{}_of_int", to)), call(QName::qualified(from, "to_int"), e))
+        }
+        (None, None) => return None,
+    };
+    Some(cast)
+}
+
+/// The condition under which `l op r` doesn't overflow, when both operands have the machine
+/// integer type `ty`. Returns `None` for operators which can't overflow or types which aren't
+/// machine integers.
+pub fn overflow_condition(op: &BinOp, l: Exp, r: Exp, ty: &Type) -> Option<Exp> {
+    if !matches!(op, BinOp::Add | BinOp::Sub | BinOp::Mul) {
+        return None;
+    }
+    let (module, min, max) = machine_int(ty)?;
+
+    let to_int = |e| Exp::Call(box Exp::QVar(QName::qualified(module, "to_int")), vec![e]);
+    let (min, max) = (Exp::Const(Constant::Int(min, None)), Exp::Const(Constant::Uint(max, None)));
+    let result = Exp::BinaryOp(op.clone(), box to_int(l), box to_int(r));
+
+    Some(Exp::conj(
+        Exp::BinaryOp(BinOp::Le, box min, box result.clone()),
+        Exp::BinaryOp(BinOp::Le, box result, box max),
+    ))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Pattern {
     Wildcard,
     VarP(LocalIdent),
@@ -525,33 +2292,1573 @@ pub enum Pattern {
 
 impl Pattern {
     pub fn mk_true() -> Self {
-        Self::ConsP(QName { module: vec![], name: vec!["True".into()] }, vec![])
+        Self::ConsP(QName::from("True"), vec![])
     }
 
     pub fn mk_false() -> Self {
-        Self::ConsP(QName { module: vec![], name: vec!["False".into()] }, vec![])
+        Self::ConsP(QName::from("False"), vec![])
     }
 
-    pub fn binders(&self) -> HashSet<LocalIdent> {
+    /// Whether some values aren't matched by this pattern. Constructors of types missing from
+    /// `decls` are assumed to be refutable.
+    pub fn is_refutable(&self, decls: &[&TyDecl]) -> bool {
         match self {
-            Pattern::Wildcard => HashSet::new(),
-            Pattern::VarP(s) => {
-                let mut b = HashSet::new();
-                b.insert(s.clone());
-                b
-            }
-            Pattern::TupleP(pats) => {
-                pats.iter().map(|p| p.binders()).fold(HashSet::new(), |mut set, x| {
-                    set.extend(x);
-                    set
-                })
-            }
-            Pattern::ConsP(_, args) => {
-                args.iter().map(|p| p.binders()).fold(HashSet::new(), |mut set, x| {
-                    set.extend(x);
-                    set
-                })
+            Pattern::Wildcard | Pattern::VarP(_) => false,
+            Pattern::TupleP(pats) => pats.iter().any(|p| p.is_refutable(decls)),
+            Pattern::ConsP(ctor, args) => {
+                let ctor = ctor.name();
+                let single = decls.iter().any(|d| match &d.ty_constructors[..] {
+                    [(c, _)] => *c == ctor,
+                    _ => false,
+                });
+                !single || args.iter().any(|p| p.is_refutable(decls))
             }
         }
     }
+
+    /// Check that every constructor pattern has as many fields as its constructor declares in
+    /// `decls`. Constructors missing from `decls` are not checked.
+    pub fn check_arity(&self, decls: &[TyDecl]) -> Result<(), ArityMismatch> {
+        match self {
+            Pattern::Wildcard | Pattern::VarP(_) => Ok(()),
+            Pattern::TupleP(pats) => pats.iter().try_for_each(|p| p.check_arity(decls)),
+            Pattern::ConsP(ctor, args) => {
+                let name = ctor.name();
+                let declared = match &name[..] {
+                    "True" | "False" => Some(0),
+                    _ => decls
+                        .iter()
+                        .flat_map(|d| &d.ty_constructors)
+                        .find(|(c, _)| *c == name)
+                        .map(|(_, fields)| fields.len()),
+                };
+                match declared {
+                    Some(expected) if expected != args.len() => {
+                        Err(ArityMismatch { ctor: name, expected, found: args.len() })
+                    }
+                    _ => args.iter().try_for_each(|p| p.check_arity(decls)),
+                }
+            }
+        }
+    }
+
+    fn subst_qname(&mut self, map: &HashMap<QName, QName>) {
+        match self {
+            Pattern::Wildcard | Pattern::VarP(_) => {}
+            Pattern::TupleP(pats) => pats.iter_mut().for_each(|p| p.subst_qname(map)),
+            Pattern::ConsP(ctor, pats) => {
+                if let Some(new) = map.get(ctor) {
+                    *ctor = new.clone();
+                }
+                pats.iter_mut().for_each(|p| p.subst_qname(map))
+            }
+        }
+    }
+
+    fn collect_qnames(&self, names: &mut BTreeSet<QName>) {
+        match self {
+            Pattern::Wildcard | Pattern::VarP(_) => {}
+            Pattern::TupleP(pats) => pats.iter().for_each(|p| p.collect_qnames(names)),
+            Pattern::ConsP(ctor, pats) => {
+                names.insert(ctor.clone());
+                pats.iter().for_each(|p| p.collect_qnames(names))
+            }
+        }
+    }
+
+    /// Whether this pattern binds `v`
+    fn rename_binders(&mut self, renamed: &HashMap<LocalIdent, LocalIdent>) {
+        match self {
+            Pattern::Wildcard => {}
+            Pattern::VarP(x) => {
+                if let Some(fresh) = renamed.get(x) {
+                    *x = fresh.clone();
+                }
+            }
+            Pattern::TupleP(pats) | Pattern::ConsP(_, pats) => {
+                pats.iter_mut().for_each(|p| p.rename_binders(renamed))
+            }
+        }
+    }
+
+    pub fn binds(&self, v: &LocalIdent) -> bool {
+        match self {
+            Pattern::Wildcard => false,
+            Pattern::VarP(x) => x == v,
+            Pattern::TupleP(pats) | Pattern::ConsP(_, pats) => pats.iter().any(|p| p.binds(v)),
+        }
+    }
+
+    pub fn binders(&self) -> HashSet<LocalIdent> {
+        match self {
+            Pattern::Wildcard => HashSet::new(),
+            Pattern::VarP(s) => {
+                let mut b = HashSet::new();
+                b.insert(s.clone());
+                b
+            }
+            Pattern::TupleP(pats) => {
+                pats.iter().map(|p| p.binders()).fold(HashSet::new(), |mut set, x| {
+                    set.extend(x);
+                    set
+                })
+            }
+            Pattern::ConsP(_, args) => {
+                args.iter().map(|p| p.binders()).fold(HashSet::new(), |mut set, x| {
+                    set.extend(x);
+                    set
+                })
+            }
+        }
+    }
+
+    /// Collapse tuple patterns made up only of wildcards into a single wildcard.
+    ///
+    /// Constructor patterns are kept even when all their fields are wildcards, as they still
+    /// discriminate between the variants of their type.
+    pub fn simplify(self) -> Pattern {
+        match self {
+            Pattern::TupleP(pats) => {
+                let pats: Vec<_> = pats.into_iter().map(Pattern::simplify).collect();
+                if pats.iter().all(|p| matches!(p, Pattern::Wildcard)) {
+                    Pattern::Wildcard
+                } else {
+                    Pattern::TupleP(pats)
+                }
+            }
+            Pattern::ConsP(c, pats) => {
+                Pattern::ConsP(c, pats.into_iter().map(Pattern::simplify).collect())
+            }
+            p => p,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verbatim(src: &str, fvs: &[&str]) -> Exp {
+        Exp::Verbatim(src.into(), fvs.iter().map(|v| LocalIdent::from(*v)).collect())
+    }
+
+    #[test]
+    fn verbatim_fvs_are_declared_set() {
+        assert!(verbatim("true", &[]).fvs().is_empty());
+
+        let exp = Exp::conj(verbatim("x < y", &["x", "y"]), Exp::Var(LocalIdent::Name("z".into())));
+        let expected: HashSet<_> = ["x", "y", "z"].iter().map(|v| LocalIdent::from(*v)).collect();
+        assert_eq!(exp.fvs(), expected);
+    }
+
+    #[test]
+    fn verbatim_subst_ignores_unrelated_vars() {
+        let mut exp = verbatim("x < 0", &["x"]);
+        let mut subst = HashMap::new();
+        subst.insert(LocalIdent::Name("y".into()), Exp::Absurd);
+        exp.subst(&subst);
+        assert!(matches!(exp, Exp::Verbatim(ref s, _) if s == "x < 0"));
+    }
+
+    #[test]
+    fn verbatim_subst_binds_declared_vars() {
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        let mut exp = verbatim("x < 0", &["x"]);
+        let mut subst = HashMap::new();
+        subst.insert(LocalIdent::from("x"), Exp::Const(Constant::Int(1, None)));
+        exp.subst(&subst);
+        assert_eq!(print(&exp), "let x = 1 in (x < 0)");
+        assert!(exp.fvs().is_empty());
+    }
+
+    #[test]
+    fn verbatim_under_renamed_binder() {
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        // `y` is renamed so as not to capture the `y` substituted for `x`, which the verbatim
+        // also has to follow
+        let mut exp = Exp::let_(
+            Pattern::VarP("y".into()),
+            Exp::Var("a".into()),
+            verbatim("x + y", &["x", "y"]),
+        );
+        let mut subst = HashMap::new();
+        subst.insert(LocalIdent::from("x"), Exp::Var("y".into()));
+        exp.subst(&subst);
+        let expected = [
+            "let y'0 = a in",
+            "let x'0 = y in",
+            "let y'1 = y'0 in",
+            "let x = x'0 in",
+            "let y = y'1 in",
+            "(x + y)",
+        ];
+        assert_eq!(print(&exp), expected.join("\n"));
+        assert_eq!(exp.fvs(), ["a", "y"].iter().map(|v| LocalIdent::from(*v)).collect());
+    }
+
+    fn raw(src: &str, points: &[(&str, &str)]) -> Exp {
+        let subst_points = points.iter().map(|(t, l)| (t.to_string(), (*l).into())).collect();
+        Exp::Raw { src: src.into(), subst_points }
+    }
+
+    #[test]
+    fn raw_follows_renamed_binder() {
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        let mut body = raw("forall y . y < {x} /\\ {x} < {z}", &[("{x}", "x"), ("{z}", "z")]);
+        let expected: HashSet<_> = ["x", "z"].iter().map(|v| LocalIdent::from(*v)).collect();
+        assert_eq!(body.fvs(), expected);
+
+        // Freshen the binder of `fun x -> body`
+        body.rename(&"x".into(), "x_1".into());
+        let abs = Exp::Abs("x_1".into(), None, box body);
+        assert_eq!(print(&abs), "fun x_1 -> forall y . y < x_1 /\\ x_1 < z");
+        assert_eq!(abs.fvs(), ["z"].iter().map(|v| LocalIdent::from(*v)).collect());
+    }
+
+    #[test]
+    fn raw_substitutes_whole_tokens() {
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        // `$1` is a prefix of `$10`, but only stands for itself
+        let exp = raw("$1 + $10 + $1", &[("$1", "a"), ("$10", "b")]);
+        assert_eq!(print(&exp), "a + b + a");
+        let exp = raw("$1 + $10", &[("$1", "a")]);
+        assert_eq!(print(&exp), "a + $10");
+
+        // Substituted names aren't substituted again
+        let exp = raw("x < y", &[("x", "y"), ("y", "x")]);
+        assert_eq!(print(&exp), "y < x");
+        let exp = raw("xs < x", &[("x", "z")]);
+        assert_eq!(print(&exp), "xs < z");
+    }
+
+    #[test]
+    fn raw_subst_binds_non_variables() {
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        let mut exp = raw("{x} + {x}", &[("{x}", "x")]);
+        let mut subst = HashMap::new();
+        subst.insert(LocalIdent::from("x"), Exp::Const(Constant::Int(1, None)));
+        exp.subst(&subst);
+        assert_eq!(print(&exp), "let x'0 = 1 in (x'0 + x'0)");
+        assert!(exp.fvs().is_empty());
+
+        // The substitution is simultaneous: the `a` substituted for `b` is the outer one
+        let mut exp = raw("{a} + {b}", &[("{a}", "a"), ("{b}", "b")]);
+        let mut subst = HashMap::new();
+        subst.insert(LocalIdent::from("a"), Exp::Const(Constant::Int(1, None)));
+        subst.insert(LocalIdent::from("b"), Exp::Var("a".into()));
+        exp.subst(&subst);
+        assert_eq!(print(&exp), "let a'0 = 1 in (a'0 + a)");
+        assert_eq!(exp.fvs(), ["a"].iter().map(|v| LocalIdent::from(*v)).collect());
+
+        // Nor do the bindings of several replacements capture each other
+        let one = || box Exp::Const(Constant::Int(1, None));
+        let succ = |v: &str| Exp::BinaryOp(BinOp::Add, box Exp::Var(v.into()), one());
+        let mut exp = raw("{x} * {y}", &[("{x}", "x"), ("{y}", "y")]);
+        let mut subst = HashMap::new();
+        subst.insert(LocalIdent::from("x"), succ("y"));
+        subst.insert(LocalIdent::from("y"), succ("x"));
+        exp.subst(&subst);
+        assert_eq!(print(&exp), "let x'0 = y + 1 in\nlet y'0 = x + 1 in\n(x'0 * y'0)");
+        assert_eq!(exp.fvs(), ["x", "y"].iter().map(|v| LocalIdent::from(*v)).collect());
+    }
+
+    #[test]
+    fn block_locals() {
+        let var = |v: &str| Exp::Var(v.into());
+        let some = QName::from("Some");
+        let block = Block {
+            statements: vec![
+                Statement::Assign { lhs: "x".into(), rhs: Exp::conj(var("a"), var("b")) },
+                Statement::Assert { goal: Exp::Impl(box var("c"), box var("x")), by: None },
+            ],
+            terminator: Terminator::Switch(
+                var("d"),
+                vec![
+                    (
+                        Pattern::ConsP(some, vec![Pattern::VarP("e".into())]),
+                        Terminator::Goto(BlockId(1)),
+                    ),
+                    (Pattern::Wildcard, Terminator::Return),
+                ],
+            ),
+        };
+
+        let expected: HashSet<_> =
+            ["a", "b", "c", "d", "e", "x"].iter().map(|v| LocalIdent::from(*v)).collect();
+        assert_eq!(block.locals(), expected);
+    }
+
+    #[test]
+    fn negation_folding() {
+        let x = || Exp::Var("x".into());
+
+        assert!(matches!(Exp::not(Exp::not(x())), Exp::Var(_)));
+        assert!(matches!(Exp::neg(Exp::neg(x())), Exp::Var(_)));
+        assert!(matches!(Exp::not(Exp::mk_true()), Exp::Const(Constant::Other(b)) if b == "false"));
+        assert!(matches!(Exp::not(Exp::mk_false()), Exp::Const(Constant::Other(b)) if b == "true"));
+
+        assert!(matches!(Exp::not(x()), Exp::UnaryOp(UnOp::Not, box Exp::Var(_))));
+        assert!(matches!(Exp::neg(x()), Exp::UnaryOp(UnOp::Neg, box Exp::Var(_))));
+        assert!(matches!(
+            Exp::neg(Exp::not(x())),
+            Exp::UnaryOp(UnOp::Neg, box Exp::UnaryOp(UnOp::Not, _))
+        ));
+    }
+
+    #[test]
+    fn record_decl_used_types() {
+        let qname = |n: &str| QName::from(n);
+        let decl = TyDecl {
+            ty_name: qname("pair"),
+            ty_params: vec!["a".into()],
+            ty_constructors: vec![],
+            ty_fields: vec![
+                ("fst".into(), Type::TConstructor(qname("left"))),
+                (
+                    "snd".into(),
+                    Type::TApp(
+                        box Type::TConstructor(qname("right")),
+                        vec![Type::TVar("a".into())],
+                    ),
+                ),
+            ],
+            ty_alias: None,
+        };
+
+        let expected: BTreeSet<_> = vec![qname("left"), qname("right")].into_iter().collect();
+        assert_eq!(decl.used_types(), expected);
+    }
+
+    #[test]
+    fn match_exhaustiveness() {
+        let ctor = |c: &str| QName::qualified("Type", c);
+        let option = TyDecl {
+            ty_name: ctor("option"),
+            ty_params: vec!["a".into()],
+            ty_constructors: vec![
+                ("None".into(), vec![]),
+                ("Some".into(), vec![Type::TVar("a".into())]),
+            ],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let decls = vec![option];
+        let scrut = || box Exp::Var("o".into());
+        let none = || (Pattern::ConsP(ctor("None"), vec![]), None, Exp::mk_true());
+        let some =
+            || (Pattern::ConsP(ctor("Some"), vec![Pattern::Wildcard]), None, Exp::mk_false());
+
+        let exhaustive = Exp::Match(scrut(), vec![none(), some()]);
+        assert_eq!(exhaustive.check_match_exhaustive(&decls), Ok(()));
+
+        let wildcard = (Pattern::Wildcard, None, Exp::mk_false());
+        let wildcard = Exp::Match(scrut(), vec![none(), wildcard]);
+        assert_eq!(wildcard.check_match_exhaustive(&decls), Ok(()));
+
+        // The missing arm is reported even when the match is nested
+        let missing = Exp::not(Exp::Match(scrut(), vec![some()]));
+        assert_eq!(
+            missing.check_match_exhaustive(&decls),
+            Err(NonExhaustiveMatch { ty: "option".into(), missing: "None".into() })
+        );
+    }
+
+    #[test]
+    fn rename_free_occurrences() {
+        let var = |v: &str| Exp::Var(v.into());
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        let mut sum = Exp::BinaryOp(BinOp::Add, box var("x"), box var("y"));
+        sum.rename(&"x".into(), "z".into());
+        assert_eq!(print(&sum), "z + y");
+
+        sum.rename(&"x".into(), "w".into());
+        assert_eq!(print(&sum), "z + y");
+
+        let mut shadow = Exp::Let {
+            pattern: Pattern::VarP("x".into()),
+            arg: box var("x"),
+            body: box var("x"),
+            ghost: false,
+        };
+        shadow.rename(&"x".into(), "z".into());
+        assert_eq!(print(&shadow), "let x = z in x");
+    }
+
+    #[test]
+    fn qname_helpers() {
+        let name = vec!["List".into(), "Cons".into()];
+        let manual = QName { module: vec!["Type".into()].into(), name: name.clone().into() };
+        let built = QName::new(vec!["Type".into()], name);
+        assert_eq!(built, manual);
+        assert_eq!(built.name(), "List_Cons");
+
+        let qualified = QName::qualified("Type", "list");
+        let list = QName { module: vec!["Type".into()].into(), name: vec!["list".into()].into() };
+        assert_eq!(qualified, list);
+        assert_eq!(qualified.name(), "list");
+
+        assert_eq!(QName::new(vec![], vec!["x".into()]), QName::from("x"));
+    }
+
+    #[test]
+    fn tuple_projection() {
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+        let triple = Exp::Tuple(vec![Exp::Var("x".into()), Exp::Var("y".into()), Exp::mk_true()]);
+        let proj = |idx| Exp::TupleField { tuple: box triple.clone(), idx, arity: 3 };
+
+        assert_eq!(print(&proj(0)), "let (a, _, _) = (x, y, true) in a");
+        assert_eq!(print(&proj(1)), "let (_, a, _) = (x, y, true) in a");
+
+        let expected: HashSet<_> = ["x", "y"].iter().map(|v| LocalIdent::from(*v)).collect();
+        assert_eq!(proj(1).fvs(), expected);
+
+        let mut renamed = proj(0);
+        renamed.rename(&"x".into(), "z".into());
+        assert_eq!(print(&renamed), "let (a, _, _) = (z, y, true) in a");
+    }
+
+    #[test]
+    fn map_subexprs_visits_each_node_once() {
+        fn count(e: &mut Exp, visits: &mut usize) {
+            *visits += 1;
+            e.map_subexprs(&mut |e| count(e, visits))
+        }
+
+        let var = |v: &str| Exp::Var(v.into());
+        let some = |e| Exp::Constructor { ctor: "Some".into(), args: vec![e] };
+        let mut exp = Exp::Let {
+            pattern: Pattern::VarP("o".into()),
+            arg: box some(var("x")),
+            body: box Exp::Forall(
+                vec![("y".into(), Type::Integer)],
+                box Exp::Match(
+                    box var("o"),
+                    vec![
+                        (Pattern::VarP("a".into()), Some(var("a")), Exp::conj(var("a"), var("y"))),
+                        (Pattern::Wildcard, None, Exp::mk_false()),
+                    ],
+                ),
+            ),
+            ghost: false,
+        };
+
+        let mut visits = 0;
+        count(&mut exp, &mut visits);
+        assert_eq!(visits, 11);
+        assert_eq!(visits, exp.size());
+    }
+
+    fn ty_decl(name: &str, uses: &[&str]) -> TyDecl {
+        let args = uses.iter().map(|u| Type::TConstructor((*u).into())).collect();
+        TyDecl {
+            ty_name: name.into(),
+            ty_params: vec![],
+            ty_constructors: vec![(name.to_uppercase(), args)],
+            ty_fields: vec![],
+            ty_alias: None,
+        }
+    }
+
+    fn group_names(groups: &[Vec<TyDecl>]) -> Vec<Vec<String>> {
+        groups.iter().map(|g| g.iter().map(|d| d.ty_name.name()).collect()).collect()
+    }
+
+    #[test]
+    fn sort_ty_decls_chain() {
+        let decls = vec![ty_decl("a", &["b"]), ty_decl("c", &[]), ty_decl("b", &["c", "int"])];
+        let sorted = sort_ty_decls(decls);
+        assert_eq!(group_names(&sorted), vec![vec!["c"], vec!["b"], vec!["a"]]);
+    }
+
+    #[test]
+    fn sort_ty_decls_groups_cycles() {
+        let decls = vec![
+            ty_decl("user", &["tree"]),
+            ty_decl("tree", &["forest"]),
+            ty_decl("forest", &["tree", "forest"]),
+            ty_decl("list", &["list"]),
+        ];
+        let sorted = sort_ty_decls(decls);
+        assert_eq!(group_names(&sorted), vec![vec!["tree", "forest"], vec!["user"], vec!["list"]]);
+    }
+
+    #[test]
+    fn expression_size() {
+        let var = |v: &str| Exp::Var(v.into());
+
+        assert_eq!(Exp::Absurd.size(), 1);
+        assert_eq!(Exp::conj(var("x"), Exp::mk_true()).size(), 3);
+
+        let call = Exp::Call(box Exp::QVar(QName::from("f")), vec![var("x"), var("y")]);
+        assert_eq!(call.size(), 4);
+
+        let quant = Exp::Forall(
+            vec![("x".into(), Type::Integer), ("y".into(), Type::Integer)],
+            box Exp::Impl(box var("x"), box var("y")),
+        );
+        assert_eq!(quant.size(), 4);
+
+        let ctor = |name: &str, args| Exp::Constructor { ctor: QName::from(name), args };
+        let matched = Exp::Match(
+            box var("o"),
+            vec![
+                (Pattern::VarP("a".into()), Some(var("a")), ctor("Some", vec![var("a")])),
+                (Pattern::Wildcard, None, ctor("None", vec![])),
+            ],
+        );
+        assert_eq!(matched.size(), 6);
+    }
+
+    #[test]
+    fn rename_avoids_capture() {
+        let var = |v: &str| Exp::Var(v.into());
+        let mut exp = Exp::Forall(
+            vec![("y".into(), Type::Integer)],
+            box Exp::BinaryOp(BinOp::Lt, box var("x"), box var("y")),
+        );
+        exp.rename(&"x".into(), "y".into());
+        assert!(exp.fvs().contains(&"y".into()));
+        let expected = Exp::Forall(
+            vec![("y'0".into(), Type::Integer)],
+            box Exp::BinaryOp(BinOp::Lt, box var("y"), box var("y'0")),
+        );
+        assert!(exp.alpha_eq(&expected));
+
+        let some = |v: &str| Pattern::ConsP("Some".into(), vec![Pattern::VarP(v.into())]);
+        let mut exp = Exp::Match(
+            box var("o"),
+            vec![(some("y"), Some(var("x")), Exp::conj(var("x"), var("y")))],
+        );
+        let mut subst = HashMap::new();
+        subst.insert("x".into(), Exp::conj(var("y"), var("y'0")));
+        exp.subst(&subst);
+        let fvs: HashSet<_> = ["o", "y", "y'0"].iter().map(|v| LocalIdent::from(*v)).collect();
+        assert_eq!(exp.fvs(), fvs);
+        assert_eq!(
+            format!("{}", printer::FormatEnv::default().to(&exp)),
+            "match (o) with\n  | Some(y'1) -> if y && y'0 then y && y'0 && y'1 else absurd\n  \
+             | _ -> absurd\n  end\n"
+        );
+
+        // Binders which capture nothing are left alone
+        let mut exp = Exp::Abs("y".into(), None, box var("y"));
+        exp.rename(&"x".into(), "y".into());
+        assert!(matches!(exp, Exp::Abs(LocalIdent::Name(y), _, _) if y == "y"));
+    }
+
+    #[test]
+    fn anonymous_locals_are_valid_identifiers() {
+        let anon = |n: &str| LocalIdent::Anon(3, Some(n.into())).to_string();
+        assert_eq!(anon("x"), "x_3");
+        assert_eq!(anon("Foo"), "foo_3");
+        assert_eq!(anon("my var"), "my_var_3");
+        assert_eq!(anon("{closure#0}"), "closure_0__3");
+        assert_eq!(anon("{}"), "_3");
+        assert_eq!(LocalIdent::Anon(3, None).to_string(), "_3");
+    }
+
+    #[test]
+    fn simplify_collapses_wildcard_tuples() {
+        use Pattern::*;
+        let pat = TupleP(vec![Wildcard, TupleP(vec![Wildcard, Wildcard])]);
+        assert!(matches!(pat.simplify(), Wildcard));
+
+        let some = |p| ConsP(QName::from("Some"), vec![p]);
+        let pat = TupleP(vec![some(TupleP(vec![Wildcard, Wildcard])), VarP("x".into())]);
+        match pat.simplify() {
+            TupleP(pats) => match &pats[..] {
+                [ConsP(_, inner), VarP(x)] => {
+                    assert!(matches!(&inner[..], [Wildcard]));
+                    assert_eq!(x, &LocalIdent::from("x"));
+                }
+                pats => panic!("unexpected patterns {:?}", pats),
+            },
+            pat => panic!("unexpected pattern {:?}", pat),
+        }
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        let var = |v: &str| Exp::Var(v.into());
+        let gt = |l, r| Exp::BinaryOp(BinOp::Gt, box l, box r);
+        let forall = |x: &str, body| Exp::Forall(vec![(x.into(), Type::Integer)], box body);
+        let zero = || Exp::Const(Constant::Int(0, None));
+
+        assert!(forall("x", gt(var("x"), zero())).alpha_eq(&forall("y", gt(var("y"), zero()))));
+        let bounded = |bound: &str| forall("x", gt(var("x"), var(bound)));
+        assert!(!bounded("z").alpha_eq(&bounded("w")));
+        // A bound variable is never equal to a free one, even with the same name
+        assert!(!bounded("y").alpha_eq(&forall("y", gt(var("y"), var("y")))));
+
+        let let_ = |x: &str, body| Exp::Let {
+            pattern: Pattern::TupleP(vec![Pattern::VarP(x.into()), Pattern::Wildcard]),
+            arg: box var("t"),
+            body: box body,
+            ghost: false,
+        };
+        assert!(let_("a", var("a")).alpha_eq(&let_("b", var("b"))));
+        assert!(!let_("a", var("a")).alpha_eq(&let_("b", var("a"))));
+
+        let abs = |x: &str, body| Exp::Abs(x.into(), None, box body);
+        let shadowed = |x: &str, y: &str, v: &str| abs(x, abs(y, var(v)));
+        assert!(shadowed("a", "a", "a").alpha_eq(&shadowed("b", "c", "c")));
+        assert!(!shadowed("a", "a", "a").alpha_eq(&shadowed("b", "c", "b")));
+    }
+
+    #[test]
+    fn display_short_elides_deep_subtrees() {
+        let var = |v: &str| Exp::Var(v.into());
+        let add = Exp::BinaryOp(BinOp::Add, box var("a"), box var("b"));
+        let exp = Exp::BinaryOp(BinOp::Mul, box add, box var("c"));
+
+        assert_eq!(exp.display_short(0), "… * …");
+        assert_eq!(exp.display_short(1), "(… + …) * c");
+        assert_eq!(exp.display_short(2), "(a + b) * c");
+
+        let let_ = Exp::Let {
+            pattern: Pattern::VarP("x".into()),
+            arg: box exp,
+            body: box var("x"),
+            ghost: false,
+        };
+        assert_eq!(let_.display_short(0), "let x = … in …");
+    }
+
+    #[test]
+    fn app_to_accumulates_arguments() {
+        let var = |v: &str| Exp::Var(v.into());
+        let lambda = || Exp::Abs("x".into(), None, box Exp::Abs("y".into(), None, box var("x")));
+
+        let app = lambda().app_to(var("a")).app_to(var("b")).app_to(var("c"));
+        match &app {
+            Exp::Call(box f, args) => {
+                assert!(f.alpha_eq(&lambda()));
+                assert_eq!(args.len(), 3);
+                assert!(args.iter().all(|a| matches!(a, Exp::Var(_))));
+            }
+            _ => panic!("expected a single application, got {:?}", app),
+        }
+        assert_eq!(app.display_short(3), "(fun x -> fun y -> x) a b c");
+    }
+
+    #[test]
+    fn hoist_common_lets() {
+        let var = |v: &str| Exp::Var(v.into());
+        let tmp = |arg| Exp::let_(Pattern::VarP("tmp".into()), arg, var("tmp"));
+        let one = || Exp::Const(Constant::Int(1, None));
+        let succ = |v| Exp::BinaryOp(BinOp::Add, box var(v), box one());
+        let arms = |l, r| {
+            let some = Pattern::ConsP(QName::from("Some"), vec![Pattern::VarP("y".into())]);
+            Exp::Match(box var("o"), vec![(some, None, l), (Pattern::Wildcard, None, r)])
+        };
+
+        let mut shared = arms(tmp(succ("x")), tmp(succ("x")));
+        shared.hoist_common_lets();
+        let tmp_pat = || Pattern::VarP("tmp".into());
+        let let_above = Exp::let_(tmp_pat(), succ("x"), arms(var("tmp"), var("tmp")));
+        assert!(shared.alpha_eq(&let_above), "{}", shared.display_short(10));
+
+        let mut differ = arms(tmp(succ("x")), tmp(succ("z")));
+        differ.hoist_common_lets();
+        assert!(differ.alpha_eq(&arms(tmp(succ("x")), tmp(succ("z")))));
+
+        // `y` is bound by the first arm, so the binding can't be moved out of it
+        let mut uses_arm = arms(tmp(succ("y")), tmp(succ("y")));
+        uses_arm.hoist_common_lets();
+        assert!(uses_arm.alpha_eq(&arms(tmp(succ("y")), tmp(succ("y")))));
+    }
+
+    #[test]
+    fn refutable_lets_become_matches() {
+        let var = |v: &str| Exp::Var(v.into());
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+        let list = ty_decl("list", &["int"]);
+        let option = TyDecl {
+            ty_name: "option".into(),
+            ty_params: vec![],
+            ty_constructors: vec![("None".into(), vec![]), ("Some".into(), vec![Type::Integer])],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let decls = [&list, &option];
+        let cons = |c: &str, args| Pattern::ConsP(QName::from(c), args);
+
+        let pair = Pattern::TupleP(vec![Pattern::VarP("a".into()), Pattern::Wildcard]);
+        let tuple = Exp::let_or_match(pair, var("t"), var("a"), &decls);
+        assert_eq!(print(&tuple), "let (a, _) = t in a");
+
+        let list = cons("LIST", vec![Pattern::VarP("x".into())]);
+        assert!(!list.is_refutable(&decls));
+        let some = cons("Some", vec![Pattern::VarP("x".into())]);
+        assert!(cons("LIST", vec![some.clone()]).is_refutable(&decls));
+
+        let refutable = Exp::let_or_match(some, var("o"), var("x"), &decls);
+        assert_eq!(print(&refutable), "match (o) with\n  | Some(x) -> x\n  | _ -> absurd\n  end\n");
+    }
+
+    #[test]
+    fn qname_clones_share_segments() {
+        let name = QName::new(vec!["Type".into()], vec!["List".into(), "Cons".into()]);
+        let copy = name.clone();
+        assert!(Rc::ptr_eq(&name.name, &copy.name));
+        assert!(Rc::ptr_eq(&name.module, &copy.module));
+
+        let rebuilt = QName::new(vec!["Type".into()], vec!["List".into(), "Cons".into()]);
+        assert!(!Rc::ptr_eq(&name.name, &rebuilt.name));
+        assert_eq!(name, rebuilt);
+        assert_ne!(name, QName::qualified("Type", "List"));
+        assert_eq!(QName::from("x"), QName::from("x"));
+    }
+
+    #[test]
+    fn parse_meta_args() {
+        let args = |src: &str| match Decl::parse_meta(src) {
+            Some(Decl::Meta { args, .. }) => Some(args),
+            _ => None,
+        };
+
+        assert!(matches!(
+            Decl::parse_meta(" \"compute_max_steps\" 1000000 "),
+            Some(Decl::Meta { ref name, ref args })
+                if name == "compute_max_steps" && args == &[MetaArg::Integer(1000000)]
+        ));
+        assert_eq!(args("\"hint\""), Some(vec![]));
+
+        // Quoted arguments may contain commas
+        let expected = vec![MetaArg::String("a, b".into()), MetaArg::String("".into())];
+        assert_eq!(args("\"hint\" \"a, b\", \"\""), Some(expected));
+        let expected =
+            vec![MetaArg::Ident("prop p".into()), MetaArg::Integer(-2), MetaArg::Ident("x".into())];
+        assert_eq!(args("\"rewrite\" prop p , -2,x"), Some(expected));
+
+        // Unclosed quotes, in the name or an argument, are rejected
+        assert_eq!(args("\"compute_max_steps 1"), None);
+        assert_eq!(args("\"hint\" \"lemma, 3"), None);
+        assert_eq!(args("\"hint\" lemma\", 3"), None);
+        assert_eq!(args("\"hint\" 1,"), None);
+    }
+
+    #[test]
+    fn qnames_hash_by_value() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |q: &QName| {
+            let mut h = DefaultHasher::new();
+            q.hash(&mut h);
+            h.finish()
+        };
+
+        let name = QName::new(vec!["Type".into()], vec!["List".into(), "Cons".into()]);
+        let rebuilt = QName::new(vec!["Type".into()], vec!["List".into(), "Cons".into()]);
+        assert_eq!(hash(&name), hash(&name.clone()));
+        assert_eq!(hash(&name), hash(&rebuilt));
+
+        // Shared and independently built names are the same key
+        let mut names = HashSet::new();
+        names.insert(name.clone());
+        assert!(names.contains(&rebuilt));
+        assert!(!names.insert(rebuilt));
+        assert!(names.insert(QName::qualified("Type", "List")));
+    }
+
+    #[test]
+    fn exists_fvs() {
+        let var = |v: &str| Exp::Var(v.into());
+        let fvs = |vs: &[&str]| vs.iter().map(|v| LocalIdent::from(*v)).collect::<HashSet<_>>();
+
+        let body = Exp::BinaryOp(BinOp::Gt, box var("x"), box var("y"));
+        let exists = Exp::Exists(vec![("x".into(), Type::Integer)], box body);
+        assert_eq!(exists.fvs(), fvs(&["y"]));
+
+        let inner = Exp::BinaryOp(BinOp::Lt, box var("w"), box var("z"));
+        let nested = Exp::Forall(
+            vec![("y".into(), Type::Integer)],
+            box Exp::conj(exists, Exp::Exists(vec![("z".into(), Type::Integer)], box inner)),
+        );
+        assert_eq!(nested.fvs(), fvs(&["w"]));
+    }
+
+    #[test]
+    fn prune_unreachable_blocks() {
+        let block = |terminator| Block { statements: vec![], terminator };
+        let mut blocks = BTreeMap::new();
+        blocks.insert(
+            BlockId(0),
+            block(Terminator::if_(
+                Exp::Var("c".into()),
+                Terminator::Goto(BlockId(1)),
+                Terminator::Goto(BlockId(2)),
+            )),
+        );
+        blocks.insert(BlockId(1), block(Terminator::Goto(BlockId(0))));
+        blocks.insert(BlockId(2), block(Terminator::Return));
+        blocks.insert(BlockId(3), block(Terminator::Goto(BlockId(2))));
+        blocks.insert(BlockId(4), block(Terminator::Absurd));
+
+        prune_unreachable(BlockId(0), &mut blocks);
+        let kept: Vec<_> = blocks.keys().copied().collect();
+        assert_eq!(kept, vec![BlockId(0), BlockId(1), BlockId(2)]);
+    }
+
+    #[test]
+    fn merge_linear_goto_chain() {
+        let assign = |x: &str| Statement::Assign { lhs: x.into(), rhs: Exp::mk_true() };
+        let mut blocks = BTreeMap::new();
+        blocks.insert(
+            BlockId(0),
+            Block { statements: vec![assign("a")], terminator: Terminator::Goto(BlockId(1)) },
+        );
+        blocks.insert(
+            BlockId(1),
+            Block { statements: vec![assign("b")], terminator: Terminator::Goto(BlockId(2)) },
+        );
+        blocks.insert(
+            BlockId(2),
+            Block { statements: vec![assign("c")], terminator: Terminator::Return },
+        );
+
+        merge_goto_chains(BlockId(0), &mut blocks);
+        assert_eq!(blocks.len(), 1);
+        let block = &blocks[&BlockId(0)];
+        let lhs: Vec<_> = block
+            .statements
+            .iter()
+            .map(|s| match s {
+                Statement::Assign { lhs, .. } => lhs.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(lhs, vec!["a".into(), "b".into(), "c".into()]);
+        assert!(matches!(block.terminator, Terminator::Return));
+    }
+
+    #[test]
+    fn merge_keeps_join_points() {
+        let goto = |tgt| Block { statements: vec![], terminator: Terminator::Goto(BlockId(tgt)) };
+        let mut blocks = BTreeMap::new();
+        blocks.insert(
+            BlockId(0),
+            Block {
+                statements: vec![],
+                terminator: Terminator::if_(
+                    Exp::Var("c".into()),
+                    Terminator::Goto(BlockId(1)),
+                    Terminator::Goto(BlockId(2)),
+                ),
+            },
+        );
+        blocks.insert(BlockId(1), goto(3));
+        blocks.insert(BlockId(2), goto(3));
+        blocks.insert(BlockId(3), goto(0));
+
+        merge_goto_chains(BlockId(0), &mut blocks);
+        assert_eq!(blocks.len(), 4);
+    }
+
+    #[test]
+    fn conj_and_disj_all() {
+        let var = |v: &str| Exp::Var(v.into());
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        assert_eq!(print(&Exp::conj_all(vec![])), "true");
+        assert_eq!(print(&Exp::disj_all(vec![])), "false");
+        assert_eq!(print(&Exp::conj_all(vec![var("a")])), "a");
+        assert_eq!(print(&Exp::disj_all(vec![var("a")])), "a");
+        assert_eq!(print(&Exp::conj_all(vec![var("a"), var("b"), var("c")])), "a && b && c");
+        assert_eq!(print(&Exp::disj_all(vec![var("a"), var("b"), var("c")])), "a || b || c");
+    }
+
+    #[test]
+    fn float_int_casts() {
+        let ty = |n: &str| Type::TConstructor(n.into());
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+        let cast = |from, to| numeric_cast(Exp::Var("x".into()), &ty(from), &ty(to)).unwrap();
+
+        assert_eq!(print(&cast("int64", "double")), "double_of_int (Int64.to_int x)");
+        assert_eq!(print(&cast("double", "int32")), "Int32.of_int (truncate_double x)");
+        assert_eq!(print(&cast("single", "double")), "double_of_single x");
+        assert!(numeric_cast(Exp::Var("x".into()), &ty("int64"), &ty("int32")).is_none());
+    }
+
+    #[test]
+    fn collect_qnames() {
+        let some = QName::qualified("Type", "Some");
+        let len = QName::qualified("Seq", "length");
+        let none = QName::qualified("Type", "None");
+
+        // match Some(Seq.length s) with None -> 0 | Some(n) -> n end
+        let exp = Exp::Match(
+            box Exp::Constructor {
+                ctor: some.clone(),
+                args: vec![Exp::Call(box Exp::QVar(len.clone()), vec![Exp::Var("s".into())])],
+            },
+            vec![
+                (Pattern::ConsP(none.clone(), vec![]), None, Exp::Const(Constant::Int(0, None))),
+                (
+                    Pattern::ConsP(some.clone(), vec![Pattern::VarP("n".into())]),
+                    None,
+                    Exp::Var("n".into()),
+                ),
+            ],
+        );
+
+        assert_eq!(exp.qnames(), vec![some, len, none].into_iter().collect());
+        assert!(Exp::Var("s".into()).qnames().is_empty());
+    }
+
+    #[test]
+    fn match_or_absurd() {
+        let option = TyDecl {
+            ty_name: "option".into(),
+            ty_params: vec![],
+            ty_constructors: vec![
+                ("None".into(), vec![]),
+                ("Some".into(), vec![Type::Integer]),
+            ],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let decls = vec![&option];
+        let cons = |c: &str, args| Pattern::ConsP(c.into(), args);
+        let var = |v: &str| Exp::Var(v.into());
+        let arm = |pat| (pat, None, var("x"));
+
+        let partial = Exp::match_or_absurd(var("o"), vec![arm(cons("Some", vec![]))], &decls);
+        assert!(matches!(&partial, Exp::Match(_, brs)
+            if brs.len() == 2 && matches!(brs[1], (Pattern::Wildcard, None, Exp::Absurd))));
+
+        let total = Exp::match_or_absurd(
+            var("o"),
+            vec![arm(cons("Some", vec![Pattern::Wildcard])), arm(cons("None", vec![]))],
+            &decls,
+        );
+        assert!(matches!(&total, Exp::Match(_, brs) if brs.len() == 2));
+
+        let wildcard = Exp::match_or_absurd(
+            var("o"),
+            vec![arm(cons("Some", vec![])), arm(Pattern::VarP("y".into()))],
+            &decls,
+        );
+        assert!(matches!(&wildcard, Exp::Match(_, brs) if brs.len() == 2));
+
+        let unknown = Exp::match_or_absurd(var("l"), vec![arm(cons("Nil", vec![]))], &decls);
+        assert!(matches!(&unknown, Exp::Match(_, brs) if brs.len() == 2));
+    }
+
+    #[test]
+    fn borrows_are_rejected_in_logic() {
+        let logic = |body| Logic {
+            name: "f".into(),
+            retty: Type::Integer,
+            args: vec![("x".into(), Type::Integer)],
+            body,
+            contract: Contract::new(),
+        };
+        let x = || Exp::Var("x".into());
+
+        assert_eq!(logic(x()).check_logic(), Ok(()));
+
+        let borrow = logic(Exp::Current(box Exp::BorrowMut(box x())));
+        let err = borrow.check_logic().unwrap_err();
+        assert_eq!(err, ProgramOnlyConstruct { function: "f".into(), construct: "borrow_mut" });
+        assert_eq!(
+            err.to_string(),
+            "logic function `f` uses `borrow_mut`, which is only allowed in programs"
+        );
+
+        let mut in_contract = logic(x());
+        in_contract.contract.ensures.push(Exp::BorrowMut(box x()));
+        assert!(in_contract.check_logic().is_err());
+    }
+
+    #[test]
+    fn constants_equal_modulo_type() {
+        let u8_ty = || Some(Type::TConstructor("uint8".into()));
+        let u16_ty = || Some(Type::TConstructor("uint16".into()));
+
+        assert_eq!(Constant::Uint(5, u8_ty()), Constant::Uint(5, None));
+        assert_eq!(Constant::Uint(5, None), Constant::Uint(5, u8_ty()));
+        assert_eq!(Constant::Uint(5, u8_ty()), Constant::Uint(5, u8_ty()));
+        assert_ne!(Constant::Uint(5, u8_ty()), Constant::Uint(5, u16_ty()));
+        assert_ne!(Constant::Uint(5, None), Constant::Uint(6, None));
+        assert_ne!(Constant::Uint(5, None), Constant::Int(5, None));
+
+        let folded = Exp::Const(Constant::Uint(5, None));
+        assert!(Exp::Const(Constant::Uint(5, u8_ty())).alpha_eq(&folded));
+        let wide = Exp::Const(Constant::Uint(5, u16_ty()));
+        assert!(!wide.alpha_eq(&Exp::Const(Constant::Uint(5, u8_ty()))));
+    }
+
+    #[test]
+    fn is_variant() {
+        let option = TyDecl {
+            ty_name: "option".into(),
+            ty_params: vec![],
+            ty_constructors: vec![("None".into(), vec![]), ("Some".into(), vec![Type::Integer])],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let wrapper = TyDecl {
+            ty_name: "wrapper".into(),
+            ty_params: vec![],
+            ty_constructors: vec![("Wrap".into(), vec![Type::Integer, Type::Bool])],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let decls = vec![&option, &wrapper];
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        let some = Exp::is_variant(Exp::Var("x".into()), "Some".into(), &decls).unwrap();
+        assert_eq!(print(&some), "match (x) with\n  | Some(_) -> true\n  | _ -> false\n  end\n");
+
+        let wrap = Exp::is_variant(Exp::Var("w".into()), "Wrap".into(), &decls).unwrap();
+        assert_eq!(print(&wrap), "true");
+
+        // The arity of an unknown constructor can't be guessed
+        assert!(Exp::is_variant(Exp::Var("x".into()), "Other".into(), &decls).is_none());
+    }
+
+    #[test]
+    fn implies_nests_to_the_right() {
+        let var = |v: &str| Exp::Var(v.into());
+        let is_var = |e: &Exp, v: &str| matches!(e, Exp::Var(x) if *x == v.into());
+
+        assert!(is_var(&Exp::implies(vec![], var("goal")), "goal"));
+
+        match Exp::implies(vec![var("p")], var("goal")) {
+            Exp::Impl(box h, box g) => assert!(is_var(&h, "p") && is_var(&g, "goal")),
+            e => panic!("expected an implication, got {:?}", e),
+        }
+
+        match Exp::implies(vec![var("p1"), var("p2"), var("p3")], var("goal")) {
+            Exp::Impl(box h1, box Exp::Impl(box h2, box Exp::Impl(box h3, box g))) => {
+                assert!(is_var(&h1, "p1") && is_var(&h2, "p2") && is_var(&h3, "p3"));
+                assert!(is_var(&g, "goal"));
+            }
+            e => panic!("expected three nested implications, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn qnames_sort_by_module_then_name() {
+        let mut names = vec![
+            QName::qualified("Seq", "length"),
+            QName::from("zero"),
+            QName::qualified("Int", "abs"),
+            QName::qualified("Seq", "get"),
+            QName::from("abs"),
+        ];
+        names.sort();
+
+        assert_eq!(
+            names,
+            vec![
+                QName::from("abs"),
+                QName::from("zero"),
+                QName::qualified("Int", "abs"),
+                QName::qualified("Seq", "get"),
+                QName::qualified("Seq", "length"),
+            ]
+        );
+    }
+
+    #[test]
+    fn subst_covers_all_variants() {
+        let v = || Exp::Var("old_v".into());
+        let exps = vec![
+            Exp::Current(box v()),
+            Exp::Final(box v()),
+            Exp::Old(box v()),
+            Exp::let_(Pattern::Wildcard, v(), v()),
+            v(),
+            Exp::QVar("q".into()),
+            Exp::RecUp { record: box v(), label: "f".into(), val: box v() },
+            Exp::RecField { record: box v(), label: "f".into() },
+            Exp::Record { ctor: "r".into(), fields: vec![("f".into(), v())] },
+            Exp::Tuple(vec![v(), v()]),
+            Exp::TupleField { tuple: box v(), idx: 0, arity: 2 },
+            Exp::Index { base: box v(), index: box v() },
+            Exp::Constructor { ctor: "C".into(), args: vec![v()] },
+            Exp::BorrowMut(box v()),
+            Exp::Const(Constant::Int(0, None)),
+            Exp::BinaryOp(BinOp::Add, box v(), box v()),
+            Exp::UnaryOp(UnOp::Neg, box v()),
+            Exp::Call(box v(), vec![v()]),
+            Exp::Verbatim("verbatim".into(), HashSet::new()),
+            Exp::Raw { src: "f $0".into(), subst_points: vec![("$0".into(), "old_v".into())] },
+            Exp::Abs("y".into(), None, box v()),
+            Exp::Match(box v(), vec![(Pattern::Wildcard, Some(v()), v())]),
+            Exp::Attr("attr".into(), box v()),
+            Exp::Assume(box v(), box v()),
+            Exp::Assert { goal: box v(), by: Some(box v()), body: box v() },
+            Exp::Absurd,
+            Exp::Result,
+            Exp::Impl(box v(), box v()),
+            Exp::Forall(vec![("y".into(), Type::Integer)], box v()),
+            Exp::Exists(vec![("y".into(), Type::Integer)], box v()),
+        ];
+
+        // Make sure every variant has a case above: this stops compiling when one is added
+        for exp in &exps {
+            match exp {
+                Exp::Current(_)
+                | Exp::Final(_)
+                | Exp::Old(_)
+                | Exp::Let { .. }
+                | Exp::Var(_)
+                | Exp::QVar(_)
+                | Exp::RecUp { .. }
+                | Exp::RecField { .. }
+                | Exp::Record { .. }
+                | Exp::Tuple(_)
+                | Exp::TupleField { .. }
+                | Exp::Index { .. }
+                | Exp::Constructor { .. }
+                | Exp::BorrowMut(_)
+                | Exp::Const(_)
+                | Exp::BinaryOp(_, _, _)
+                | Exp::UnaryOp(_, _)
+                | Exp::Call(_, _)
+                | Exp::Verbatim(_, _)
+                | Exp::Raw { .. }
+                | Exp::Abs(..)
+                | Exp::Match(_, _)
+                | Exp::Attr(_, _)
+                | Exp::Assume(_, _)
+                | Exp::Assert { .. }
+                | Exp::Absurd
+                | Exp::Result
+                | Exp::Impl(_, _)
+                | Exp::Forall(_, _)
+                | Exp::Exists(_, _) => {}
+            }
+        }
+
+        let mut subst = HashMap::new();
+        subst.insert("old_v".into(), Exp::Var("new_v".into()));
+        for mut exp in exps {
+            let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+            let before = print(&exp);
+            exp.subst(&subst);
+            let after = print(&exp);
+
+            assert!(!after.contains("old_v"), "substitution missed a leaf of `{}`", before);
+            assert_eq!(before.contains("old_v"), after.contains("new_v"), "in `{}`", before);
+        }
+    }
+
+    #[test]
+    fn verbatim_validation() {
+        let logic = |body| {
+            let body = Exp::Verbatim(body, HashSet::new());
+            Decl::LogicDecl(Logic {
+                name: "f".into(),
+                retty: Type::Bool,
+                args: vec![],
+                body,
+                contract: Contract::new(),
+            })
+        };
+
+        let balanced = "match (x) with | C(a, b) -> a [0] = (* (unclosed *) \")\" | _ -> true end";
+        assert_eq!(logic(balanced.into()).check_verbatims(), Ok(()));
+
+        let unbalanced = logic("forall i : int . (i < len s".into()).check_verbatims().unwrap_err();
+        assert_eq!(unbalanced.problem, "unclosed `(`");
+        assert_eq!(
+            unbalanced.to_string(),
+            "malformed Why3 in `f`: unclosed `(` in `forall i : int . (i < len s`"
+        );
+
+        let err = logic("match x with | _ -> (true end)".into()).check_verbatims().unwrap_err();
+        assert_eq!(err.problem, "`end` closes `(`");
+
+        // The first `*)` only closes the inner comment
+        let nested = "(* outer (* inner *) still a comment ) *) x";
+        assert_eq!(logic(nested.into()).check_verbatims(), Ok(()));
+        let err = logic("(* outer (* inner *) x".into()).check_verbatims().unwrap_err();
+        assert_eq!(err.problem, "unterminated comment");
+    }
+
+    #[test]
+    fn constructor_pattern_arity() {
+        let pair = TyDecl {
+            ty_name: "pair".into(),
+            ty_params: vec![],
+            ty_constructors: vec![("Pair".into(), vec![Type::Bool, Type::Bool])],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let decls = vec![pair];
+        let pair_p = |args| Pattern::ConsP("Pair".into(), args);
+        let var = |v: &str| Pattern::VarP(v.into());
+
+        let good = Pattern::TupleP(vec![pair_p(vec![Pattern::mk_true(), var("b")]), var("c")]);
+        assert_eq!(good.check_arity(&decls), Ok(()));
+
+        let nested = pair_p(vec![pair_p(vec![var("a")]), Pattern::Wildcard]);
+        let err = nested.check_arity(&decls).unwrap_err();
+        assert_eq!(err, ArityMismatch { ctor: "Pair".into(), expected: 2, found: 1 });
+        assert_eq!(err.to_string(), "constructor `Pair` has 2 fields, but its pattern has 1");
+
+        let in_tuple = Pattern::TupleP(vec![Pattern::ConsP("True".into(), vec![var("x")])]);
+        assert!(in_tuple.check_arity(&decls).is_err());
+    }
+
+    #[test]
+    fn short_circuit_guards_right_operands() {
+        let var = |v: &str| Exp::Var(v.into());
+        let div = Exp::BinaryOp(BinOp::Div, box var("x"), box var("y"));
+        let zero = || box Exp::Const(Constant::Int(0, None));
+        let nonzero = Exp::BinaryOp(BinOp::Ne, box var("y"), zero());
+        let positive = Exp::BinaryOp(BinOp::Gt, box div, zero());
+        let logic = Exp::BinaryOp(
+            BinOp::Or,
+            box var("b"),
+            box Exp::BinaryOp(BinOp::And, box nonzero, box positive),
+        );
+        let show = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+        let show_stmt = |s: &Statement| format!("{}", printer::FormatEnv::default().to(s));
+        assert_eq!(show(&logic), "b || y <> 0 && x / y > 0");
+
+        let mut program = logic.clone();
+        program.short_circuit();
+        let guarded = "match (b) with\n  | True -> true\n  | False -> match (y <> 0) with\n    \
+                       | True -> x / y > 0\n    | False -> false\n    end\n\n  end\n";
+        assert_eq!(show(&program), guarded);
+
+        // Assignments are program code, assumptions are logic
+        let mut assign = Statement::Assign { lhs: "r".into(), rhs: logic.clone() };
+        assign.short_circuit();
+        assert_eq!(show_stmt(&assign), format!("r <- ({})", guarded));
+        let mut assume = Statement::Assume(logic);
+        assume.short_circuit();
+        assert_eq!(show_stmt(&assume), "assume { b || y <> 0 && x / y > 0 }");
+    }
+
+    #[test]
+    fn subst_qname_rewrites_globals() {
+        let f = || Exp::QVar(QName::qualified("M", "f"));
+        let call = Exp::Call(box f(), vec![Exp::Var("f".into())]);
+        let some = Exp::Constructor { ctor: "Some".into(), args: vec![call, f()] };
+        let mut exp = Exp::Match(
+            box some,
+            vec![(Pattern::ConsP("Some".into(), vec![Pattern::Wildcard]), None, f())],
+        );
+
+        let mut map = HashMap::new();
+        map.insert(QName::qualified("M", "f"), QName::qualified("M", "f_u32"));
+        map.insert("Some".into(), QName::qualified("Option", "Some"));
+        exp.subst_qname(&map);
+
+        assert_eq!(
+            format!("{}", printer::FormatEnv::default().to(&exp)),
+            "match (Option.Some(M.f_u32 f, M.f_u32)) with\n  | Option.Some(_) -> M.f_u32\n  end\n"
+        );
+    }
+
+    #[test]
+    fn nested_tuple_let() {
+        let var = |v: &str| Exp::Var(v.into());
+        let pat = Pattern::TupleP(vec![
+            Pattern::VarP("a".into()),
+            Pattern::TupleP(vec![Pattern::VarP("b".into()), Pattern::VarP("c".into())]),
+        ]);
+        let idents = |vs: &[&str]| vs.iter().map(|v| LocalIdent::from(*v)).collect::<HashSet<_>>();
+        assert_eq!(pat.binders(), idents(&["a", "b", "c"]));
+
+        let sum = Exp::BinaryOp(BinOp::Add, box var("a"), box var("c"));
+        let body = Exp::BinaryOp(BinOp::Add, box sum, box var("d"));
+        let mut exp = Exp::let_(pat, var("p"), body);
+        assert_eq!(exp.fvs(), idents(&["p", "d"]));
+
+        let mut subst = HashMap::new();
+        for v in &["a", "b", "c", "d", "p"] {
+            subst.insert(LocalIdent::from(*v), var(&format!("{}'", v)));
+        }
+        exp.subst(&subst);
+        assert_eq!(
+            format!("{}", printer::FormatEnv::default().to(&exp)),
+            "let (a, (b, c)) = p' in a + c + d'"
+        );
+    }
+
+    #[test]
+    fn contains_var_respects_binders() {
+        let var = |v: &str| Exp::Var(v.into());
+        let x: LocalIdent = "x".into();
+        let add = |l, r| Exp::BinaryOp(BinOp::Add, box l, box r);
+
+        assert!(add(var("y"), var("x")).contains_var(&x));
+        assert!(!add(var("y"), Exp::QVar("x".into())).contains_var(&x));
+
+        let shadowed = Exp::let_(Pattern::VarP("x".into()), var("y"), var("x"));
+        assert!(!shadowed.contains_var(&x));
+        let in_arg = Exp::let_(Pattern::VarP("x".into()), var("x"), var("x"));
+        assert!(in_arg.contains_var(&x));
+
+        let tuple = Pattern::TupleP(vec![Pattern::Wildcard, Pattern::VarP("x".into())]);
+        let arm = |pat| Exp::Match(box var("s"), vec![(pat, Some(var("x")), var("z"))]);
+        assert!(!arm(tuple).contains_var(&x));
+        assert!(arm(Pattern::Wildcard).contains_var(&x));
+
+        let forall = Exp::Forall(vec![("x".into(), Type::Integer)], box var("x"));
+        assert!(!forall.contains_var(&x));
+        assert!(!Exp::Abs("x".into(), None, box var("x")).contains_var(&x));
+        assert!(Exp::Abs("y".into(), None, box var("x")).contains_var(&x));
+
+        for e in &[shadowed, in_arg, forall, add(var("x"), var("y"))] {
+            assert_eq!(e.contains_var(&x), e.fvs().contains(&x));
+        }
+    }
+
+    #[test]
+    fn result_binder() {
+        let zero = box Exp::Const(Constant::Int(0, None));
+        let mut post = Exp::BinaryOp(BinOp::Gt, box Exp::Var("result".into()), zero);
+
+        let mut subst = HashMap::new();
+        subst.insert("result".into(), Exp::Result);
+        post.subst(&subst);
+        assert!(post.fvs().is_empty());
+
+        subst.insert("result".into(), Exp::Var("x".into()));
+        post.subst(&subst);
+        assert_eq!(format!("{}", printer::FormatEnv::default().to(&post)), "result > 0");
+    }
+
+    fn function(blocks: Vec<Block>) -> Function {
+        Function {
+            name: "f".into(),
+            retty: Type::Integer,
+            args: vec![("x".into(), Type::Integer)],
+            vars: Vec::new(),
+            blocks: blocks.into_iter().enumerate().map(|(i, b)| (BlockId(i), b)).collect(),
+            invariants: BTreeMap::new(),
+            contract: Contract::new(),
+        }
+    }
+
+    #[test]
+    fn acyclic_functions_structure() {
+        let var = |v: &str| Exp::Var(v.into());
+        let assign = |lhs: LocalIdent, rhs| Statement::Assign { lhs, rhs };
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+        let ret = LocalIdent::Anon(0, None);
+        let returning = |v| Block {
+            statements: vec![assign(ret.clone(), var(v))],
+            terminator: Terminator::Return,
+        };
+
+        let straight = function(vec![
+            Block {
+                statements: vec![assign("y".into(), var("x"))],
+                terminator: Terminator::Goto(BlockId(1)),
+            },
+            returning("y"),
+        ]);
+        let body = straight.structured_body().unwrap();
+        assert_eq!(print(&body), "let y = x in\nlet _0 = y in\n_0");
+
+        let branch = function(vec![
+            Block {
+                statements: Vec::new(),
+                terminator: Terminator::if_(
+                    var("x"),
+                    Terminator::Goto(BlockId(1)),
+                    Terminator::Goto(BlockId(2)),
+                ),
+            },
+            returning("a"),
+            returning("b"),
+        ]);
+        let body = branch.structured_body().unwrap();
+        assert_eq!(
+            print(&body),
+            "match (x) with\n  | True -> let _0 = a in _0\n  | False -> let _0 = b in _0\n  end\n"
+        );
+    }
+
+    #[test]
+    fn specs_structure_as_sequences() {
+        let var = |v: &str| Exp::Var(v.into());
+        let assign = |lhs: &str, rhs| Statement::Assign { lhs: lhs.into(), rhs };
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+        let zero = box Exp::Const(Constant::Int(0, None));
+
+        // A borrow, resolved by an assumption before the result is checked
+        let borrowing = function(vec![Block {
+            statements: vec![
+                assign("b", Exp::BorrowMut(box var("x"))),
+                assign("x", Exp::Final(box var("b"))),
+                Statement::Assume(Exp::QVar("drop_mut_ref".into()).app_to(var("b"))),
+                Statement::Assert { goal: Exp::BinaryOp(BinOp::Ge, box var("x"), zero), by: None },
+                Statement::Assign { lhs: LocalIdent::Anon(0, None), rhs: var("x") },
+            ],
+            terminator: Terminator::Return,
+        }]);
+        let body = borrowing.structured_body().unwrap();
+        let expected = [
+            "let b = borrow_mut x in",
+            "let x =  ^ b in",
+            "assume { drop_mut_ref b };",
+            "assert { x >= 0 };",
+            "let _0 = x in _0",
+        ];
+        assert_eq!(print(&body), expected.join("\n"));
+        assert_eq!(body.fvs(), ["x"].iter().map(|v| LocalIdent::from(*v)).collect());
+
+        // As an operand, the sequence is parenthesized
+        let by = Some(box var("p"));
+        let seq = Exp::Assert { goal: box var("x"), by, body: box var("y") };
+        let sum = Exp::BinaryOp(BinOp::Add, box seq, box var("z"));
+        assert_eq!(print(&sum), "(assert { x by p };\ny) + z");
+    }
+
+    #[test]
+    fn loops_stay_cfgs() {
+        let var = |v: &str| Exp::Var(v.into());
+        let looping = function(vec![
+            Block { statements: Vec::new(), terminator: Terminator::Goto(BlockId(1)) },
+            Block {
+                statements: Vec::new(),
+                terminator: Terminator::if_(
+                    var("x"),
+                    Terminator::Goto(BlockId(1)),
+                    Terminator::Return,
+                ),
+            },
+        ]);
+        assert!(looping.structured_body().is_none());
+
+        // The join block would be copied into both branches
+        let diamond = function(vec![
+            Block {
+                statements: Vec::new(),
+                terminator: Terminator::if_(
+                    var("x"),
+                    Terminator::Goto(BlockId(1)),
+                    Terminator::Goto(BlockId(1)),
+                ),
+            },
+            Block { statements: Vec::new(), terminator: Terminator::Return },
+        ]);
+        assert!(diamond.structured_body().is_none());
+        assert!(diamond.into_structured().is_err());
+    }
+
+    #[test]
+    fn duplicate_quantifier_binders() {
+        let p = Exp::Var("p".into());
+        let forall = |vs: &[&str]| {
+            let binders = vs.iter().map(|v| ((*v).into(), Type::Integer)).collect();
+            Exp::Forall(binders, box p.clone())
+        };
+
+        assert_eq!(forall(&["x", "y"]).check_binders(), Ok(()));
+        // Shadowing the binder of an enclosing quantifier is fine
+        let nested = Exp::Exists(vec![("x".into(), Type::Bool)], box forall(&["x"]));
+        assert_eq!(nested.check_binders(), Ok(()));
+
+        let dup = Exp::Impl(box p.clone(), box forall(&["x", "y", "x"]));
+        let err = dup.check_binders().unwrap_err();
+        assert_eq!(err, DuplicateBinder { binder: "x".into() });
+        assert_eq!(err.to_string(), "quantifier binds `x` more than once");
+    }
+
+    #[test]
+    fn canonical_hash_agrees_with_alpha_eq() {
+        let var = |v: &str| Exp::Var(v.into());
+        let forall = |x: &str, body| Exp::Forall(vec![(x.into(), Type::Integer)], box body);
+        let eq = |l, r| Exp::BinaryOp(BinOp::Eq, box l, box r);
+        let let_in = |x: &str, arg, body| Exp::let_(Pattern::VarP(x.into()), arg, body);
+
+        let pairs = vec![
+            (forall("x", eq(var("x"), var("y"))), forall("z", eq(var("z"), var("y")))),
+            (let_in("a", var("b"), var("a")), let_in("c", var("b"), var("c"))),
+            (Exp::Abs("x".into(), None, box var("x")), Exp::Abs("y".into(), None, box var("y"))),
+        ];
+        for (l, r) in &pairs {
+            assert!(l.alpha_eq(r));
+            assert_eq!(l.canonical_hash(), r.canonical_hash());
+        }
+
+        let distinct = vec![
+            forall("x", eq(var("x"), var("y"))),
+            forall("x", eq(var("y"), var("x"))),
+            forall("x", eq(var("x"), var("z"))),
+            Exp::Exists(vec![("x".into(), Type::Integer)], box eq(var("x"), var("y"))),
+            Exp::Forall(vec![("x".into(), Type::Bool)], box eq(var("x"), var("y"))),
+            let_in("a", var("b"), var("b")),
+            Exp::Const(Constant::Int(1, None)),
+            Exp::Const(Constant::Int(2, None)),
+        ];
+        let hashes: HashSet<_> = distinct.iter().map(Exp::canonical_hash).collect();
+        assert_eq!(hashes.len(), distinct.len());
+
+        let mut cache = HashMap::new();
+        cache.insert(ExpKey(pairs[0].0.clone()), 1);
+        assert_eq!(cache.get(&ExpKey(pairs[0].1.clone())), Some(&1));
+        assert_eq!(cache.get(&ExpKey(distinct[1].clone())), None);
+    }
+
+    #[test]
+    fn type_subst_instantiates_variables() {
+        let cons = |c: &str| Type::TConstructor(c.into());
+        let list = |t| Type::TApp(box cons("list"), vec![t]);
+        let map: HashMap<_, _> = vec![("a".to_owned(), cons("uint32"))].into_iter().collect();
+
+        let mut ty = Type::TFun(box list(Type::TVar("a".into())), box Type::TVar("b".into()));
+        let mut used = BTreeSet::new();
+        ty.find_used_types(&mut used);
+        assert_eq!(used.into_iter().collect::<Vec<_>>(), vec![QName::from("list")]);
+
+        ty.subst(&map);
+        assert_eq!(ty, Type::TFun(box list(cons("uint32")), box Type::TVar("b".into())));
+        let mut used = BTreeSet::new();
+        ty.find_used_types(&mut used);
+        assert_eq!(used.into_iter().collect::<Vec<_>>(), vec!["list".into(), "uint32".into()]);
+    }
+
+    #[test]
+    fn cfg_targets_must_exist() {
+        let block = |terminator| Block { statements: Vec::new(), terminator };
+        let cfg = |blocks: Vec<(usize, Block)>| -> BTreeMap<_, _> {
+            blocks.into_iter().map(|(id, b)| (BlockId(id), b)).collect()
+        };
+        let branch = |then, else_| Terminator::if_(Exp::Var("c".into()), then, else_);
+
+        let valid = cfg(vec![
+            (0, block(branch(Terminator::Goto(BlockId(1)), Terminator::Return))),
+            (1, block(Terminator::Return)),
+        ]);
+        assert_eq!(validate_cfg(BlockId(0), &valid), Ok(()));
+        assert_eq!(validate_cfg(BlockId(2), &valid), Err(CfgError::MissingEntry(BlockId(2))));
+
+        let nested = branch(Terminator::Goto(BlockId(3)), Terminator::Absurd);
+        let nested = branch(Terminator::Return, nested);
+        let dangling = cfg(vec![(0, block(Terminator::Goto(BlockId(1)))), (1, block(nested))]);
+        let err = validate_cfg(BlockId(0), &dangling).unwrap_err();
+        assert_eq!(err, CfgError::DanglingTarget { block: BlockId(1), target: BlockId(3) });
+        assert_eq!(err.to_string(), "block BB1 jumps to BB3, which doesn't exist");
+    }
+
+    #[test]
+    fn fluent_and_or_fold_constants() {
+        let var = |v: &str| Exp::Var(v.into());
+        let print = |e: &Exp| format!("{}", printer::FormatEnv::default().to(e));
+
+        assert_eq!(print(&var("a").and(var("b")).and(var("c"))), "a && b && c");
+        assert_eq!(print(&var("a").or(var("b")).or(var("c"))), "a || b || c");
+        assert_eq!(print(&Exp::mk_true().and(var("a")).and(Exp::mk_true())), "a");
+        assert_eq!(print(&var("a").and(Exp::mk_false()).and(var("b"))), "false");
+        assert_eq!(print(&Exp::mk_false().or(var("a")).or(Exp::mk_false())), "a");
+        assert_eq!(print(&var("a").or(Exp::mk_true())), "true");
+        assert_eq!(print(&var("a").and(var("b")).or(var("c"))), "a && b || c");
+    }
 }
@@ -0,0 +1,273 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::*;
+
+/// Maps the types and modules a declaration mentions to the Why3 theories which provide them.
+/// The default table covers the standard library theories used by translated programs, and
+/// further entries can be registered with [`Theories::with_type`] and [`Theories::with_module`].
+pub struct Theories {
+    /// Theories providing unqualified type constructors, like `uint32`
+    types: HashMap<String, String>,
+    /// Theories providing the module prefixes of qualified names, like `UInt32.to_int`
+    modules: HashMap<String, String>,
+}
+
+impl Default for Theories {
+    fn default() -> Self {
+        let mut theories = Theories { types: HashMap::new(), modules: HashMap::new() };
+        for (ty, module, theory) in &[
+            ("int8", "Int8", "mach.int.Int8"),
+            ("int16", "Int16", "mach.int.Int16"),
+            ("int32", "Int32", "mach.int.Int32"),
+            ("int64", "Int64", "mach.int.Int64"),
+            ("int128", "Int128", "mach.int.Int128"),
+            ("uint8", "UInt8", "mach.int.UInt8"),
+            ("uint16", "UInt16", "mach.int.UInt16"),
+            ("uint32", "UInt32", "mach.int.UInt32"),
+            ("uint64", "UInt64", "mach.int.UInt64"),
+            ("uint128", "UInt128", "mach.int.UInt128"),
+            ("single", "Single", "floating_point.Single"),
+            ("double", "Double", "floating_point.Double"),
+            ("string", "String", "string.String"),
+        ] {
+            theories = theories.with_type(ty, theory).with_module(module, theory);
+        }
+        // `usize` and `isize` are defined by the prelude as 64 bit integers, whose operations
+        // come from the machine integer theories
+        theories
+            .with_type("int", "mach.int.Int")
+            .with_type("usize", "mach.int.UInt64")
+            .with_type("isize", "mach.int.Int64")
+            .with_module("Rounding", "floating_point.Rounding")
+            .with_module("FromInt", "real.FromInt")
+            .with_module("Truncate", "real.Truncate")
+    }
+}
+
+impl Theories {
+    /// Register `theory` as providing the unqualified type `ty`
+    pub fn with_type(mut self, ty: &str, theory: &str) -> Self {
+        self.types.insert(ty.to_owned(), theory.to_owned());
+        self
+    }
+
+    /// Register `theory` as providing the names qualified by `module`
+    pub fn with_module(mut self, module: &str, theory: &str) -> Self {
+        self.modules.insert(module.to_owned(), theory.to_owned());
+        self
+    }
+
+    /// The theories needed by `decls`, in a deterministic order
+    pub fn required<'a>(&self, decls: impl IntoIterator<Item = &'a Decl>) -> BTreeSet<String> {
+        let mut cx = Collector { theories: self, required: BTreeSet::new() };
+        decls.into_iter().for_each(|d| cx.decl(d));
+        cx.required
+    }
+
+    /// The `use` lines importing the theories needed by `decls`
+    pub fn imports<'a>(&self, decls: impl IntoIterator<Item = &'a Decl>) -> String {
+        self.required(decls).iter().map(|th| format!("use {}\n", th)).collect()
+    }
+}
+
+struct Collector<'a> {
+    theories: &'a Theories,
+    required: BTreeSet<String>,
+}
+
+impl Collector<'_> {
+    fn require(&mut self, theory: &str) {
+        self.required.insert(theory.to_owned());
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::FunDecl(fun) => {
+                self.signature(&fun.args, &fun.retty, &fun.contract);
+                fun.vars.iter().for_each(|(_, ty)| self.ty(ty));
+                fun.invariants.values().flatten().for_each(|(_, inv)| self.exp(inv));
+                for block in fun.blocks.values() {
+                    block.statements.iter().for_each(|s| self.statement(s));
+                    self.terminator(&block.terminator);
+                }
+            }
+            Decl::LogicDecl(logic) | Decl::LetFun(logic) => {
+                self.signature(&logic.args, &logic.retty, &logic.contract);
+                self.exp(&logic.body);
+            }
+            Decl::ValDecl(val) => self.signature(&val.args, &val.retty, &val.contract),
+            Decl::TyDecl(decl) => {
+                let fields = decl.ty_constructors.iter().flat_map(|(_, tys)| tys);
+                let fields = fields.chain(decl.ty_fields.iter().map(|(_, ty)| ty));
+                fields.chain(&decl.ty_alias).for_each(|ty| self.ty(ty));
+            }
+            Decl::Lemma { body, .. } => self.exp(body),
+            Decl::Meta { .. } => {}
+        }
+    }
+
+    fn signature(&mut self, args: &[(LocalIdent, Type)], retty: &Type, contract: &Contract) {
+        args.iter().for_each(|(_, ty)| self.ty(ty));
+        self.ty(retty);
+        let clauses = contract.requires.iter().chain(&contract.ensures).chain(&contract.variant);
+        clauses.for_each(|e| self.exp(e));
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Assign { rhs, .. } => self.exp(rhs),
+            Statement::Call { func, args, .. } => {
+                self.qname(func);
+                args.iter().for_each(|a| self.exp(a));
+            }
+            Statement::Invariant { exp, .. } | Statement::Assume(exp) => self.exp(exp),
+            Statement::Assert { goal, by } => {
+                self.exp(goal);
+                by.iter().for_each(|p| self.exp(p));
+            }
+        }
+    }
+
+    fn terminator(&mut self, term: &Terminator) {
+        if let Terminator::Switch(scrut, brs) = term {
+            self.exp(scrut);
+            brs.iter().for_each(|(_, tgt)| self.terminator(tgt));
+        }
+    }
+
+    fn qname(&mut self, qn: &QName) {
+        if let Some(theory) = qn.module.first().and_then(|m| self.theories.modules.get(m)) {
+            self.required.insert(theory.clone());
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Integer => self.require("mach.int.Int"),
+            Type::Char => self.require("string.Char"),
+            Type::MutableBorrow(ty) => {
+                self.require("prelude.Prelude");
+                self.ty(ty)
+            }
+            Type::TConstructor(qn) if qn.module.is_empty() => {
+                if let Some(theory) = self.theories.types.get(&qn.name()) {
+                    self.required.insert(theory.clone());
+                }
+            }
+            Type::TConstructor(qn) => self.qname(qn),
+            Type::TApp(f, args) => {
+                self.ty(f);
+                args.iter().for_each(|a| self.ty(a));
+            }
+            Type::Tuple(tys) => tys.iter().for_each(|t| self.ty(t)),
+            Type::TFun(a, b) => {
+                self.ty(a);
+                self.ty(b)
+            }
+            Type::Bool | Type::TVar(_) => {}
+        }
+    }
+
+    fn exp(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Const(Constant::Int(_, ty)) | Exp::Const(Constant::Uint(_, ty)) => match ty {
+                Some(ty) => self.ty(ty),
+                None => self.require("mach.int.Int"),
+            },
+            Exp::Const(Constant::Char(_)) => self.require("string.Char"),
+            Exp::Const(Constant::Str(_)) => self.require("string.String"),
+            Exp::Const(Constant::Float(_, ty)) => match ty {
+                Some(ty) => self.ty(ty),
+                None => self.require("real.Real"),
+            },
+            // Integer operators come from `mach.int.Int`, even on machine integers
+            Exp::BinaryOp(op, _, _) if is_arithmetic(op) => self.require("mach.int.Int"),
+            Exp::UnaryOp(UnOp::Neg, _) => self.require("mach.int.Int"),
+            Exp::Current(_) | Exp::Final(_) | Exp::BorrowMut(_) => self.require("prelude.Prelude"),
+            // The `s[i]` notation comes from the theory of sequences
+            Exp::Index { .. } => self.require("seq.Seq"),
+            Exp::Forall(binders, _) | Exp::Exists(binders, _) => {
+                binders.iter().for_each(|(_, ty)| self.ty(ty))
+            }
+            Exp::QVar(qn) | Exp::Constructor { ctor: qn, .. } | Exp::Record { ctor: qn, .. } => {
+                self.qname(qn)
+            }
+            _ => {}
+        }
+        exp.subexps().into_iter().for_each(|e| self.exp(e));
+    }
+}
+
+fn is_arithmetic(op: &BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::Add
+            | BinOp::Sub
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::Lt
+            | BinOp::Le
+            | BinOp::Gt
+            | BinOp::Ge
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logic(args: Vec<(LocalIdent, Type)>, retty: Type, body: Exp) -> Decl {
+        Decl::LogicDecl(Logic { name: "f".into(), retty, args, body, contract: Contract::new() })
+    }
+
+    #[test]
+    fn machine_arithmetic_imports_its_theory() {
+        let u32_ty = || Type::TConstructor("uint32".into());
+        let one = Exp::Const(Constant::Uint(1, Some(u32_ty())));
+        let body = Exp::BinaryOp(BinOp::Add, box Exp::Var("x".into()), box one);
+        let decls = vec![logic(vec![("x".into(), u32_ty())], u32_ty(), body)];
+
+        let required: Vec<_> = Theories::default().required(&decls).into_iter().collect();
+        assert_eq!(required, vec!["mach.int.Int", "mach.int.UInt32"]);
+        assert_eq!(
+            Theories::default().imports(&decls),
+            "use mach.int.Int\nuse mach.int.UInt32\n"
+        );
+    }
+
+    #[test]
+    fn qualified_names_and_custom_theories() {
+        let length = Exp::QVar(QName::qualified("Seq", "length"));
+        let body = Exp::Call(box length, vec![Exp::Var("s".into())]);
+        let args = vec![("s".into(), Type::TConstructor("seq".into()))];
+        let decls = vec![logic(args, Type::Bool, body)];
+
+        assert!(Theories::default().required(&decls).is_empty());
+
+        let theories =
+            Theories::default().with_module("Seq", "seq.Seq").with_type("seq", "seq.Seq");
+        let required: Vec<_> = theories.required(&decls).into_iter().collect();
+        assert_eq!(required, vec!["seq.Seq"]);
+    }
+
+    #[test]
+    fn wide_integers_import_their_theories() {
+        let ty = |t: &str| Type::TConstructor(t.into());
+        let max = Exp::Const(Constant::Uint(u128::MAX, Some(ty("uint128"))));
+        let decls = vec![logic(vec![("x".into(), ty("int128"))], ty("uint128"), max)];
+
+        let required: Vec<_> = Theories::default().required(&decls).into_iter().collect();
+        assert_eq!(required, vec!["mach.int.Int128", "mach.int.UInt128"]);
+    }
+
+    #[test]
+    fn indexing_imports_sequences() {
+        let seq = Type::TApp(box Type::TConstructor("seq".into()), vec![Type::Bool]);
+        let body = Exp::Index { base: box Exp::Var("s".into()), index: box Exp::Var("i".into()) };
+        let args = vec![("s".into(), seq), ("i".into(), Type::Integer)];
+        let decls = vec![logic(args, Type::Bool, body)];
+
+        let required: Vec<_> = Theories::default().required(&decls).into_iter().collect();
+        assert_eq!(required, vec!["mach.int.Int", "seq.Seq"]);
+    }
+}
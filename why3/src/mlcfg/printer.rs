@@ -11,6 +11,82 @@ pub struct FormatEnv<'a> {
     pub scope: &'a [String],
     /// Indentation to prefix lines with
     pub indent: usize,
+    /// Characters already printed on the current line past the indentation
+    pub column: usize,
+    /// Layout options
+    pub config: PrintConfig,
+    /// Namespacing of the names declared by the printed output
+    pub mangler: Option<&'a NameMangler>,
+}
+
+/// Options controlling the layout of the printed output.
+#[derive(Copy, Clone, Debug)]
+pub struct PrintConfig {
+    /// Number of spaces each nested block is indented by.
+    pub indent: usize,
+    /// Lines longer than this are broken at binary operators and call arguments, when possible.
+    pub width: usize,
+}
+
+impl Default for PrintConfig {
+    fn default() -> Self {
+        PrintConfig { indent: 2, width: usize::MAX }
+    }
+}
+
+/// Prefixes the names declared by the printed output, so they can't clash with the names of
+/// hand-written Why3 they are combined with. Names which are only referred to, like the
+/// `drop_*` helpers from the prelude, are left as they are.
+#[derive(Debug)]
+pub struct NameMangler {
+    prefix: String,
+    declared: BTreeSet<QName>,
+}
+
+impl NameMangler {
+    /// A mangler prefixing every name declared by `decls`
+    pub fn new<'a>(prefix: &str, decls: impl IntoIterator<Item = &'a Decl>) -> Self {
+        let mut mangler = NameMangler { prefix: prefix.to_owned(), declared: BTreeSet::new() };
+        for decl in decls {
+            match decl {
+                Decl::FunDecl(fun) => mangler.declare(&fun.name),
+                Decl::LogicDecl(logic) | Decl::LetFun(logic) => mangler.declare(&logic.name),
+                Decl::ValDecl(val) => mangler.declare(&val.name),
+                Decl::Lemma { name, .. } => mangler.declare(name),
+                Decl::TyDecl(ty) => {
+                    mangler.declare(&ty.ty_name);
+                    for (cons, _) in &ty.ty_constructors {
+                        let module = ty.ty_name.module.iter().cloned();
+                        mangler.declare(&QName::new(module, vec![cons.clone()]));
+                    }
+                }
+                Decl::Meta { .. } => {}
+            }
+        }
+        mangler
+    }
+
+    /// Also prefix `name`, for declarations printed outside of a module like drop predicates
+    pub fn declare(&mut self, name: &QName) {
+        self.declared.insert(name.clone());
+    }
+
+    fn mangle(&self, qname: &QName) -> Option<String> {
+        if !self.declared.contains(qname) {
+            return None;
+        }
+        let name = qname.name();
+        // Why3 tells constructors apart from other names by the case of their first letter
+        let mut chars = self.prefix.chars();
+        let first = chars.next().map(|c| {
+            if name.starts_with(char::is_uppercase) {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        });
+        Some(format!("{}{}_{}", first.into_iter().collect::<String>(), chars.as_str(), name))
+    }
 }
 
 /// A trait for displaying data given access to the environment.
@@ -22,7 +98,13 @@ pub trait EnvDisplay {
 
 impl<'a> Default for FormatEnv<'a> {
     fn default() -> Self {
-        FormatEnv { scope: &[], indent: 0 }
+        FormatEnv {
+            scope: &[],
+            indent: 0,
+            column: 0,
+            config: PrintConfig::default(),
+            mangler: None,
+        }
     }
 }
 
@@ -45,13 +127,34 @@ impl<'a> FormatEnv<'a> {
         F: FnMut(Self) -> std::fmt::Result,
     {
         self.indent += i;
+        self.column = 0;
         f(self)
     }
 
+    // The environment of an object printed right after `prefix`. Lines of `prefix` after the
+    // first carry their own indentation.
+    fn after(mut self, prefix: &str) -> Self {
+        match prefix.rfind('\n') {
+            Some(ix) => self.column = (prefix.len() - ix - 1).saturating_sub(self.indent),
+            None => self.column += prefix.len(),
+        }
+        self
+    }
+
     // Print the correct indentation for this line
     pub fn indent_line(self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:indent$}", "", indent = self.indent)
     }
+
+    // Whether every line of `s` fits in the configured width when `s` starts at the current
+    // column. Lines after the first carry their own indentation.
+    fn fits(self, s: &str) -> bool {
+        let mut lines = s.lines();
+        let first = lines.next().unwrap_or("");
+        let start = self.indent.saturating_add(self.column);
+        start.saturating_add(first.len()) <= self.config.width
+            && lines.all(|l| l.len() <= self.config.width)
+    }
 }
 
 impl<'a, D: EnvDisplay + ?Sized> fmt::Display for Print<'a, D> {
@@ -80,17 +183,67 @@ macro_rules! parens {
     };
 }
 
+impl EnvDisplay for MetaArg {
+    fn fmt(&self, _: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaArg::String(s) => write!(f, "\"{}\"", s),
+            MetaArg::Ident(i) => write!(f, "{}", i),
+            MetaArg::Integer(i) => write!(f, "{}", i),
+        }
+    }
+}
+
 impl EnvDisplay for Decl {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Decl::FunDecl(fun) => writeln!(f, "{}", fe.to(fun)),
             Decl::LogicDecl(log) => writeln!(f, "{}", fe.to(log)),
-            // Decl::TyDecl(t) => { writeln!(f, "{}", fe.to(t)) }
+            Decl::LetFun(fun) => fmt_let_fun(fun, fe, f),
+            Decl::ValDecl(val) => writeln!(f, "{}", fe.to(val)),
+            Decl::Meta { name, args } => {
+                fe.indent_line(f)?;
+                write!(f, "meta \"{}\"", name)?;
+                if !args.is_empty() {
+                    write!(f, " {}", args.iter().map(|a| fe.to(a)).format(", "))?;
+                }
+                writeln!(f)
+            }
+            Decl::Lemma { name, body } => {
+                fe.indent_line(f)?;
+                writeln!(f, "lemma {} : {}", fe.to(name), fe.to(body))
+            }
+            Decl::TyDecl(t) => writeln!(f, "{}", fe.to(t)),
             // Decl::PredDecl(p) => { writeln!(f, "{}", fe.to(p)) }
         }
     }
 }
 
+impl EnvDisplay for Module {
+    fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fe.indent_line(f)?;
+        writeln!(f, "module {}", self.name)?;
+        fe.indent(fe.config.indent, |fe| {
+            // Types are declared first, each after the types it uses
+            let tys: Vec<_> = self
+                .decls
+                .iter()
+                .filter_map(|d| if let Decl::TyDecl(ty) = d { Some(ty) } else { None })
+                .collect();
+            for group in ty_decl_sccs(&tys) {
+                let group: Vec<_> = group.into_iter().map(|ix| tys[ix]).collect();
+                writeln!(f, "{}", fe.to(&TyDeclGroup(&group)))?;
+            }
+
+            for decl in self.decls.iter().filter(|d| !matches!(d, Decl::TyDecl(_))) {
+                write!(f, "{}", fe.to(decl))?;
+            }
+            Ok(())
+        })?;
+        fe.indent_line(f)?;
+        writeln!(f, "end")
+    }
+}
+
 impl EnvDisplay for Predicate {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fe.indent_line(f)?;
@@ -106,7 +259,7 @@ impl EnvDisplay for Predicate {
         }
 
         writeln!(f, "=")?;
-        fe.indent(2, |fe| {
+        fe.indent(fe.config.indent, |fe| {
             fe.indent_line(f)?;
             write!(f, "{}", fe.to(&self.body))
         })?;
@@ -118,7 +271,11 @@ impl EnvDisplay for Predicate {
 impl EnvDisplay for Logic {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fe.indent_line(f)?;
-        write!(f, "let rec function {} ", fe.to(&self.name))?;
+        // Why3 only lets a function refer to itself when it is declared `rec`
+        let rec = if self.is_recursive() { "rec " } else { "" };
+        // Boolean valued functions are declared as predicates, which have no return type
+        let kind = if self.is_predicate() { "predicate" } else { "function" };
+        write!(f, "let {}{} {} ", rec, kind, fe.to(&self.name))?;
 
         if self.args.is_empty() {
             write!(f, "()")?;
@@ -128,9 +285,13 @@ impl EnvDisplay for Logic {
             write!(f, "({} : {})", nm, fe.to(ty))?;
         }
 
-        writeln!(f, " : {}", fe.to(&self.retty))?;
+        if self.is_predicate() {
+            writeln!(f)?;
+        } else {
+            writeln!(f, " : {}", fe.to(&self.retty))?;
+        }
 
-        fe.indent(2, |fe| {
+        fe.indent(fe.config.indent, |fe| {
             write!(f, "{}", fe.to(&self.contract))?;
             fe.indent_line(f)?;
             writeln!(f, "=")?;
@@ -149,22 +310,66 @@ impl EnvDisplay for Contract {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for req in &self.requires {
             fe.indent_line(f)?;
-            writeln!(f, "requires {{ {} }}", fe.to(req))?;
+            writeln!(f, "requires {{ {} }}", fe.after("requires { ").to(req))?;
         }
 
         for req in &self.ensures {
             fe.indent_line(f)?;
-            writeln!(f, "ensures {{ {} }}", fe.to(req))?;
+            writeln!(f, "ensures {{ {} }}", fe.after("ensures { ").to(req))?;
         }
 
         if let Some(variant) = &self.variant {
             fe.indent_line(f)?;
-            writeln!(f, "variant {{ {} }}", fe.to(variant))?;
+            writeln!(f, "variant {{ {} }}", fe.after("variant { ").to(variant))?;
         }
         Ok(())
     }
 }
 
+impl EnvDisplay for ValDecl {
+    fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fe.indent_line(f)?;
+        write!(f, "val {} ", fe.to(&self.name))?;
+
+        if self.args.is_empty() {
+            write!(f, "()")?;
+        }
+
+        for (nm, ty) in &self.args {
+            write!(f, "({} : {})", nm, fe.to(ty))?;
+        }
+
+        writeln!(f, " : {}", fe.to(&self.retty))?;
+        fe.indent(fe.config.indent, |fe| write!(f, "{}", fe.to(&self.contract)))
+    }
+}
+
+// Without a CFG the arguments are bound directly, as nothing assigns to them
+fn fmt_let_fun(fun: &Logic, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fe.indent_line(f)?;
+    write!(f, "let rec {} ", fe.to(&fun.name))?;
+
+    if fun.args.is_empty() {
+        write!(f, "()")?;
+    }
+
+    for (nm, ty) in &fun.args {
+        write!(f, "({} : {})", nm, fe.to(ty))?;
+    }
+
+    writeln!(f, " : {}", fe.to(&fun.retty))?;
+
+    fe.indent(fe.config.indent, |fe| {
+        write!(f, "{}", fe.to(&fun.contract))?;
+        fe.indent_line(f)?;
+        writeln!(f, "=")?;
+
+        fe.indent_line(f)?;
+        writeln!(f, "{}", fe.to(&fun.body))
+    })?;
+    writeln!(f)
+}
+
 impl EnvDisplay for Function {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fe.indent_line(f)?;
@@ -180,7 +385,7 @@ impl EnvDisplay for Function {
 
         writeln!(f, " : {}", fe.to(&self.retty))?;
 
-        fe.indent(2, |fe| {
+        fe.indent(fe.config.indent, |fe| {
             write!(f, "{}", fe.to(&self.contract))?;
             fe.indent_line(f)?;
             writeln!(f, "=")?;
@@ -205,7 +410,7 @@ impl EnvDisplay for Function {
 
         fe.indent_line(f)?;
         writeln!(f, "{{")?;
-        fe.indent(2, |fe| {
+        fe.indent(fe.config.indent, |fe| {
             for (arg, _) in self.args.iter() {
                 fe.indent_line(f)?;
                 writeln!(f, "{} <- o_{};", arg, arg)?;
@@ -218,9 +423,20 @@ impl EnvDisplay for Function {
         fe.indent_line(f)?;
         writeln!(f, "}}")?;
 
+        // Invariants are numbered across the whole function, so that their labels stay unique
+        // even when several loops share one
+        let mut inv_id = 0;
         for (id, block) in &self.blocks {
             fe.indent_line(f)?;
-            write!(f, "{} {}", id, fe.to(block))?;
+            write!(f, "{} ", id)?;
+
+            let mut invariants = Vec::new();
+            for (label, exp) in self.invariants.get(id).into_iter().flatten() {
+                let (label, exp) = (label.clone(), exp.clone());
+                invariants.push(Statement::Invariant { label, id: inv_id, exp });
+                inv_id += 1;
+            }
+            fmt_block(&invariants, block, fe, f)?;
         }
 
         Ok(())
@@ -294,19 +510,42 @@ impl EnvDisplay for Exp {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Exp::Current(box e) => {
-                write!(f, " * {}", fe.to(e))?;
+                write!(f, " * {}", prophecy_operand(fe, e))?;
             }
             Exp::Final(box e) => {
-                write!(f, " ^ {}", fe.to(e))?;
+                write!(f, " ^ {}", prophecy_operand(fe, e))?;
             }
-            Exp::Let { pattern, box arg, box body } => {
-                write!(
-                    f,
-                    "let {} = {} in {}",
-                    fe.to(pattern),
-                    parens!(fe, self, arg),
-                    parens!(fe, self, body)
-                )?;
+            Exp::Old(box e) => {
+                if e.precedence() == Precedence::Closed {
+                    write!(f, "old {}", fe.to(e))?;
+                } else {
+                    write!(f, "old ({})", fe.to(e))?;
+                }
+            }
+            Exp::Let { .. } => {
+                // A chain of `let`s is printed one binding per line, at the same indentation
+                let mut lets = Vec::new();
+                let mut rest = self;
+                while let Exp::Let { pattern, box arg, box body, ghost } = rest {
+                    lets.push(format!(
+                        "let {}{} = {} in",
+                        if *ghost { "ghost " } else { "" },
+                        fe.to(pattern),
+                        parens!(fe, self, arg)
+                    ));
+                    rest = body;
+                }
+                let body = parens!(fe, self, rest);
+
+                if lets.len() == 1 {
+                    write!(f, "{} {}", lets[0], body)?;
+                } else {
+                    for l in &lets {
+                        writeln!(f, "{}", l)?;
+                        fe.indent_line(f)?;
+                    }
+                    write!(f, "{}", body)?;
+                }
             }
             Exp::Var(v) => {
                 write!(f, "{}", v)?;
@@ -326,6 +565,27 @@ impl EnvDisplay for Exp {
             Exp::RecField { box record, label } => {
                 write!(f, "{}.{}", parens!(fe, self, record), label)?;
             }
+            Exp::Index { box base, box index } => {
+                // Only atoms can be indexed without parentheses
+                if base.precedence() == Precedence::Closed {
+                    write!(f, "{}[{}]", fe.to(base), fe.to(index))?;
+                } else {
+                    write!(f, "({})[{}]", fe.to(base), fe.to(index))?;
+                }
+            }
+            Exp::TupleField { box tuple, idx, arity } => {
+                let pat = (0..*arity).map(|i| if i == *idx { "a" } else { "_" });
+                write!(f, "let ({}) = {} in a", pat.format(", "), parens!(fe, self, tuple))?;
+            }
+            Exp::Record { fields, .. } => {
+                write!(
+                    f,
+                    "{{ {} }}",
+                    fields.iter().format_with("; ", |(label, val), f| {
+                        f(&format_args!("{} = {}", label, fe.to(val)))
+                    })
+                )?;
+            }
             Exp::Tuple(vs) => {
                 write!(f, "({})", vs.iter().format_with(", ", |elt, f| { f(&fe.to(elt)) }))?;
             }
@@ -342,44 +602,105 @@ impl EnvDisplay for Exp {
                 }
             }
             Exp::BorrowMut(box exp) => {
-                write!(f, "borrow_mut {}", parens!(fe, self, exp))?;
+                write!(f, "borrow_mut {}", parens!(fe, Precedence::Call, exp))?;
             }
             Exp::Const(c) => {
                 c.fmt(fe, f)?;
             }
             Exp::UnaryOp(UnOp::Not, box op) => {
-                write!(f, "not {}", parens!(fe, self, op))?;
+                // Comparisons don't need parentheses under `not`, but are clearer with them
+                write!(f, "not {}", parens!(fe, Precedence::PrefixOp, op))?;
             }
             Exp::UnaryOp(UnOp::Neg, box op) => {
                 write!(f, "- {}", parens!(fe, self, op))?;
             }
             Exp::BinaryOp(op, box l, box r) => {
-                write!(
-                    f,
-                    "{} {} {}",
-                    parens!(fe, self, l),
-                    bin_op_to_string(op),
-                    parens!(fe, self, r)
-                )?;
+                let (l_parens, r_parens) = match op {
+                    // `<->` doesn't associate with `->`, so any implication or equivalence
+                    // operands need parentheses
+                    BinOp::Iff => {
+                        (Precedence::Or > l.precedence(), Precedence::Or > r.precedence())
+                    }
+                    _ => {
+                        let prec = self.precedence();
+                        // Operators are left-associative, so a right operand of the same
+                        // precedence needs parentheses unless regrouping can't change its meaning.
+                        // Comparisons don't chain at all.
+                        let same_op = matches!(r, Exp::BinaryOp(r_op, _, _) if r_op == op);
+                        let l_parens = prec > l.precedence()
+                            || (prec == Precedence::Compare && l.precedence() == prec);
+                        let r_parens = prec > r.precedence()
+                            || (prec == r.precedence() && !(same_op && is_associative(op)));
+                        (l_parens, r_parens)
+                    }
+                };
+                let wrap = |fe: FormatEnv, e: &Exp, needed| {
+                    if needed && e.precedence() != Precedence::Closed {
+                        format!("({})", fe.to(e))
+                    } else {
+                        format!("{}", fe.to(e))
+                    }
+                };
+                let l = wrap(fe, l, l_parens);
+                let r = wrap(fe.after(&format!("{} {} ", l, bin_op_to_string(op))), r, r_parens);
+                let flat = format!("{} {} {}", l, bin_op_to_string(op), r);
+
+                if fe.fits(&flat) {
+                    write!(f, "{}", flat)?;
+                } else {
+                    write!(f, "{}", l)?;
+                    fe.indent(fe.config.indent, |fe| {
+                        writeln!(f)?;
+                        fe.indent_line(f)?;
+                        write!(f, "{} {}", bin_op_to_string(op), r)
+                    })?;
+                }
             }
             Exp::Call(box fun, args) => {
-                write!(
-                    f,
-                    "{} {}",
-                    parens!(fe, self, fun),
-                    args.iter().map(|a| parens!(fe, self, a)).format(" ")
-                )?;
+                let fun = parens!(fe, self, fun);
+                let mut flat = fun.clone();
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|a| {
+                        let arg = call_arg(fe.after(&format!("{} ", flat)), a);
+                        flat = format!("{} {}", flat, arg);
+                        arg
+                    })
+                    .collect();
+
+                if fe.fits(&flat) {
+                    write!(f, "{}", flat)?;
+                } else {
+                    write!(f, "{}", fun)?;
+                    fe.indent(fe.config.indent, |fe| {
+                        for a in &args {
+                            writeln!(f)?;
+                            fe.indent_line(f)?;
+                            write!(f, "{}", a)?;
+                        }
+                        Ok(())
+                    })?;
+                }
             }
-            Exp::Verbatim(verb) => {
+            Exp::Verbatim(verb, _) => {
                 write!(f, "{}", verb)?;
             }
-            Exp::Abs(ident, box body) => {
+            Exp::Raw { src, subst_points } => {
+                write!(f, "{}", substitute_tokens(src, subst_points))?;
+            }
+            Exp::Abs(ident, None, box body) => {
                 write!(f, "fun {} -> {}", ident, fe.to(body))?;
             }
+            Exp::Abs(ident, Some(ty), box body) => {
+                write!(f, "fun ({} : {}) -> {}", ident, fe.to(ty), fe.to(body))?;
+            }
+            Exp::Match(box scrut, brs) if brs.iter().any(|(_, guard, _)| guard.is_some()) => {
+                fmt_guarded_match(scrut, brs, fe, f)?
+            }
             Exp::Match(box scrut, brs) => {
                 writeln!(f, "match ({}) with", fe.to(scrut))?;
-                fe.indent(2, |fe| {
-                    for (pat, tgt) in brs {
+                fe.indent(fe.config.indent, |fe| {
+                    for (pat, _, tgt) in brs {
                         fe.indent_line(f)?;
                         writeln!(f, "| {} -> {}", fe.to(pat), fe.to(tgt))?;
                     }
@@ -389,51 +710,192 @@ impl EnvDisplay for Exp {
             }
             Exp::Forall(binders, box exp) => {
                 write!(f, "forall ")?;
-
-                let binder_fmt = binders
-                    .iter()
-                    .format_with(", ", |(l, ty), f| f(&format_args!("{} : {}", l, fe.to(ty))));
-
-                write!(f, "{} . {}", binder_fmt, fe.to(exp))?;
+                fmt_binders(binders, fe, f)?;
+                write!(f, " . {}", fe.to(exp))?;
             }
             Exp::Exists(binders, box exp) => {
                 write!(f, "exists ")?;
-
-                let binder_fmt = binders
-                    .iter()
-                    .format_with(", ", |(l, ty), f| f(&format_args!("{} : {}", l, fe.to(ty))));
-
-                write!(f, "{} . {}", binder_fmt, fe.to(exp))?;
+                fmt_binders(binders, fe, f)?;
+                write!(f, " . {}", fe.to(exp))?;
             }
+            // Implication is right-associative, only a nested hypothesis needs parentheses
             Exp::Impl(box hyp, box exp) => {
-                write!(f, "{} -> {}", parens!(fe, self, hyp), parens!(fe, self, exp))?;
+                write!(f, "{} -> {}", parens!(fe, Precedence::Or, hyp), parens!(fe, self, exp))?;
+            }
+            Exp::Attr(attr, box e) => write!(f, "[@{}] {}", attr, fe.to(e))?,
+            // Like `let`s, sequenced assumptions and assertions are printed one per line
+            Exp::Assume(box assump, box body) => {
+                writeln!(f, "assume {{ {} }};", fe.after("assume { ").to(assump))?;
+                fe.indent_line(f)?;
+                write!(f, "{}", fe.to(body))?;
+            }
+            Exp::Assert { box goal, by, box body } => {
+                fmt_assert(goal, by.as_deref(), fe, f)?;
+                writeln!(f, ";")?;
+                fe.indent_line(f)?;
+                write!(f, "{}", fe.to(body))?;
             }
             Exp::Absurd => write!(f, "absurd")?,
+            Exp::Result => write!(f, "result")?,
         }
         Ok(())
     }
 }
 
+// Application is left-associative, so unlike other operators an argument which is itself an
+// application needs parentheses
+fn call_arg(fe: FormatEnv, arg: &Exp) -> String {
+    match arg.precedence() {
+        Precedence::Call => format!("({})", fe.to(arg)),
+        _ => parens!(fe, Precedence::Call, arg),
+    }
+}
+
+// Replace the placeholders of `src` by the names of their locals in a single pass. A placeholder
+// only matches on token boundaries, preferring the longest one, so `$1` is left alone in `$10`
+// and a name which was just substituted in is never substituted again.
+fn substitute_tokens(src: &str, points: &[(String, LocalIdent)]) -> String {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    let mut out = String::with_capacity(src.len());
+    let mut rest = src;
+    let mut prev = None;
+    while let Some(c) = rest.chars().next() {
+        let point = points
+            .iter()
+            .filter(|(tok, _)| !tok.is_empty() && rest.starts_with(&tok[..]))
+            .filter(|(tok, _)| {
+                let starts = !tok.starts_with(is_ident) || !prev.map_or(false, is_ident);
+                let ends = !tok.ends_with(is_ident) || !rest[tok.len()..].starts_with(is_ident);
+                starts && ends
+            })
+            .max_by_key(|(tok, _)| tok.len());
+        match point {
+            Some((tok, local)) => {
+                out.push_str(&local.to_string());
+                prev = tok.chars().last();
+                rest = &rest[tok.len()..];
+            }
+            None => {
+                out.push(c);
+                prev = Some(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    out
+}
+
+// Why3 has no guards on match arms, so the arms are split after each guarded arm and a failing
+// guard falls through to a local function `rest'N` matching the next group. The scrutinee is
+// bound once, ahead of any pattern binders which could capture it, and every arm is printed
+// exactly once.
+fn fmt_guarded_match(
+    scrut: &Exp,
+    brs: &[(Pattern, Option<Exp>, Exp)],
+    fe: FormatEnv,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for (ix, (_, guard, _)) in brs.iter().enumerate() {
+        if guard.is_some() {
+            groups.push(&brs[start..=ix]);
+            start = ix + 1;
+        }
+    }
+    groups.push(&brs[start..]);
+
+    let scrut = match scrut {
+        Exp::Var(v) => v.to_string(),
+        _ => {
+            writeln!(f, "let scrut' = {} in", fe.to(scrut))?;
+            fe.indent_line(f)?;
+            "scrut'".to_owned()
+        }
+    };
+
+    // Groups are defined last to first, so each function only refers to earlier definitions
+    for (ix, group) in groups.iter().enumerate().rev().filter(|(_, g)| !g.is_empty()) {
+        let rest = match groups.get(ix + 1) {
+            Some(next) if !next.is_empty() => format!("rest'{} ()", ix + 1),
+            _ => "absurd".to_owned(),
+        };
+        if ix > 0 {
+            write!(f, "let rest'{} () = ", ix)?;
+        }
+        writeln!(f, "match ({}) with", scrut)?;
+        fe.indent(fe.config.indent, |fe| {
+            for (pat, guard, tgt) in group.iter() {
+                fe.indent_line(f)?;
+                match guard {
+                    None => writeln!(f, "| {} -> {}", fe.to(pat), fe.to(tgt))?,
+                    Some(guard) => {
+                        let tgt = parens!(fe, Precedence::Assign, tgt);
+                        writeln!(
+                            f,
+                            "| {} -> if {} then {} else {}",
+                            fe.to(pat),
+                            fe.to(guard),
+                            tgt,
+                            rest
+                        )?;
+                        fe.indent_line(f)?;
+                        writeln!(f, "| _ -> {}", rest)?;
+                    }
+                }
+            }
+            fe.indent_line(f)?;
+            writeln!(f, "end")
+        })?;
+        if ix > 0 {
+            fe.indent_line(f)?;
+            writeln!(f, "in")?;
+            fe.indent_line(f)?;
+        }
+    }
+    Ok(())
+}
+
 impl EnvDisplay for Statement {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Statement::Assign { lhs, rhs } => {
+                let fe = fe.after(&format!("{} <- ", lhs));
                 write!(f, "{} <- {}", lhs, parens!(fe, Precedence::Assign, rhs))?;
             }
-            Statement::Invariant(nm, e) => {
-                write!(f, "invariant {} {{ {} }}", nm, fe.to(e))?;
+            Statement::Call { dest, func, args } => {
+                let args = args.iter().map(|a| parens!(fe, Precedence::Call, a)).format(" ");
+                write!(f, "{} <- {} {}", dest, fe.to(func), args)?;
             }
-            Statement::Assume(assump) => {
-                write!(f, "assume {{ {} }}", fe.to(assump))?;
+            Statement::Invariant { label, id, exp } => {
+                let prefix = format!("invariant {{ [@expl:{} #{}] ", label, id);
+                write!(f, "{}{} }}", prefix, fe.after(&prefix).to(exp))?;
             }
-            Statement::Assert(assert) => {
-                write!(f, "assert {{ {} }}", fe.to(assert))?;
+            Statement::Assume(assump) => {
+                write!(f, "assume {{ {} }}", fe.after("assume { ").to(assump))?;
             }
+            Statement::Assert { goal, by } => fmt_assert(goal, by.as_ref(), fe, f)?,
         }
         Ok(())
     }
 }
 
+fn fmt_assert(
+    goal: &Exp,
+    by: Option<&Exp>,
+    fe: FormatEnv,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    match by {
+        None => write!(f, "assert {{ {} }}", fe.after("assert { ").to(goal)),
+        // `by` binds more loosely than any other connective
+        Some(proof) => {
+            let goal = parens!(fe, Precedence::Iff, goal);
+            write!(f, "assert {{ {} by {} }}", goal, parens!(fe, Precedence::Iff, proof))
+        }
+    }
+}
+
 impl EnvDisplay for Terminator {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Terminator::*;
@@ -447,9 +909,13 @@ impl EnvDisplay for Terminator {
             Return => {
                 writeln!(f, "return _0")?;
             }
+            Switch(discr, brs) if bool_gotos(brs).is_some() => {
+                let (then, else_) = bool_gotos(brs).unwrap();
+                writeln!(f, "if {} then goto {} else goto {}", fe.to(discr), then, else_)?;
+            }
             Switch(discr, brs) => {
                 writeln!(f, "switch ({})", fe.to(discr))?;
-                fe.indent(2, |fe| {
+                fe.indent(fe.config.indent, |fe| {
                     for (pat, tgt) in brs {
                         fe.indent_line(f)?;
                         write!(f, "| {} -> {}", fe.to(pat), fe.to(tgt))?;
@@ -472,9 +938,11 @@ impl EnvDisplay for Pattern {
             Pattern::VarP(v) => {
                 write!(f, "{}", v)?;
             }
-            Pattern::TupleP(vs) => {
-                write!(f, "({})", vs.iter().map(|x| fe.to(x)).format(", "))?;
-            }
+            Pattern::TupleP(vs) => match &vs[..] {
+                [] => write!(f, "()")?,
+                [p] => EnvDisplay::fmt(p, fe, f)?,
+                _ => write!(f, "({})", vs.iter().map(|x| fe.to(x)).format(", "))?,
+            },
             Pattern::ConsP(c, pats) => {
                 if pats.is_empty() {
                     write!(f, "{}", fe.to(c))?;
@@ -495,36 +963,100 @@ impl Display for BlockId {
 
 impl EnvDisplay for Block {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "{{")?;
-
-        fe.indent(2, |fe| {
-            for stmt in &self.statements {
-                fe.indent_line(f)?;
-                writeln!(f, "{};", fe.to(stmt))?;
-            }
+        fmt_block(&[], self, fe, f)
+    }
+}
 
+// Print `block`, starting with the invariants of the loop it is the header of
+fn fmt_block(
+    invariants: &[Statement],
+    block: &Block,
+    fe: FormatEnv,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    writeln!(f, "{{")?;
+
+    fe.indent(fe.config.indent, |fe| {
+        for stmt in invariants.iter().chain(&block.statements) {
             fe.indent_line(f)?;
-            self.terminator.fmt(fe, f)
-        })?;
+            writeln!(f, "{};", fe.to(stmt))?;
+        }
 
         fe.indent_line(f)?;
-        writeln!(f, "}}")?;
+        block.terminator.fmt(fe, f)
+    })?;
 
-        Ok(())
+    fe.indent_line(f)?;
+    writeln!(f, "}}")?;
+
+    Ok(())
+}
+
+// The targets of a switch with exactly a `True` and a `False` arm, both of which are gotos
+fn bool_gotos(brs: &[(Pattern, Terminator)]) -> Option<(&BlockId, &BlockId)> {
+    let is_ctor = |pat: &Pattern, name: &str| match pat {
+        Pattern::ConsP(c, args) => c.module.is_empty() && c.name[..] == [name] && args.is_empty(),
+        _ => false,
+    };
+
+    match brs {
+        [(p1, Terminator::Goto(t1)), (p2, Terminator::Goto(t2))] => {
+            if is_ctor(p1, "True") && is_ctor(p2, "False") {
+                Some((t1, t2))
+            } else if is_ctor(p1, "False") && is_ctor(p2, "True") {
+                Some((t2, t1))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// The operands of ` * ` and ` ^ ` are parenthesized unless they are atomic or themselves
+// prophecies, so that ` * borrow_mut x` can't be read as an application of ` * borrow_mut`.
+fn prophecy_operand(fe: FormatEnv, e: &Exp) -> String {
+    if e.precedence() == Precedence::PrefixOp {
+        format!("{}", fe.to(e))
+    } else {
+        parens!(fe, Precedence::Call, e)
     }
 }
 
+// Print quantifier binders, grouping consecutive binders of the same type as `x y : t`
+fn fmt_binders(
+    binders: &[(LocalIdent, Type)],
+    fe: FormatEnv,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let mut groups: Vec<(Vec<&LocalIdent>, &Type)> = Vec::new();
+    for (l, ty) in binders {
+        match groups.last_mut() {
+            Some((ls, last)) if *last == ty => ls.push(l),
+            _ => groups.push((vec![l], ty)),
+        }
+    }
+
+    let groups = groups.iter().format_with(", ", |(ls, ty), f| {
+        f(&format_args!("{} : {}", ls.iter().format(" "), fe.to(*ty)))
+    });
+    write!(f, "{}", groups)
+}
+
 fn bin_op_to_string(op: &BinOp) -> &str {
     use BinOp::*;
     match op {
         And => "&&",
         Or => "||",
+        LAnd => "/\\",
+        LOr => "\\/",
         Add => "+",
         Sub => "-",
         Mul => "*",
         Div => "/",
         Eq => "=",
         Ne => "<>",
+        Iff => "<->",
         Gt => ">",
         Ge => ">=",
         Lt => "<",
@@ -532,44 +1064,136 @@ fn bin_op_to_string(op: &BinOp) -> &str {
     }
 }
 
+// Operators for which `a op (b op c)` means the same as `(a op b) op c`
+fn is_associative(op: &BinOp) -> bool {
+    matches!(op, BinOp::And | BinOp::Or | BinOp::LAnd | BinOp::LOr | BinOp::Add | BinOp::Mul)
+}
+
 impl EnvDisplay for Constant {
     fn fmt(&self, fe: FormatEnv, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Constant::Other(o) => write!(f, "{}", o),
             Constant::Int(i, Some(t)) => write!(f, "({} : {})", i, fe.to(t)),
+            // Otherwise `f -1` would be read as a subtraction
+            Constant::Int(i, None) if *i < 0 => write!(f, "({})", i),
             Constant::Int(i, None) => write!(f, "{}", i),
             Constant::Uint(i, Some(t)) => write!(f, "({} : {})", i, fe.to(t)),
             Constant::Uint(i, None) => write!(f, "{}", i),
+            // Why3 characters are built from their code point
+            Constant::Char(c) => write!(f, "(chr {})", *c as u32),
+            Constant::Float(x, Some(t)) => write!(f, "({} : {})", hex_float(*x), fe.to(t)),
+            Constant::Float(x, None) if x.is_sign_negative() => write!(f, "({})", hex_float(*x)),
+            Constant::Float(x, None) => write!(f, "{}", hex_float(*x)),
+            Constant::Str(s) => {
+                write!(f, "\"")?;
+                for b in s.bytes() {
+                    match b {
+                        b'"' => write!(f, "\\\"")?,
+                        b'\\' => write!(f, "\\\\")?,
+                        b'\n' => write!(f, "\\n")?,
+                        b'\t' => write!(f, "\\t")?,
+                        b'\r' => write!(f, "\\r")?,
+                        // Why3 strings are made of bytes, anything else is written by its code
+                        b' '..=b'~' => write!(f, "{}", b as char)?,
+                        _ => write!(f, "\\x{:02x}", b)?,
+                    }
+                }
+                write!(f, "\"")
+            }
         }
     }
 }
 
+// The exact value of a finite float as a hexadecimal literal, `0.1` isn't representable so
+// printing the shortest decimal would change the value Why3 reasons about
+fn hex_float(x: f64) -> String {
+    debug_assert!(x.is_finite(), "{} has no Why3 literal", x);
+    let bits = x.to_bits();
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let exp = ((bits >> 52) & 0x7ff) as i64;
+    let mantissa = bits & ((1 << 52) - 1);
+
+    // Subnormals (and zero) have no implicit leading one
+    let (lead, exp) = if exp == 0 { (0, -1022) } else { (1, exp - 1023) };
+    let (lead, exp) = if mantissa == 0 && lead == 0 { (0, 0) } else { (lead, exp) };
+
+    let digits = format!("{:013x}", mantissa);
+    let digits = match digits.trim_end_matches('0') {
+        "" => "0",
+        digits => digits,
+    };
+    format!("{}0x{}.{}p{}", sign, lead, digits, exp)
+}
+
 impl EnvDisplay for TyDecl {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fe.indent_line(f)?;
-        writeln!(f, "type {} {} =", fe.to(&self.ty_name), self.ty_params.iter().format_with(" ", |p, f| f(&format_args!("'{}", p))))?;
+        fmt_ty_decl(self, "type", fe, f)
+    }
+}
 
-        fe.indent(2, |fe| {
-            for (cons, args) in self.ty_constructors.iter() {
-                fe.indent_line(f)?;
-                if args.is_empty() {
-                    writeln!(f, "  | {}", cons)?;
-                } else {
-                    writeln!(
-                        f,
-                        "  | {}({})",
-                        cons,
-                        args.iter().format_with(", ", |elt, f| { f(&fe.to(elt)) })
-                    )?;
-                }
-            }
-            Ok(())
-        })?;
+/// Mutually recursive type declarations, declared jointly using `with`.
+pub struct TyDeclGroup<'a>(pub &'a [&'a TyDecl]);
 
+impl EnvDisplay for TyDeclGroup<'_> {
+    fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (ix, decl) in self.0.iter().enumerate() {
+            fmt_ty_decl(decl, if ix == 0 { "type" } else { "with" }, fe, f)?;
+        }
         Ok(())
     }
 }
 
+fn fmt_ty_decl(
+    decl: &TyDecl,
+    keyword: &str,
+    fe: FormatEnv,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    fe.indent_line(f)?;
+    writeln!(
+        f,
+        "{} {} {} =",
+        keyword,
+        fe.to(&decl.ty_name),
+        decl.ty_params.iter().format_with(" ", |p, f| f(&format_args!("'{}", p)))
+    )?;
+
+    fe.indent(fe.config.indent, |fe| {
+        if let Some(ty) = &decl.ty_alias {
+            fe.indent_line(f)?;
+            return writeln!(f, "  {}", fe.to(ty));
+        }
+
+        if !decl.ty_fields.is_empty() {
+            fe.indent_line(f)?;
+            return writeln!(
+                f,
+                "  {{ {} }}",
+                decl.ty_fields.iter().format_with("; ", |(fld, ty), f| {
+                    f(&format_args!("{} : {}", fld, fe.to(ty)))
+                })
+            );
+        }
+
+        for (cons, args) in decl.ty_constructors.iter() {
+            let cons = QName::new(decl.ty_name.module.iter().cloned(), vec![cons.clone()]);
+            let cons = fe.mangler.and_then(|m| m.mangle(&cons)).unwrap_or_else(|| cons.name());
+            fe.indent_line(f)?;
+            if args.is_empty() {
+                writeln!(f, "  | {}", cons)?;
+            } else {
+                writeln!(
+                    f,
+                    "  | {}({})",
+                    cons,
+                    args.iter().format_with(", ", |elt, f| { f(&fe.to(elt)) })
+                )?;
+            }
+        }
+        Ok(())
+    })
+}
+
 impl EnvDisplay for QName {
     fn fmt(&self, fe: FormatEnv, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use itertools::EitherOrBoth::*;
@@ -591,10 +1215,871 @@ impl EnvDisplay for QName {
                 .format(".")
         );
 
+        let name = fe.mangler.and_then(|m| m.mangle(self)).unwrap_or_else(|| self.name());
         if module_path.is_empty() {
-            write!(f, "{}", self.name())
+            write!(f, "{}", name)
         } else {
-            write!(f, "{}.{}", module_path, self.name())
+            write!(f, "{}.{}", module_path, name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print<D: EnvDisplay>(d: &D) -> String {
+        format!("{}", FormatEnv::default().to(d))
+    }
+
+    #[test]
+    fn record_construction_and_projection() {
+        let record = Exp::Record {
+            ctor: "point".into(),
+            fields: vec![
+                ("x".into(), Exp::Var("a".into())),
+                ("y".into(), Exp::Const(Constant::Int(1, None))),
+            ],
+        };
+        assert_eq!(print(&record), "{ x = a; y = 1 }");
+
+        let proj = Exp::RecField { record: box record, label: "x".into() };
+        assert_eq!(print(&proj), "{ x = a; y = 1 }.x");
+    }
+
+    #[test]
+    fn meta_decl() {
+        let meta =
+            Decl::Meta { name: "compute_max_steps".into(), args: vec![MetaArg::Integer(1000000)] };
+        assert_eq!(print(&meta), "meta \"compute_max_steps\" 1000000\n");
+
+        let args = vec![MetaArg::String("lemma".into()), MetaArg::Integer(3)];
+        let meta = Decl::Meta { name: "hint".into(), args };
+        assert_eq!(print(&meta), "meta \"hint\" \"lemma\", 3\n");
+
+        let parsed = Decl::parse_meta("\"rewrite\" prop p, \"x\", -2").unwrap();
+        assert_eq!(print(&parsed), "meta \"rewrite\" prop p, \"x\", -2\n");
+        assert!(matches!(
+            &parsed,
+            Decl::Meta { args, .. } if args[0] == MetaArg::Ident("prop p".into())
+                && args[1] == MetaArg::String("x".into())
+                && args[2] == MetaArg::Integer(-2)
+        ));
+        assert!(Decl::parse_meta("compute_max_steps 1").is_none());
+    }
+
+    #[test]
+    fn invariants_sharing_a_label() {
+        let inv =
+            |id| Statement::Invariant { label: "bound".into(), id, exp: Exp::Var("b".into()) };
+        assert_eq!(print(&inv(0)), "invariant { [@expl:bound #0] b }");
+        assert_eq!(print(&inv(1)), "invariant { [@expl:bound #1] b }");
+    }
+
+    #[test]
+    fn low_precedence_operands_are_parenthesized() {
+        let var = |v: &str| Exp::Var(v.into());
+        let cases = vec![
+            (
+                Exp::Let {
+                    pattern: Pattern::VarP("x".into()),
+                    arg: box var("a"),
+                    body: box var("x"),
+                    ghost: false,
+                },
+                "(let x = a in x)",
+            ),
+            (Exp::Abs("x".into(), None, box var("x")), "(fun x -> x)"),
+            (Exp::Forall(vec![("x".into(), Type::Integer)], box var("x")), "(forall x : int . x)"),
+            (
+                Exp::Match(box var("a"), vec![(Pattern::Wildcard, None, var("b"))]),
+                "(match (a) with\n  | _ -> b\n  end\n)",
+            ),
+        ];
+
+        for (exp, expected) in cases {
+            let call = Exp::Call(box var("f"), vec![exp.clone()]);
+            assert_eq!(print(&call), format!("f {}", expected));
+
+            let add = Exp::BinaryOp(BinOp::Add, box exp, box var("c"));
+            assert_eq!(print(&add), format!("{} + c", expected));
+        }
+    }
+
+    #[test]
+    fn wide_conjunction_wraps() {
+        let var = |v: &str| Exp::Var(v.into());
+        let conj = Exp::conj(Exp::conj(var("aaaa"), var("bbbb")), var("cccc"));
+
+        assert_eq!(print(&conj), "aaaa && bbbb && cccc");
+
+        let fe = FormatEnv {
+            config: PrintConfig { indent: 4, width: 16, ..PrintConfig::default() },
+            ..FormatEnv::default()
+        };
+        assert_eq!(format!("{}", fe.to(&conj)), "aaaa && bbbb\n    && cccc");
+
+        let fe = FormatEnv {
+            config: PrintConfig { indent: 2, width: 8, ..PrintConfig::default() },
+            ..FormatEnv::default()
+        };
+        assert_eq!(format!("{}", fe.to(&conj)), "aaaa\n  && bbbb\n  && cccc");
+
+        let call = Exp::Call(box var("f"), vec![var("aaaa"), var("bbbb")]);
+        assert_eq!(format!("{}", fe.to(&call)), "f\n  aaaa\n  bbbb");
+    }
+
+    #[test]
+    fn wrapping_counts_the_line_prefix() {
+        let var = |v: &str| Exp::Var(v.into());
+        let conj = Exp::conj(var("aaaa"), var("bbbb"));
+        let fe = FormatEnv {
+            config: PrintConfig { indent: 2, width: 20, ..PrintConfig::default() },
+            ..FormatEnv::default()
+        };
+
+        // The conjunction alone fits, but not after `assert { `
+        assert_eq!(format!("{}", fe.to(&conj)), "aaaa && bbbb");
+        let assert = Statement::Assert { goal: conj, by: None };
+        assert_eq!(format!("{}", fe.to(&assert)), "assert { aaaa\n  && bbbb }");
+
+        // The sum alone fits, but not once it is preceded by the left operand
+        let sum = Exp::BinaryOp(BinOp::Add, box var("cccccc"), box var("dddddd"));
+        let cmp = Exp::BinaryOp(BinOp::Lt, box var("x"), box sum);
+        let fe = FormatEnv { config: PrintConfig { width: 18, ..fe.config }, ..fe };
+        assert_eq!(format!("{}", fe.to(&cmp)), "x < cccccc\n  + dddddd");
+    }
+
+    #[test]
+    fn guarded_match() {
+        let var = |v: &str| Exp::Var(v.into());
+        let guard = Exp::BinaryOp(BinOp::Lt, box var("x"), box Exp::Const(Constant::Int(0, None)));
+        let exp = Exp::Match(
+            box var("a"),
+            vec![
+                (Pattern::VarP("x".into()), Some(guard), var("b")),
+                (Pattern::Wildcard, None, var("c")),
+            ],
+        );
+
+        assert_eq!(
+            print(&exp),
+            "let rest'1 () = match (a) with\n  \
+               | _ -> c\n  \
+               end\n\
+             in\n\
+             match (a) with\n  \
+               | x -> if x < 0 then b else rest'1 ()\n  \
+               | _ -> rest'1 ()\n  \
+               end\n"
+        );
+
+        let fvs: HashSet<_> = ["a", "b", "c"].iter().map(|v| LocalIdent::from(*v)).collect();
+        assert_eq!(exp.fvs(), fvs);
+    }
+
+    #[test]
+    fn guards_fall_through_without_capture() {
+        let var = |v: &str| Exp::Var(v.into());
+        let some = |v: &str| Pattern::ConsP("Some".into(), vec![Pattern::VarP(v.into())]);
+        let scrut = Exp::Constructor { ctor: "Some".into(), args: vec![var("x")] };
+        let exp = Exp::Match(
+            box scrut,
+            vec![
+                (some("x"), Some(var("p")), var("a")),
+                (some("y"), Some(var("q")), var("x")),
+                (Pattern::Wildcard, None, var("x")),
+            ],
+        );
+
+        // Later arms refer to the outer `x`, so they can't be printed under the `Some(x)` binder
+        assert_eq!(
+            print(&exp),
+            "let scrut' = Some(x) in\n\
+             let rest'2 () = match (scrut') with\n  \
+               | _ -> x\n  \
+               end\n\
+             in\n\
+             let rest'1 () = match (scrut') with\n  \
+               | Some(y) -> if q then x else rest'2 ()\n  \
+               | _ -> rest'2 ()\n  \
+               end\n\
+             in\n\
+             match (scrut') with\n  \
+               | Some(x) -> if p then a else rest'1 ()\n  \
+               | _ -> rest'1 ()\n  \
+               end\n"
+        );
+
+        let guarded = |n: usize| (Pattern::Wildcard, Some(var(&format!("g{}", n))), var("b"));
+        let exp = Exp::Match(box var("s"), (0..20).map(guarded).collect());
+        assert_eq!(print(&exp).matches("then b").count(), 20);
+        assert!(print(&exp).contains("| _ -> if g19 then b else absurd\n  | _ -> absurd\n"));
+    }
+
+    #[test]
+    fn borrows_and_prophecies() {
+        let x = || Exp::Var("x".into());
+        let field = || Exp::RecField { record: box x(), label: "f".into() };
+
+        assert_eq!(print(&Exp::BorrowMut(box x())), "borrow_mut x");
+        assert_eq!(print(&Exp::BorrowMut(box field())), "borrow_mut (x.f)");
+
+        assert_eq!(print(&Exp::Current(box Exp::BorrowMut(box x()))), " * (borrow_mut x)");
+        assert_eq!(print(&Exp::Final(box Exp::BorrowMut(box field()))), " ^ (borrow_mut (x.f))");
+        assert_eq!(print(&Exp::Current(box Exp::Final(box x()))), " *  ^ x");
+
+        let arg = Exp::Call(box Exp::Var("f".into()), vec![Exp::BorrowMut(box x())]);
+        assert_eq!(print(&arg), "f (borrow_mut x)");
+    }
+
+    #[test]
+    fn record_decl() {
+        let decl = TyDecl {
+            ty_name: "point".into(),
+            ty_params: vec![],
+            ty_constructors: vec![],
+            ty_fields: vec![("x".into(), Type::Integer), ("y".into(), Type::Bool)],
+            ty_alias: None,
+        };
+        assert_eq!(print(&decl), "type point  =\n    { x : int; y : bool }\n");
+    }
+
+    #[test]
+    fn alias_decl() {
+        let byte = TyDecl {
+            ty_name: "byte".into(),
+            ty_params: vec![],
+            ty_constructors: vec![],
+            ty_fields: vec![],
+            ty_alias: Some(Type::TConstructor("uint8".into())),
+        };
+        assert_eq!(print(&byte), "type byte  =\n    uint8\n");
+        assert_eq!(byte.used_types(), vec!["uint8".into()].into_iter().collect());
+
+        let list = Type::TApp(box Type::TConstructor("list".into()), vec![Type::TVar("a".into())]);
+        let stack = TyDecl {
+            ty_name: "stack".into(),
+            ty_params: vec!["a".into()],
+            ty_alias: Some(list),
+            ..byte
+        };
+        assert_eq!(print(&stack), "type stack 'a =\n    list 'a\n");
+    }
+
+    #[test]
+    fn module_declares_types_before_use() {
+        let ty = |name: &str, args| TyDecl {
+            ty_name: name.into(),
+            ty_params: vec![],
+            ty_constructors: vec![(name.to_uppercase(), args)],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let module = Module {
+            name: "M".into(),
+            decls: vec![
+                Decl::parse_meta("\"compute_max_steps\" 100").unwrap(),
+                Decl::TyDecl(ty("a", vec![Type::TConstructor("b".into())])),
+                Decl::TyDecl(ty("b", vec![Type::Integer])),
+            ],
+        };
+
+        assert_eq!(
+            print(&module),
+            "module M\n  type b  =\n      | B(int)\n\n  type a  =\n      | A(b)\n\n  \
+             meta \"compute_max_steps\" 100\nend\n"
+        );
+    }
+
+    #[test]
+    fn mutually_recursive_types_use_with() {
+        let ty = |name: &str, uses: &str| TyDecl {
+            ty_name: name.into(),
+            ty_params: vec![],
+            ty_constructors: vec![(name.to_uppercase(), vec![Type::TConstructor(uses.into())])],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let module = Module {
+            name: "M".into(),
+            decls: vec![Decl::TyDecl(ty("tree", "forest")), Decl::TyDecl(ty("forest", "tree"))],
+        };
+
+        assert_eq!(
+            print(&module),
+            "module M\n  type tree  =\n      | TREE(forest)\n  \
+             with forest  =\n      | FOREST(tree)\n\nend\n"
+        );
+    }
+
+    #[test]
+    fn absurd_terminator_and_expression() {
+        let block = Block {
+            statements: vec![Statement::Assign { lhs: "x".into(), rhs: Exp::Absurd }],
+            terminator: Terminator::Absurd,
+        };
+        assert_eq!(print(&block), "{\n  x <- absurd;\n  absurd\n}\n");
+    }
+
+    #[test]
+    fn boolean_switch_prints_as_if() {
+        let cond = Exp::BinaryOp(BinOp::Lt, box Exp::Var("x".into()), box Exp::Var("y".into()));
+        let goto = |bb| Terminator::Goto(BlockId(bb));
+        let branch = Terminator::if_(cond.clone(), goto(1), goto(2));
+        assert_eq!(print(&branch), "if x < y then goto BB1 else goto BB2\n");
+
+        let swapped = Terminator::Switch(
+            cond.clone(),
+            vec![(Pattern::mk_false(), goto(2)), (Pattern::mk_true(), goto(1))],
+        );
+        assert_eq!(print(&swapped), "if x < y then goto BB1 else goto BB2\n");
+
+        let nested = Terminator::if_(cond, goto(1), Terminator::Absurd);
+        assert_eq!(
+            print(&nested),
+            "switch (x < y)\n  | True -> goto BB1\n  | False -> absurd\n  end\n"
+        );
+    }
+
+    #[test]
+    fn three_way_switch_prints_as_match() {
+        let switch = Terminator::Switch(
+            Exp::Var("b".into()),
+            vec![
+                (Pattern::mk_false(), Terminator::Goto(BlockId(2))),
+                (Pattern::mk_true(), Terminator::Goto(BlockId(1))),
+                (Pattern::Wildcard, Terminator::Goto(BlockId(1))),
+            ],
+        );
+        assert_eq!(
+            print(&switch),
+            "switch (b)\n  | False -> goto BB2\n  | True -> goto BB1\n  | _ -> goto BB1\n  end\n"
+        );
+    }
+
+    #[test]
+    fn three_constructor_switch_prints_as_match() {
+        let arm =
+            |ctor: &str, bb| (Pattern::ConsP(ctor.into(), vec![]), Terminator::Goto(BlockId(bb)));
+        let arms = vec![arm("Less", 1), arm("Equal", 2), arm("Greater", 3)];
+        let switch = Terminator::Switch(Exp::Var("o".into()), arms);
+        assert_eq!(
+            print(&switch),
+            "switch (o)\n  | Less -> goto BB1\n  | Equal -> goto BB2\n  | Greater -> goto BB3\n  \
+             end\n"
+        );
+    }
+
+    #[test]
+    fn integer_constants() {
+        let ty = |t: &str| Type::TConstructor(t.into());
+        let int = |i, t: Option<&str>| Exp::Const(Constant::Int(i, t.map(ty)));
+
+        assert_eq!(print(&int(-5, Some("int32"))), "(-5 : int32)");
+        assert_eq!(print(&Exp::Const(Constant::Uint(255, Some(ty("uint8"))))), "(255 : uint8)");
+        assert_eq!(print(&int(7, None)), "7");
+        assert_eq!(print(&Exp::Call(box Exp::Var("f".into()), vec![int(-7, None)])), "f (-7)");
+    }
+
+    #[test]
+    fn char_constants() {
+        let chr = |c| print(&Exp::Const(Constant::Char(c)));
+
+        assert_eq!(chr('a'), "(chr 97)");
+        assert_eq!(chr('\n'), "(chr 10)");
+        assert_eq!(chr('é'), "(chr 233)");
+        assert_eq!(chr('€'), "(chr 8364)");
+    }
+
+    #[test]
+    fn old_values() {
+        let x = || Exp::Var("x".into());
+        let one = Exp::Const(Constant::Int(1, None));
+        let old = |e| Exp::Old(box e);
+
+        assert_eq!(print(&old(x())), "old x");
+        assert_eq!(print(&old(Exp::BinaryOp(BinOp::Add, box x(), box one.clone()))), "old (x + 1)");
+        assert_eq!(print(&old(Exp::Current(box x()))), "old ( * x)");
+        assert_eq!(
+            print(&Exp::BinaryOp(BinOp::Eq, box x(), box old(x()).app_to(one))),
+            "x = (old x) 1"
+        );
+    }
+
+    #[test]
+    fn nested_borrow_types() {
+        let borrow = |t| Type::MutableBorrow(box t);
+        let t = || Type::TVar("t".into());
+
+        assert_eq!(print(&borrow(t())), "borrowed 't");
+        assert_eq!(print(&borrow(borrow(t()))), "borrowed (borrowed 't)");
+        assert_eq!(print(&borrow(borrow(borrow(t())))), "borrowed (borrowed (borrowed 't))");
+        assert_eq!(
+            print(&borrow(Type::Tuple(vec![Type::Integer, borrow(Type::Bool)]))),
+            "borrowed (int, borrowed bool)"
+        );
+        assert_eq!(print(&Type::TFun(box borrow(t()), box Type::Bool)), "(borrowed 't) -> bool");
+    }
+
+    #[test]
+    fn equivalence() {
+        let var = |v: &str| Exp::Var(v.into());
+        let iff = |l, r| Exp::BinaryOp(BinOp::Iff, box l, box r);
+
+        assert_eq!(print(&iff(var("a"), Exp::mk_true())), "a <-> true");
+        assert_eq!(print(&Exp::not(iff(var("a"), var("b")))), "not (a <-> b)");
+        assert_eq!(print(&Exp::Impl(box iff(var("a"), var("b")), box var("c"))), "(a <-> b) -> c");
+        assert_eq!(print(&iff(Exp::Impl(box var("a"), box var("b")), var("c"))), "(a -> b) <-> c");
+        assert_eq!(print(&iff(var("a"), iff(var("b"), var("c")))), "a <-> (b <-> c)");
+    }
+
+    #[test]
+    fn call_statement() {
+        let var = |v: &str| Exp::Var(v.into());
+        let call = Statement::Call {
+            dest: "x".into(),
+            func: QName::from("f"),
+            args: vec![var("a"), Exp::BinaryOp(BinOp::Add, box var("b"), box var("c"))],
+        };
+        assert_eq!(print(&call), "x <- f a (b + c)");
+
+        let block = Block { statements: vec![call], terminator: Terminator::Return };
+        let mut locals: Vec<_> = block.locals().into_iter().map(|l| l.to_string()).collect();
+        locals.sort();
+        assert_eq!(locals, ["a", "b", "c", "x"]);
+    }
+
+    #[test]
+    fn only_recursive_logic_is_rec() {
+        let var = |v: &str| Exp::Var(v.into());
+        let one_plus = |e| Exp::BinaryOp(BinOp::Add, box Exp::Const(Constant::Int(1, None)), box e);
+        let logic = |body| Logic {
+            name: QName::from("len"),
+            retty: Type::Integer,
+            args: vec![("l".into(), Type::TConstructor("list".into()))],
+            body,
+            contract: Contract::new(),
+        };
+
+        let call = |f: &str| Exp::Call(box Exp::QVar(QName::from(f)), vec![var("l")]);
+
+        let len = logic(one_plus(call("len")));
+        assert!(print(&len).starts_with("let rec function len (l : list) : int\n"));
+
+        let not_len = logic(one_plus(call("size")));
+        assert!(print(&not_len).starts_with("let function len (l : list) : int\n"));
+    }
+
+    #[test]
+    fn boolean_logic_is_a_predicate() {
+        let logic = |retty, body| Logic {
+            name: QName::from("f"),
+            retty,
+            args: vec![("x".into(), Type::Integer)],
+            body,
+            contract: Contract::new(),
+        };
+
+        let pred = logic(Type::Bool, Exp::mk_true());
+        assert_eq!(print(&pred), "let predicate f (x : int)\n  =\n  true\n");
+
+        let fun = logic(Type::Integer, Exp::Var("x".into()));
+        assert_eq!(print(&fun), "let function f (x : int) : int\n  =\n  x\n");
+    }
+
+    #[test]
+    fn ghost_lets() {
+        let var = |v: &str| Exp::Var(v.into());
+        let plain = Exp::let_(Pattern::VarP("x".into()), var("a"), var("x"));
+        assert_eq!(print(&plain), "let x = a in x");
+
+        let ghost = Exp::ghost_let(Pattern::VarP("x".into()), var("a"), var("x"));
+        assert_eq!(print(&ghost), "let ghost x = a in x");
+        assert_eq!(ghost.fvs(), plain.fvs());
+        assert!(!ghost.alpha_eq(&plain));
+    }
+
+    #[test]
+    fn associativity() {
+        let var = |v: &str| Exp::Var(v.into());
+        let bin = |op, l, r| Exp::BinaryOp(op, box l, box r);
+        let sub = |l, r| bin(BinOp::Sub, l, r);
+
+        assert_eq!(print(&sub(var("a"), sub(var("b"), var("c")))), "a - (b - c)");
+        assert_eq!(print(&sub(sub(var("a"), var("b")), var("c"))), "a - b - c");
+        assert_eq!(print(&Exp::conj(var("a"), Exp::conj(var("b"), var("c")))), "a && b && c");
+        assert_eq!(print(&Exp::conj(Exp::conj(var("a"), var("b")), var("c"))), "a && b && c");
+
+        let add = |l, r| bin(BinOp::Add, l, r);
+        assert_eq!(print(&add(var("a"), sub(var("b"), var("c")))), "a + (b - c)");
+        assert_eq!(print(&sub(add(var("a"), var("b")), var("c"))), "a + b - c");
+
+        let eq = |l, r| bin(BinOp::Eq, l, r);
+        assert_eq!(print(&eq(eq(var("a"), var("b")), var("c"))), "(a = b) = c");
+    }
+
+    #[test]
+    fn indexing() {
+        let var = |v: &str| Exp::Var(v.into());
+        let index = |base, index| Exp::Index { base: box base, index: box index };
+
+        assert_eq!(print(&index(var("a"), Exp::Const(Constant::Int(0, None)))), "a[0]");
+        assert_eq!(print(&index(index(var("a"), var("i")), var("j"))), "a[i][j]");
+
+        let concat = Exp::BinaryOp(BinOp::Add, box var("a"), box var("b"));
+        let sum = Exp::BinaryOp(BinOp::Add, box var("i"), box var("j"));
+        assert_eq!(print(&index(concat, sum)), "(a + b)[i + j]");
+        let call = Exp::Call(box var("f"), vec![var("x")]);
+        assert_eq!(print(&Exp::Call(box var("g"), vec![index(call, var("i"))])), "g (f x)[i]");
+    }
+
+    #[test]
+    fn assert_by() {
+        let var = |v: &str| Exp::Var(v.into());
+        let gt = Exp::BinaryOp(BinOp::Gt, box var("x"), box Exp::Const(Constant::Int(0, None)));
+        let plain = Statement::Assert { goal: gt.clone(), by: None };
+        assert_eq!(print(&plain), "assert { x > 0 }");
+
+        let proof = Exp::Impl(box var("p"), box var("q"));
+        let by = Statement::Assert { goal: gt, by: Some(proof) };
+        assert_eq!(print(&by), "assert { x > 0 by p -> q }");
+
+        let block = Block { statements: vec![by], terminator: Terminator::Return };
+        let mut locals: Vec<_> = block.locals().into_iter().map(|l| l.to_string()).collect();
+        locals.sort();
+        assert_eq!(locals, ["p", "q", "x"]);
+    }
+
+    #[test]
+    fn overflow_conditions() {
+        let var = |v: &str| Exp::Var(v.into());
+        let ty = |t: &str| Type::TConstructor(t.into());
+
+        let u8_add = overflow_condition(&BinOp::Add, var("a"), var("b"), &ty("uint8")).unwrap();
+        assert_eq!(
+            print(&u8_add),
+            "0 <= UInt8.to_int a + UInt8.to_int b && UInt8.to_int a + UInt8.to_int b <= 255"
+        );
+
+        let i32_mul = overflow_condition(&BinOp::Mul, var("a"), var("b"), &ty("int32")).unwrap();
+        assert_eq!(
+            print(&i32_mul),
+            "(-2147483648) <= Int32.to_int a * Int32.to_int b \
+             && Int32.to_int a * Int32.to_int b <= 2147483647"
+        );
+
+        let u128_add = overflow_condition(&BinOp::Add, var("a"), var("b"), &ty("uint128")).unwrap();
+        assert!(print(&u128_add).ends_with(" <= 340282366920938463463374607431768211455"));
+        let i128_sub = overflow_condition(&BinOp::Sub, var("a"), var("b"), &ty("int128")).unwrap();
+        assert!(print(&i128_sub).starts_with("(-170141183460469231731687303715884105728) <= "));
+
+        assert!(overflow_condition(&BinOp::Lt, var("a"), var("b"), &ty("int32")).is_none());
+        assert!(overflow_condition(&BinOp::Add, var("a"), var("b"), &Type::Integer).is_none());
+    }
+
+    #[test]
+    fn mul_div_chains() {
+        let var = |v: &str| Exp::Var(v.into());
+        let bin = |op, l, r| Exp::BinaryOp(op, box l, box r);
+        let div = |l, r| bin(BinOp::Div, l, r);
+        let mul = |l, r| bin(BinOp::Mul, l, r);
+
+        assert_eq!(print(&div(div(var("a"), var("b")), var("c"))), "a / b / c");
+        assert_eq!(print(&div(var("a"), div(var("b"), var("c")))), "a / (b / c)");
+        assert_eq!(print(&div(mul(var("a"), var("b")), var("c"))), "a * b / c");
+        assert_eq!(print(&mul(div(var("a"), var("b")), var("c"))), "a / b * c");
+        assert_eq!(print(&mul(var("a"), div(var("b"), var("c")))), "a * (b / c)");
+        assert_eq!(print(&div(bin(BinOp::Add, var("a"), var("b")), var("c"))), "(a + b) / c");
+    }
+
+    #[test]
+    fn abstract_functions() {
+        let var = |v: &str| Exp::Var(v.into());
+        let mut contract = Contract::new();
+        contract.requires.push(Exp::BinaryOp(BinOp::Ge, box var("x"), box var("lo")));
+        contract.ensures.push(Exp::BinaryOp(BinOp::Ge, box var("result"), box var("lo")));
+        let hash = ValDecl {
+            name: QName::from("hash"),
+            retty: Type::Integer,
+            args: vec![("x".into(), Type::Integer), ("lo".into(), Type::Integer)],
+            contract,
+        };
+
+        assert_eq!(
+            print(&Decl::ValDecl(hash)),
+            "val hash (x : int)(lo : int) : int\n  requires { x >= lo }\n  \
+             ensures { result >= lo }\n\n"
+        );
+    }
+
+    #[test]
+    fn tuple_pattern_arities() {
+        let x = || Pattern::VarP("x".into());
+        assert_eq!(print(&Pattern::TupleP(vec![])), "()");
+        assert_eq!(print(&Pattern::TupleP(vec![x()])), "x");
+        assert_eq!(print(&Pattern::TupleP(vec![x(), Pattern::Wildcard])), "(x, _)");
+        assert_eq!(print(&Pattern::TupleP(vec![Pattern::TupleP(vec![x()])])), "x");
+
+        let unit = Exp::Match(
+            box Exp::Var("u".into()),
+            vec![(Pattern::TupleP(vec![]), None, Exp::Const(Constant::const_true()))],
+        );
+        assert_eq!(print(&unit), "match (u) with\n  | () -> true\n  end\n");
+
+        let binders = Pattern::TupleP(vec![x()]).binders();
+        assert_eq!(binders, vec![LocalIdent::from("x")].into_iter().collect());
+    }
+
+    #[test]
+    fn logic_and_program_connectives() {
+        let var = |v: &str| Exp::Var(v.into());
+        let bin = |op, l, r| Exp::BinaryOp(op, box l, box r);
+
+        let program = bin(BinOp::Or, bin(BinOp::And, var("a"), var("b")), var("c"));
+        assert_eq!(print(&program), "a && b || c");
+
+        let logic = bin(BinOp::LOr, bin(BinOp::LAnd, var("a"), var("b")), var("c"));
+        assert_eq!(print(&logic), "a /\\ b \\/ c");
+
+        let chain = bin(BinOp::LAnd, var("a"), bin(BinOp::LAnd, var("b"), var("c")));
+        assert_eq!(print(&chain), "a /\\ b /\\ c");
+        let mixed = bin(BinOp::LAnd, var("a"), bin(BinOp::And, var("b"), var("c")));
+        assert_eq!(print(&mixed), "a /\\ (b && c)");
+    }
+
+    #[test]
+    fn grouped_binders() {
+        let binders = vec![
+            ("x".into(), Type::Integer),
+            ("y".into(), Type::Integer),
+            ("z".into(), Type::Integer),
+            ("b".into(), Type::Bool),
+        ];
+        let forall = Exp::Forall(binders.clone(), box Exp::mk_true());
+        assert_eq!(print(&forall), "forall x y z : int, b : bool . true");
+
+        let split = vec![
+            ("x".into(), Type::Integer),
+            ("b".into(), Type::Bool),
+            ("y".into(), Type::Integer),
+        ];
+        let exists = Exp::Exists(split, box Exp::mk_true());
+        assert_eq!(print(&exists), "exists x : int, b : bool, y : int . true");
+    }
+
+    #[test]
+    fn attributes() {
+        let var = |v: &str| Exp::Var(v.into());
+        let attr = |e| Exp::Attr("expl:in bounds".into(), box e);
+
+        let conj = attr(Exp::conj(var("a"), var("b")));
+        assert_eq!(print(&conj), "[@expl:in bounds] a && b");
+        assert_eq!(
+            print(&Exp::BinaryOp(BinOp::Or, box conj.clone(), box var("c"))),
+            "([@expl:in bounds] a && b) || c"
+        );
+        assert_eq!(print(&Exp::conj(var("c"), conj)), "c && ([@expl:in bounds] a && b)");
+
+        let mut renamed = attr(var("a"));
+        renamed.rename(&"a".into(), "z".into());
+        assert_eq!(print(&renamed), "[@expl:in bounds] z");
+        assert_eq!(renamed.fvs(), vec![LocalIdent::from("z")].into_iter().collect());
+    }
+
+    #[test]
+    fn mangler_prefixes_declared_names() {
+        let x = || Exp::Var("x".into());
+        let list = TyDecl {
+            ty_name: "list".into(),
+            ty_params: vec![],
+            ty_constructors: vec![("Nil".into(), vec![])],
+            ty_fields: vec![],
+            ty_alias: None,
+        };
+        let is_nil = Logic {
+            name: "is_nil".into(),
+            retty: Type::Bool,
+            args: vec![("x".into(), Type::TConstructor("list".into()))],
+            body: Exp::Match(
+                box x(),
+                vec![
+                    (Pattern::ConsP("Nil".into(), vec![]), None, Exp::mk_true()),
+                    (Pattern::Wildcard, None, Exp::Call(box Exp::QVar(drop_bool()), vec![x()])),
+                ],
+            ),
+            contract: Contract::new(),
+        };
+        let decls = vec![Decl::TyDecl(list), Decl::LogicDecl(is_nil)];
+
+        let mangler = NameMangler::new("cr", &decls);
+        let fe = FormatEnv { mangler: Some(&mangler), ..FormatEnv::default() };
+        let printed: String = decls.iter().map(|d| format!("{}", fe.to(d))).collect();
+
+        assert!(printed.contains("type cr_list  =\n    | Cr_Nil\n"));
+        assert!(printed.contains("let predicate cr_is_nil (x : cr_list)\n"));
+        assert!(printed.contains("| Cr_Nil -> true\n"));
+        assert!(printed.contains("| _ -> drop_bool x\n"));
+
+        let plain: String = decls.iter().map(|d| print(d)).collect();
+        assert!(plain.contains("let predicate is_nil (x : list)\n"));
+    }
+
+    #[test]
+    fn invariants_printed_at_loop_header() {
+        let var = |v: &str| Exp::Var(v.into());
+        let mut blocks = BTreeMap::new();
+        let loop_back = Terminator::if_(var("b"), Terminator::Goto(BlockId(1)), Terminator::Return);
+        let entry = Block { statements: vec![], terminator: Terminator::Goto(BlockId(1)) };
+        blocks.insert(BlockId(0), entry);
+        blocks.insert(
+            BlockId(1),
+            Block {
+                statements: vec![Statement::Assign { lhs: "x".into(), rhs: var("y") }],
+                terminator: loop_back,
+            },
+        );
+        let mut invariants = BTreeMap::new();
+        invariants.insert(BlockId(1), vec![("bound".to_owned(), var("x"))]);
+        let fun = Function {
+            name: "f".into(),
+            retty: Type::Tuple(vec![]),
+            args: vec![],
+            vars: vec![],
+            blocks,
+            invariants,
+            contract: Contract::new(),
+        };
+
+        let printed = print(&fun);
+        let body = &printed[printed.find("BB0 {").unwrap()..];
+        assert_eq!(
+            body,
+            "BB0 {\n  goto BB1\n}\nBB1 {\n  invariant { [@expl:bound #0] x };\n  x <- y;\n  \
+             switch (b)\n    | True -> goto BB1\n    | False -> return _0\n    end\n}\n"
+        );
+    }
+
+    #[test]
+    fn let_chains_stay_flat() {
+        let var = |v: &str| Exp::Var(v.into());
+        let chain = ["a", "b", "c", "d"]
+            .iter()
+            .rev()
+            .fold(var("d"), |body, v| Exp::let_(Pattern::VarP((*v).into()), var("x"), body));
+        let fe = FormatEnv { indent: 4, ..FormatEnv::default() };
+        assert_eq!(
+            format!("{}", fe.to(&chain)),
+            "let a = x in\n    let b = x in\n    let c = x in\n    let d = x in\n    d"
+        );
+
+        let single = Exp::let_(Pattern::VarP("a".into()), var("x"), var("a"));
+        assert_eq!(print(&single), "let a = x in a");
+    }
+
+    #[test]
+    fn not_precedence() {
+        let var = |v: &str| Exp::Var(v.into());
+        let bin = |op, l, r| Exp::BinaryOp(op, box l, box r);
+        let not = |e| Exp::UnaryOp(UnOp::Not, box e);
+
+        assert_eq!(print(&not(bin(BinOp::Eq, var("a"), var("b")))), "not (a = b)");
+        assert_eq!(print(&not(bin(BinOp::And, var("p"), var("q")))), "not (p && q)");
+        assert_eq!(print(&bin(BinOp::And, not(var("p")), var("q"))), "not p && q");
+        assert_eq!(print(&bin(BinOp::Or, var("p"), not(var("q")))), "p || not q");
+        assert_eq!(print(&bin(BinOp::Eq, not(var("p")), var("q"))), "(not p) = q");
+        assert_eq!(print(&not(not(var("p")))), "not (not p)");
+        let call = Exp::Call(box var("f"), vec![var("x")]);
+        assert_eq!(print(&not(call.clone())), "not f x");
+        assert_eq!(print(&Exp::Call(box var("g"), vec![not(call)])), "g (not f x)");
+    }
+
+    #[test]
+    fn structured_functions() {
+        let block = |statements, terminator| Block { statements, terminator };
+        let ret = Statement::Assign { lhs: LocalIdent::Anon(0, None), rhs: Exp::Var("x".into()) };
+        let fun = Function {
+            name: "id".into(),
+            retty: Type::Integer,
+            args: vec![("x".into(), Type::Integer)],
+            vars: Vec::new(),
+            blocks: vec![(BlockId(0), block(vec![ret], Terminator::Return))].into_iter().collect(),
+            invariants: BTreeMap::new(),
+            contract: Contract::new(),
+        };
+        assert!(print(&fun).starts_with("let rec cfg id (o_x : int) : int\n"));
+        let structured = Decl::LetFun(fun.into_structured().unwrap());
+        assert_eq!(print(&structured), "let rec id (x : int) : int\n  =\n  let _0 = x in _0\n\n");
+    }
+
+    #[test]
+    fn floats_print_exactly() {
+        // Reads a printed literal back, the mantissa has at most 53 bits so this is exact
+        fn value(lit: &str) -> f64 {
+            let (sign, lit) = match lit.strip_prefix('-') {
+                Some(lit) => (-1.0, lit),
+                None => (1.0, lit),
+            };
+            let mut parts = lit.strip_prefix("0x").unwrap().split(|c| c == '.' || c == 'p');
+            let (lead, frac, exp) = (parts.next().unwrap(), parts.next().unwrap(), parts.next());
+            let mantissa = u64::from_str_radix(&format!("{}{}", lead, frac), 16).unwrap();
+            let exp: i32 = exp.unwrap().parse().unwrap();
+            sign * mantissa as f64 * 2f64.powi(-4 * frac.len() as i32) * 2f64.powi(exp)
+        }
+
+        for x in &[0.1, 3.0, -2.5, 0.0, f64::MIN_POSITIVE, 5e-324, f64::MAX, 0.1f32 as f64] {
+            assert_eq!(value(&hex_float(*x)).to_bits(), x.to_bits(), "{}", hex_float(*x));
         }
+
+        let double = Some(Type::TConstructor("double".into()));
+        assert_eq!(print(&Constant::Float(0.1, double)), "(0x1.999999999999ap-4 : double)");
+        assert_eq!(print(&Constant::Float(-2.5, None)), "(-0x1.4p1)");
+        assert_eq!(print(&Constant::Float(1.0, None)), "0x1.0p0");
+    }
+
+    #[test]
+    fn existential_lemma() {
+        let var = |v: &str| Exp::Var(v.into());
+        let witness = Exp::BinaryOp(BinOp::Eq, box var("y"), box var("x"));
+        let body = Exp::forall(
+            vec![("x".into(), Type::Integer)],
+            Exp::exists(vec![("y".into(), Type::Integer)], witness),
+        );
+        let lemma = Decl::Lemma { name: "has_witness".into(), body };
+        assert_eq!(print(&lemma), "lemma has_witness : forall x : int . exists y : int . y = x\n");
+    }
+
+    #[test]
+    fn implication_is_right_associative() {
+        let var = |v: &str| Exp::Var(v.into());
+        let imp = |l, r| Exp::Impl(box l, box r);
+
+        assert_eq!(print(&imp(imp(var("a"), var("b")), var("c"))), "(a -> b) -> c");
+        assert_eq!(print(&imp(var("a"), imp(var("b"), var("c")))), "a -> b -> c");
+        let disj = Exp::BinaryOp(BinOp::Or, box var("a"), box var("b"));
+        assert_eq!(print(&imp(disj, var("c"))), "a || b -> c");
+    }
+
+    #[test]
+    fn string_constants_are_escaped() {
+        let str = |s: &str| print(&Constant::Str(s.into()));
+        assert_eq!(str("plain"), r#""plain""#);
+        assert_eq!(str(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(str(r"C:\dir"), r#""C:\\dir""#);
+        assert_eq!(str("two\nlines\t"), r#""two\nlines\t""#);
+        assert_eq!(str("é"), r#""\xc3\xa9""#);
+        assert_eq!(print(&Constant::const_true()), "true");
+    }
+
+    #[test]
+    fn applications_in_arguments_are_parenthesized() {
+        let var = |v: &str| Exp::Var(v.into());
+        let call = |f: &str, args| Exp::Call(box var(f), args);
+
+        assert_eq!(print(&call("f", vec![call("g", vec![var("x")]), var("y")])), "f (g x) y");
+        let nested = call("f", vec![call("g", vec![call("h", vec![var("x")])])]);
+        assert_eq!(print(&nested), "f (g (h x))");
+        let sum = Exp::BinaryOp(BinOp::Add, box var("x"), box var("y"));
+        assert_eq!(print(&call("f", vec![sum, Exp::Tuple(vec![])])), "f (x + y) ()");
     }
 }